@@ -0,0 +1,550 @@
+use crate::parse::{AstNode, Node};
+use crate::span::Span;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub msg: String,
+    pub span: Span,
+}
+
+impl Warning {
+    pub fn new(msg: String, span: Span) -> Warning {
+        Warning { msg, span }
+    }
+}
+
+// Checks the matches of a program, warning about the values none of their
+// branches cover and about branches earlier ones already cover
+pub fn check(node: &Node) -> Vec<Warning> {
+    let mut checker = Checker {
+        types: BTreeMap::new(),
+    };
+    walk(node, &mut |node| checker.declare(node));
+
+    let mut out = Vec::new();
+    checker.visit(node, &BTreeMap::new(), &mut out);
+    out
+}
+
+// Calls `visit` on `node` and on every node below it
+fn walk(node: &Node, visit: &mut impl FnMut(&Node)) {
+    visit(node);
+    children(node)
+        .into_iter()
+        .for_each(|node| walk(node, visit));
+}
+
+fn children(node: &Node) -> Vec<&Node> {
+    match &node.ast {
+        AstNode::Program(lst)
+        | AstNode::Each(lst)
+        | AstNode::Funcall(_, lst)
+        | AstNode::Fun(_, _, lst)
+        | AstNode::Lambda(_, lst) => lst.iter().collect(),
+        AstNode::Expr(expr) | AstNode::Paren(expr) | AstNode::Not(expr) | AstNode::Let(_, expr) => {
+            vec![expr]
+        }
+        AstNode::Map(map) => map.values().collect(),
+        AstNode::Fork(arms) => arms.values().flatten().collect(),
+        AstNode::Pipe(head, lst) | AstNode::Call(head, lst) | AstNode::Match(head, lst) => {
+            std::iter::once(&**head).chain(lst).collect()
+        }
+        AstNode::BinAdd(lhs, _, rhs)
+        | AstNode::BinMul(lhs, _, rhs)
+        | AstNode::BinPow(lhs, _, rhs)
+        | AstNode::BinCmp(lhs, _, rhs)
+        | AstNode::BinAnd(lhs, _, rhs)
+        | AstNode::BinOr(lhs, _, rhs)
+        | AstNode::Branch(lhs, rhs)
+        | AstNode::Guard(lhs, rhs) => vec![lhs, rhs],
+        _ => vec![],
+    }
+}
+
+// Whether `name` is bound again anywhere in `node`
+fn rebinds(node: &Node, name: &str) -> bool {
+    let mut found = false;
+    walk(node, &mut |node| {
+        found |= match &node.ast {
+            AstNode::Bind(idt) | AstNode::Let(idt, _) => idt == name,
+            AstNode::Fun(idt, args, _) => {
+                idt == name
+                    || args
+                        .iter()
+                        .any(|arg| arg.ast == AstNode::Idt(name.to_string()))
+            }
+            AstNode::Lambda(args, _) => args
+                .iter()
+                .any(|arg| arg.ast == AstNode::Idt(name.to_string())),
+            _ => false,
+        }
+    });
+    found
+}
+
+// What a pattern tells apart, values of the same constructor only differ by
+// the values of their fields
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Ctor {
+    Bool(bool),
+    None,
+    // a number or string literal, with its text
+    Lit(String),
+    // a list of that many items
+    List(usize),
+    // a map holding at least these keys
+    Map(Vec<String>),
+    // whether a map holds a key, the present one has the value as field
+    Present,
+    Absent,
+    // a variant of a declared type, with its number of fields
+    Tag(String, usize),
+}
+
+#[derive(Debug, Clone)]
+enum Pat {
+    Wild,
+    // an expression compared with the value, nothing tells what it matches
+    Opaque,
+    Ctor(Ctor, Vec<Pat>),
+}
+
+struct Checker {
+    // the variants of the type each variant tag belongs to, with their
+    // number of fields
+    types: BTreeMap<String, Vec<(String, usize)>>,
+}
+
+impl Checker {
+    fn declare(&mut self, node: &Node) {
+        let variants = match &node.ast {
+            AstNode::Type(_, variants) => variants,
+            _ => return,
+        };
+
+        let tags: Vec<_> = variants
+            .iter()
+            .filter_map(|variant| match &variant.ast {
+                AstNode::Variant(tag, fields) => Some((tag.clone(), fields.len())),
+                _ => None,
+            })
+            .collect();
+
+        for (tag, _) in &tags {
+            self.types.insert(tag.clone(), tags.clone());
+        }
+    }
+
+    fn fields(&self, tag: &str) -> Option<usize> {
+        self.types
+            .get(tag)?
+            .iter()
+            .find(|(other, _)| other == tag)
+            .map(|(_, len)| *len)
+    }
+
+    // Checks the matches below `node`. A match on a name narrows what the name
+    // may hold in each branch: the patterns of the branches before it, and
+    // the other variants of the type of its pattern, are known not to match.
+    // `known` holds those patterns for the names of the enclosing branches
+    fn visit(&self, node: &Node, known: &BTreeMap<String, Vec<Pat>>, out: &mut Vec<Warning>) {
+        let (expr, cases) = match &node.ast {
+            AstNode::Match(expr, cases) => (expr, cases),
+            _ => {
+                for child in children(node) {
+                    self.visit(child, known, out);
+                }
+                return;
+            }
+        };
+
+        let subject = match &expr.unwrap_expr().ast {
+            AstNode::Idt(idt) => Some(idt),
+            _ => None,
+        };
+        let mut ruled_out = subject
+            .and_then(|subject| known.get(subject))
+            .cloned()
+            .unwrap_or_default();
+
+        self.check_match(node, cases.iter(), &ruled_out, out);
+        self.visit(expr, known, out);
+
+        for case in cases {
+            let pat = match &case.ast {
+                AstNode::Branch(pat, _) => pat,
+                _ => continue,
+            };
+            // the warnings about the pattern are given by `check_match`
+            let lowered = self.lower(pat, &mut Vec::new());
+
+            let mut known = known.clone();
+            if let Some(subject) = subject {
+                if rebinds(case, subject) {
+                    known.remove(subject);
+                } else {
+                    let mut pats = ruled_out.clone();
+                    pats.extend(self.others(&lowered));
+                    known.insert(subject.clone(), pats);
+                }
+            }
+            self.visit(case, &known, out);
+
+            if !matches!(pat.ast, AstNode::Guard(_, _)) {
+                ruled_out.push(lowered);
+            }
+        }
+    }
+
+    // Patterns for the values of the type of those `pat` matches that it does
+    // not match, for booleans and variants
+    fn others(&self, pat: &Pat) -> Vec<Pat> {
+        match pat {
+            Pat::Ctor(Ctor::Bool(b), _) => vec![Pat::Ctor(Ctor::Bool(!b), vec![])],
+            Pat::Ctor(Ctor::Tag(tag, _), _) => self
+                .types
+                .get(tag)
+                .into_iter()
+                .flatten()
+                .filter(|(other, _)| other != tag)
+                .map(|(other, len)| {
+                    Pat::Ctor(Ctor::Tag(other.clone(), *len), vec![Pat::Wild; *len])
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    fn lower(&self, node: &Node, out: &mut Vec<Warning>) -> Pat {
+        match &node.ast {
+            AstNode::Default | AstNode::Bind(_) => Pat::Wild,
+            AstNode::Guard(pat, _) => self.lower(pat, out),
+            AstNode::Bool(b) => Pat::Ctor(Ctor::Bool(*b), vec![]),
+            AstNode::None => Pat::Ctor(Ctor::None, vec![]),
+            AstNode::Num(lit) | AstNode::Str(lit) => Pat::Ctor(Ctor::Lit(lit.clone()), vec![]),
+            AstNode::Map(map) if map.keys().all(|key| matches!(key.ast, AstNode::Num(_))) => {
+                // list keys are numbers as strings, which would sort 10 before 2
+                let mut items: Vec<_> = map.iter().collect();
+                items.sort_by_key(|(key, _)| match &key.ast {
+                    AstNode::Num(num) => num.parse::<usize>().unwrap_or(0),
+                    _ => 0,
+                });
+                let pats = items
+                    .into_iter()
+                    .map(|(_, pat)| self.lower(pat, out))
+                    .collect();
+                Pat::Ctor(Ctor::List(map.len()), pats)
+            }
+            AstNode::Map(map) => {
+                let keys = map
+                    .keys()
+                    .map(|key| match &key.ast {
+                        AstNode::Idt(idt) => idt.clone(),
+                        _ => String::new(),
+                    })
+                    .collect();
+                let pats = map.values().map(|pat| self.lower(pat, out)).collect();
+                Pat::Ctor(Ctor::Map(keys), pats)
+            }
+            AstNode::Variant(tag, pats) => {
+                let pats: Vec<_> = pats.iter().map(|pat| self.lower(pat, out)).collect();
+
+                match self.fields(tag) {
+                    // a tag alone matches the variant whatever its fields
+                    Some(len) if pats.is_empty() => {
+                        Pat::Ctor(Ctor::Tag(tag.clone(), len), vec![Pat::Wild; len])
+                    }
+                    Some(len) if pats.len() != len => {
+                        out.push(Warning::new(
+                            format!(
+                                "Warning: variant {} has {} field{}, the pattern has {}",
+                                tag,
+                                len,
+                                if len == 1 { "" } else { "s" },
+                                pats.len()
+                            ),
+                            node.span,
+                        ));
+                        Pat::Opaque
+                    }
+                    _ => Pat::Ctor(Ctor::Tag(tag.clone(), pats.len()), pats),
+                }
+            }
+            _ => Pat::Opaque,
+        }
+    }
+
+    // Checks the branches of a match, the values `ruled_out` matches being
+    // known not to be matched
+    fn check_match<'a>(
+        &self,
+        node: &Node,
+        cases: impl Iterator<Item = &'a Node>,
+        ruled_out: &[Pat],
+        out: &mut Vec<Warning>,
+    ) {
+        let mut rows: Vec<Vec<Pat>> = Vec::new();
+        let known: Vec<_> = ruled_out.iter().map(|pat| vec![pat.clone()]).collect();
+
+        for case in cases {
+            let pat = match &case.ast {
+                AstNode::Branch(pat, _) => pat,
+                _ => continue,
+            };
+
+            // an expression may equal any value, it is only unreachable when
+            // earlier branches cover everything
+            let lowered = self.lower(pat, out);
+            let query = match lowered {
+                Pat::Opaque => Pat::Wild,
+                _ => lowered.clone(),
+            };
+
+            let query = [query];
+            let all: Vec<_> = known.iter().chain(&rows).cloned().collect();
+            if !self.useful(&rows, &query) {
+                out.push(Warning::new(
+                    "Warning: unreachable branch, earlier branches cover every value it matches"
+                        .to_string(),
+                    pat.span,
+                ));
+            } else if !self.useful(&all, &query) {
+                out.push(Warning::new(
+                    "Warning: unreachable branch, the enclosing branches rule out every value it matches"
+                        .to_string(),
+                    pat.span,
+                ));
+            }
+
+            // a branch with a guard may not be taken, so it covers nothing
+            if !matches!(pat.ast, AstNode::Guard(_, _)) {
+                rows.push(vec![lowered]);
+            }
+        }
+
+        rows.extend(known);
+        if let Some(pats) = self.uncovered(&rows, 1) {
+            let msg = match render(&pats[0]).as_str() {
+                "_" => "Warning: match is not exhaustive, add a `_` branch".to_string(),
+                pat => format!("Warning: match is not exhaustive, `{}` is not covered", pat),
+            };
+            out.push(Warning::new(msg, node.span));
+        }
+    }
+
+    // Every constructor of the values the heads are of, when there are few of
+    // them. Values are not typed, a column only matched against booleans is
+    // taken to hold booleans and one matched against variants of a type to
+    // hold values of that type
+    fn domain(&self, heads: &[Ctor]) -> Option<Vec<Ctor>> {
+        let tags = match heads.first()? {
+            Ctor::Tag(tag, _) => self.types.get(tag),
+            _ => None,
+        };
+
+        if heads.iter().all(|ctor| matches!(ctor, Ctor::Bool(_))) {
+            Some(vec![Ctor::Bool(true), Ctor::Bool(false)])
+        } else if heads
+            .iter()
+            .all(|ctor| matches!(ctor, Ctor::Present | Ctor::Absent))
+        {
+            Some(vec![Ctor::Present, Ctor::Absent])
+        } else {
+            tags.filter(|tags| {
+                heads.iter().all(
+                    |ctor| matches!(ctor, Ctor::Tag(tag, _) if tags.iter().any(|(t, _)| t == tag)),
+                )
+            })
+            .map(|tags| {
+                tags.iter()
+                    .map(|(tag, len)| Ctor::Tag(tag.clone(), *len))
+                    .collect()
+            })
+        }
+    }
+
+    // Whether some values matched by `pats` are matched by none of the rows
+    fn useful(&self, rows: &[Vec<Pat>], pats: &[Pat]) -> bool {
+        let head = match pats.first() {
+            Some(head) => head,
+            None => return rows.is_empty(),
+        };
+
+        match head {
+            Pat::Ctor(ctor, _) => {
+                // a map pattern is split on every key the column names
+                let row = pats.to_vec();
+                let ctor = heads(rows.iter().chain([&row]))
+                    .into_iter()
+                    .find(|head| same(head, ctor))
+                    .unwrap_or_else(|| ctor.clone());
+
+                match specialize_row(pats, &ctor) {
+                    Some(pats) => self.useful(&specialize(rows, &ctor), &pats),
+                    None => false,
+                }
+            }
+            _ => {
+                let heads = heads(rows.iter());
+
+                match self.domain(&heads) {
+                    Some(dom) if dom.iter().all(|ctor| heads.contains(ctor)) => {
+                        dom.iter().any(|ctor| match specialize_row(pats, ctor) {
+                            Some(pats) => self.useful(&specialize(rows, ctor), &pats),
+                            None => false,
+                        })
+                    }
+                    _ => self.useful(&default(rows), &pats[1..]),
+                }
+            }
+        }
+    }
+
+    // Patterns for `len` columns of values that none of the rows match, if any
+    fn uncovered(&self, rows: &[Vec<Pat>], len: usize) -> Option<Vec<Pat>> {
+        if len == 0 {
+            return if rows.is_empty() { Some(vec![]) } else { None };
+        }
+
+        let heads = heads(rows.iter());
+
+        match self.domain(&heads) {
+            Some(dom) if dom.iter().all(|ctor| heads.contains(ctor)) => {
+                dom.into_iter().find_map(|ctor| {
+                    let arity = arity(&ctor);
+                    let mut pats = self.uncovered(&specialize(rows, &ctor), arity + len - 1)?;
+                    let rest = pats.split_off(arity);
+
+                    let mut out = vec![Pat::Ctor(ctor, pats)];
+                    out.extend(rest);
+                    Some(out)
+                })
+            }
+            dom => {
+                let mut pats = self.uncovered(&default(rows), len - 1)?;
+
+                let head = dom
+                    .and_then(|dom| dom.into_iter().find(|ctor| !heads.contains(ctor)))
+                    .map(|ctor| Pat::Ctor(ctor.clone(), vec![Pat::Wild; arity(&ctor)]))
+                    .unwrap_or(Pat::Wild);
+
+                pats.insert(0, head);
+                Some(pats)
+            }
+        }
+    }
+}
+
+fn same(a: &Ctor, b: &Ctor) -> bool {
+    matches!((a, b), (Ctor::Map(_), Ctor::Map(_))) || a == b
+}
+
+fn arity(ctor: &Ctor) -> usize {
+    match ctor {
+        Ctor::List(len) | Ctor::Tag(_, len) => *len,
+        Ctor::Map(keys) => keys.len(),
+        Ctor::Present => 1,
+        _ => 0,
+    }
+}
+
+// The constructors heading the rows, map patterns with different keys are
+// merged into one holding all of them
+fn heads<'a>(rows: impl Iterator<Item = &'a Vec<Pat>>) -> Vec<Ctor> {
+    let mut out: Vec<Ctor> = Vec::new();
+
+    for row in rows {
+        let ctor = match row.first() {
+            Some(Pat::Ctor(ctor, _)) => ctor,
+            _ => continue,
+        };
+
+        match (out.iter_mut().find(|head| same(head, ctor)), ctor) {
+            (Some(Ctor::Map(keys)), Ctor::Map(more)) => {
+                for key in more {
+                    if !keys.contains(key) {
+                        keys.push(key.clone());
+                    }
+                }
+            }
+            (Some(_), _) => {}
+            (None, _) => out.push(ctor.clone()),
+        }
+    }
+
+    out
+}
+
+// The row for the fields of `ctor` followed by the rest of `row`, if its head
+// can match values of `ctor`
+fn specialize_row(row: &[Pat], ctor: &Ctor) -> Option<Vec<Pat>> {
+    let mut out = match &row[0] {
+        Pat::Wild => vec![Pat::Wild; arity(ctor)],
+        Pat::Ctor(head, pats) if same(head, ctor) => match (head, ctor) {
+            // a key the pattern does not name may be there or not
+            (Ctor::Map(keys), Ctor::Map(all)) => all
+                .iter()
+                .map(|key| match keys.iter().position(|k| k == key) {
+                    Some(i) => Pat::Ctor(Ctor::Present, vec![pats[i].clone()]),
+                    None => Pat::Wild,
+                })
+                .collect(),
+            _ => pats.clone(),
+        },
+        _ => return None,
+    };
+
+    out.extend(row[1..].iter().cloned());
+    Some(out)
+}
+
+fn specialize(rows: &[Vec<Pat>], ctor: &Ctor) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter_map(|row| specialize_row(row, ctor))
+        .collect()
+}
+
+// The rows matching any value in their first column
+fn default(rows: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter(|row| matches!(row[0], Pat::Wild))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+fn render(pat: &Pat) -> String {
+    match pat {
+        Pat::Wild | Pat::Opaque => "_".to_string(),
+        Pat::Ctor(ctor, pats) => match ctor {
+            Ctor::Bool(b) => b.to_string(),
+            Ctor::None => "none".to_string(),
+            Ctor::Lit(lit) => lit.clone(),
+            Ctor::List(_) => {
+                let items: Vec<_> = pats.iter().map(render).collect();
+                format!("{{ {} }}", items.join(", "))
+            }
+            Ctor::Map(keys) => {
+                let items: Vec<_> = keys
+                    .iter()
+                    .zip(pats)
+                    .filter_map(|(key, pat)| match pat {
+                        Pat::Ctor(Ctor::Absent, _) => None,
+                        Pat::Ctor(Ctor::Present, pats) => {
+                            Some(format!("{}: {}", key, render(&pats[0])))
+                        }
+                        pat => Some(format!("{}: {}", key, render(pat))),
+                    })
+                    .collect();
+                format!("{{ {} }}", items.join(", "))
+            }
+            Ctor::Present => render(&pats[0]),
+            Ctor::Absent => "_".to_string(),
+            Ctor::Tag(tag, _) if pats.is_empty() => tag.clone(),
+            Ctor::Tag(tag, _) => {
+                let fields: Vec<_> = pats.iter().map(render).collect();
+                format!("{}({})", tag, fields.join(", "))
+            }
+        },
+    }
+}
@@ -0,0 +1,538 @@
+use crate::parse::{AstNode, Node};
+use crate::serial::{from_binary, to_binary};
+use crate::span::Span;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, LinkedList};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// A top-level definition of a program along with the hash of its normalized
+// AST, which is what the codebase stores
+#[derive(Debug, Clone)]
+pub struct Def {
+    pub name: String,
+    pub hash: String,
+    pub node: Node,
+}
+
+// Definitions stored by hash in a directory, with an index from names to
+// hashes. Definitions refer to each other by hash, so renaming one only
+// touches the index
+//
+//   <dir>/defs/<hash>   a normalized definition, in the binary AST format
+//   <dir>/names         one `<hash> <name>` line per name
+#[derive(Debug, Clone)]
+pub struct Codebase {
+    dir: PathBuf,
+    names: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    // Replaces names by de Bruijn indices and hashes
+    Normalize,
+    // Gives the binders of a normalized definition fresh names again
+    Restore,
+}
+
+struct Renamer<'a> {
+    mode: Mode,
+    defs: &'a BTreeMap<String, String>,
+}
+
+impl Renamer<'_> {
+    // Name of a new binder, pushing it to `scope`
+    fn bind(&self, name: &str, scope: &mut Vec<String>) -> String {
+        match self.mode {
+            Mode::Normalize => {
+                scope.push(name.to_string());
+                String::new()
+            }
+            Mode::Restore => {
+                let name = format!("${}", scope.len());
+                scope.push(name.clone());
+                name
+            }
+        }
+    }
+
+    fn reference(&self, name: &str, scope: &[String]) -> String {
+        match self.mode {
+            Mode::Normalize => {
+                if let Some(i) = scope.iter().rev().position(|bound| bound == name) {
+                    format!("^{}", i)
+                } else if let Some(hash) = self.defs.get(name) {
+                    format!("#{}", hash)
+                } else {
+                    name.to_string()
+                }
+            }
+            Mode::Restore => match name.strip_prefix('^').and_then(|i| i.parse::<usize>().ok()) {
+                Some(i) if i < scope.len() => scope[scope.len() - 1 - i].clone(),
+                _ => name.to_string(),
+            },
+        }
+    }
+
+    fn node(&self, ast: AstNode, span: Span) -> Node {
+        match self.mode {
+            Mode::Normalize => Node::new(ast, Span::default()),
+            Mode::Restore => Node::new(ast, span),
+        }
+    }
+
+    fn boxed(&self, node: &Node, scope: &mut Vec<String>) -> Box<Node> {
+        Box::new(self.walk(node, scope))
+    }
+
+    fn list(&self, lst: &LinkedList<Node>, scope: &mut Vec<String>) -> LinkedList<Node> {
+        lst.iter().map(|node| self.walk(node, scope)).collect()
+    }
+
+    // The statements of a function body or of a program, a `let` or `fun`
+    // binds its name for the statements after it
+    fn block(&self, lst: &LinkedList<Node>, scope: &mut Vec<String>) -> LinkedList<Node> {
+        let depth = scope.len();
+        let mut out = LinkedList::new();
+
+        for node in lst {
+            if self.mode == Mode::Normalize && matches!(node.ast, AstNode::Comment(_, _)) {
+                continue;
+            }
+
+            let (stmt, name) = match &node.ast {
+                AstNode::Expr(expr) => match &expr.ast {
+                    AstNode::Let(name, _) | AstNode::Fun(name, _, _) => {
+                        (self.walk(node, scope), Some(name.clone()))
+                    }
+                    _ => (self.walk(node, scope), None),
+                },
+                _ => (self.walk(node, scope), None),
+            };
+
+            if let Some(name) = name {
+                self.bind(&name, scope);
+            }
+            out.push_back(stmt);
+        }
+
+        scope.truncate(depth);
+        out
+    }
+
+    #[allow(clippy::mutable_key_type)]
+    fn walk(&self, node: &Node, scope: &mut Vec<String>) -> Node {
+        let depth = scope.len();
+
+        let ast = match &node.ast {
+            AstNode::Program(lst) => AstNode::Program(self.block(lst, scope)),
+            AstNode::Expr(expr) => AstNode::Expr(self.boxed(expr, scope)),
+            AstNode::Paren(expr) => AstNode::Paren(self.boxed(expr, scope)),
+            AstNode::Not(expr) => AstNode::Not(self.boxed(expr, scope)),
+            AstNode::Idt(idt) => AstNode::Idt(self.reference(idt, scope)),
+            // identifier keys of maps and forks are labels rather than names
+            AstNode::Map(map) => AstNode::Map(
+                map.iter()
+                    .map(|(key, val)| (self.walk_key(key), self.walk(val, scope)))
+                    .collect(),
+            ),
+            AstNode::Fork(arms) => AstNode::Fork(
+                arms.iter()
+                    .map(|(key, stages)| (self.walk_key(key), self.list(stages, scope)))
+                    .collect(),
+            ),
+            AstNode::Let(_, expr) => {
+                // the name is bound in the enclosing block, see `block`
+                let expr = self.boxed(expr, scope);
+                let name = self.bind_name(scope);
+                AstNode::Let(name, expr)
+            }
+            AstNode::Fun(name, args, body) => {
+                let fun = self.bind(name, scope);
+                let args = args
+                    .iter()
+                    .map(|arg| match &arg.ast {
+                        AstNode::Idt(idt) => {
+                            self.node(AstNode::Idt(self.bind(idt, scope)), arg.span)
+                        }
+                        _ => self.walk(arg, scope),
+                    })
+                    .collect();
+                let body = self.block(body, scope);
+                AstNode::Fun(fun, args, body)
+            }
+            AstNode::Lambda(args, body) => {
+                let args = args
+                    .iter()
+                    .map(|arg| match &arg.ast {
+                        AstNode::Idt(idt) => {
+                            self.node(AstNode::Idt(self.bind(idt, scope)), arg.span)
+                        }
+                        _ => self.walk(arg, scope),
+                    })
+                    .collect();
+                let body = self.block(body, scope);
+                AstNode::Lambda(args, body)
+            }
+            AstNode::Call(callee, params) => {
+                AstNode::Call(self.boxed(callee, scope), self.list(params, scope))
+            }
+            AstNode::Funcall(idt, params) => {
+                AstNode::Funcall(self.reference(idt, scope), self.list(params, scope))
+            }
+            AstNode::Pipe(head, stages) => {
+                AstNode::Pipe(self.boxed(head, scope), self.list(stages, scope))
+            }
+            AstNode::Each(stages) => AstNode::Each(self.list(stages, scope)),
+            AstNode::BinAdd(lhs, op, rhs) => AstNode::BinAdd(
+                self.boxed(lhs, scope),
+                self.boxed(op, scope),
+                self.boxed(rhs, scope),
+            ),
+            AstNode::BinMul(lhs, op, rhs) => AstNode::BinMul(
+                self.boxed(lhs, scope),
+                self.boxed(op, scope),
+                self.boxed(rhs, scope),
+            ),
+            AstNode::BinPow(lhs, op, rhs) => AstNode::BinPow(
+                self.boxed(lhs, scope),
+                self.boxed(op, scope),
+                self.boxed(rhs, scope),
+            ),
+            AstNode::BinCmp(lhs, op, rhs) => AstNode::BinCmp(
+                self.boxed(lhs, scope),
+                self.boxed(op, scope),
+                self.boxed(rhs, scope),
+            ),
+            AstNode::BinAnd(lhs, op, rhs) => AstNode::BinAnd(
+                self.boxed(lhs, scope),
+                self.boxed(op, scope),
+                self.boxed(rhs, scope),
+            ),
+            AstNode::BinOr(lhs, op, rhs) => AstNode::BinOr(
+                self.boxed(lhs, scope),
+                self.boxed(op, scope),
+                self.boxed(rhs, scope),
+            ),
+            AstNode::Branch(pat, expr) => {
+                // the names the pattern binds are seen by the branch
+                let pat = Box::new(self.pattern(pat, scope));
+                AstNode::Branch(pat, self.boxed(expr, scope))
+            }
+            AstNode::Match(expr, cases) => {
+                let expr = self.boxed(expr, scope);
+                let cases = cases
+                    .iter()
+                    .filter(|case| {
+                        self.mode == Mode::Restore || !matches!(case.ast, AstNode::Comment(_, _))
+                    })
+                    .map(|case| self.walk(case, scope))
+                    .collect();
+                AstNode::Match(expr, cases)
+            }
+            // variant names are tags rather than names, like map keys
+            AstNode::Type(name, variants) => {
+                AstNode::Type(name.clone(), self.list(variants, scope))
+            }
+            AstNode::Variant(tag, fields) => AstNode::Variant(
+                tag.clone(),
+                fields.iter().map(|f| self.walk_key(f)).collect(),
+            ),
+            ast => ast.clone(),
+        };
+
+        scope.truncate(depth);
+        self.node(ast, node.span)
+    }
+
+    // A pattern of a match branch, binding its names in `scope` for the guard
+    // and the branch
+    #[allow(clippy::mutable_key_type)]
+    fn pattern(&self, pat: &Node, scope: &mut Vec<String>) -> Node {
+        let ast = match &pat.ast {
+            AstNode::Bind(name) => AstNode::Bind(self.bind(name, scope)),
+            AstNode::Map(pats) => AstNode::Map(
+                pats.iter()
+                    .map(|(key, pat)| (self.walk_key(key), self.pattern(pat, scope)))
+                    .collect(),
+            ),
+            AstNode::Guard(pat, guard) => {
+                let pat = Box::new(self.pattern(pat, scope));
+                AstNode::Guard(pat, self.boxed(guard, scope))
+            }
+            AstNode::Variant(tag, pats) => AstNode::Variant(
+                tag.clone(),
+                pats.iter().map(|pat| self.pattern(pat, scope)).collect(),
+            ),
+            _ => return self.walk(pat, scope),
+        };
+
+        self.node(ast, pat.span)
+    }
+
+    // The binder name a `let` gets, the name itself is pushed by `block`
+    fn bind_name(&self, scope: &[String]) -> String {
+        match self.mode {
+            Mode::Normalize => String::new(),
+            Mode::Restore => format!("${}", scope.len()),
+        }
+    }
+
+    fn walk_key(&self, key: &Node) -> Node {
+        self.node(key.ast.clone(), key.span)
+    }
+}
+
+// Normalizes a top-level definition: names bound inside it become de Bruijn
+// indices (`^0` being the innermost binder), names of the definitions in
+// `defs` become `#<hash>` and spans and comments are dropped. The name of the
+// definition is itself a binder, so recursive calls do not depend on it
+pub fn normalize(def: &Node, defs: &BTreeMap<String, String>) -> Node {
+    let renamer = Renamer {
+        mode: Mode::Normalize,
+        defs,
+    };
+
+    renamer.walk(def, &mut Vec::new())
+}
+
+// Undoes `normalize` as far as it can, binders get the names `$0`, `$1`, ...
+// after their depth, so the definition can be evaluated
+pub fn restore(def: &Node) -> Node {
+    let renamer = Renamer {
+        mode: Mode::Restore,
+        defs: &BTreeMap::new(),
+    };
+
+    renamer.walk(def, &mut Vec::new())
+}
+
+// Hash of a normalized definition
+pub fn hash(norm: &Node) -> String {
+    sha256(&to_binary(&wrap(norm)))
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// Wraps a definition into a program, the root the AST formats expect
+fn wrap(norm: &Node) -> Node {
+    Node::new(
+        AstNode::Program(LinkedList::from([norm.clone()])),
+        Span::default(),
+    )
+}
+
+// Hashes the top-level `fun` and `let` definitions of `program` in order.
+// Names are looked up in `defs` and in the definitions before them
+pub fn hash_defs(program: &Node, defs: &BTreeMap<String, String>) -> Vec<Def> {
+    let mut defs = defs.clone();
+    let mut out = Vec::new();
+
+    let lst = match &program.ast {
+        AstNode::Program(lst) => lst,
+        _ => return out,
+    };
+
+    for node in lst {
+        let name = match &node.ast {
+            AstNode::Expr(expr) => match &expr.ast {
+                AstNode::Let(name, _) | AstNode::Fun(name, _, _) => name.clone(),
+                _ => continue,
+            },
+            _ => continue,
+        };
+
+        // a definition does not refer to an older one of the same name
+        defs.remove(&name);
+
+        let norm = normalize(node, &defs);
+        let hash = hash(&norm);
+
+        defs.insert(name.clone(), hash.clone());
+        out.push(Def {
+            name,
+            hash,
+            node: norm,
+        });
+    }
+
+    out
+}
+
+// Names used by `node`, as identifiers or called functions, with where they
+// are first used
+pub fn refs(node: &Node) -> BTreeMap<String, Span> {
+    fn walk(node: &Node, out: &mut BTreeMap<String, Span>) {
+        match &node.ast {
+            AstNode::Idt(idt) => {
+                out.entry(idt.clone()).or_insert(node.span);
+            }
+            AstNode::Funcall(idt, params) => {
+                out.entry(idt.clone()).or_insert(node.span);
+                params.iter().for_each(|node| walk(node, out));
+            }
+            AstNode::Program(lst) | AstNode::Each(lst) => {
+                lst.iter().for_each(|node| walk(node, out))
+            }
+            AstNode::Fun(_, _, body) | AstNode::Lambda(_, body) => {
+                body.iter().for_each(|node| walk(node, out))
+            }
+            AstNode::Expr(expr)
+            | AstNode::Paren(expr)
+            | AstNode::Not(expr)
+            | AstNode::Let(_, expr) => walk(expr, out),
+            AstNode::Map(map) => map.values().for_each(|node| walk(node, out)),
+            AstNode::Fork(arms) => arms.values().flatten().for_each(|node| walk(node, out)),
+            AstNode::Pipe(head, stages)
+            | AstNode::Match(head, stages)
+            | AstNode::Call(head, stages) => {
+                walk(head, out);
+                stages.iter().for_each(|node| walk(node, out));
+            }
+            AstNode::BinAdd(lhs, _, rhs)
+            | AstNode::BinMul(lhs, _, rhs)
+            | AstNode::BinPow(lhs, _, rhs)
+            | AstNode::BinCmp(lhs, _, rhs)
+            | AstNode::BinAnd(lhs, _, rhs)
+            | AstNode::BinOr(lhs, _, rhs)
+            | AstNode::Branch(lhs, rhs)
+            | AstNode::Guard(lhs, rhs) => {
+                walk(lhs, out);
+                walk(rhs, out);
+            }
+            _ => {}
+        }
+    }
+
+    let mut out = BTreeMap::new();
+    walk(node, &mut out);
+    out
+}
+
+impl Codebase {
+    // Opens the codebase in `dir`, creating it when missing
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Codebase> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join("defs"))?;
+
+        let mut names = BTreeMap::new();
+        match fs::read_to_string(dir.join("names")) {
+            Ok(txt) => {
+                for line in txt.lines() {
+                    if let Some((hash, name)) = line.split_once(' ') {
+                        names.insert(name.to_string(), hash.to_string());
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(Codebase { dir, names })
+    }
+
+    pub fn names(&self) -> &BTreeMap<String, String> {
+        &self.names
+    }
+
+    // Hash bound to `name`, which can also be a `#<hash>` reference
+    pub fn hash_of(&self, name: &str) -> Option<String> {
+        match name.strip_prefix('#') {
+            Some(hash) if self.def_path(hash).is_file() => Some(hash.to_string()),
+            Some(_) => None,
+            None => self.names.get(name).cloned(),
+        }
+    }
+
+    fn def_path(&self, hash: &str) -> PathBuf {
+        self.dir.join("defs").join(hash)
+    }
+
+    // Stores the top-level definitions of `program` and points their names
+    // at them
+    pub fn add(&mut self, program: &Node) -> io::Result<Vec<Def>> {
+        let defs = hash_defs(program, &self.names);
+
+        for def in &defs {
+            let path = self.def_path(&def.hash);
+            if !path.exists() {
+                fs::write(path, to_binary(&wrap(&def.node)))?;
+            }
+            self.names.insert(def.name.clone(), def.hash.clone());
+        }
+
+        self.save()?;
+        Ok(defs)
+    }
+
+    // Points `new` at the definition `old` was bound to, returning false when
+    // there is no `old`
+    pub fn rename(&mut self, old: &str, new: &str) -> io::Result<bool> {
+        let hash = match self.names.remove(old) {
+            Some(hash) => hash,
+            None => return Ok(false),
+        };
+
+        self.names.insert(new.to_string(), hash);
+        self.save()?;
+        Ok(true)
+    }
+
+    // Loads the normalized definition stored under `hash`. The file can have
+    // been edited or damaged, so it must hold a single `let` or `fun` whose
+    // hash is still `hash`
+    pub fn load(&self, hash: &str) -> io::Result<Node> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+        let bytes = fs::read(self.def_path(hash))?;
+        let program = from_binary(&bytes).map_err(|e| invalid(e.msg))?;
+
+        let def = match program.ast {
+            AstNode::Program(mut lst) if lst.len() == 1 => lst.pop_front().unwrap(),
+            _ => {
+                return Err(invalid(format!(
+                    "definition {} is not a single statement",
+                    hash
+                )))
+            }
+        };
+
+        let is_def = match &def.ast {
+            AstNode::Expr(expr) => {
+                matches!(expr.ast, AstNode::Let(_, _) | AstNode::Fun(_, _, _))
+            }
+            _ => false,
+        };
+        if !is_def {
+            return Err(invalid(format!(
+                "definition {} is not a `let` or a `fun`",
+                hash
+            )));
+        }
+
+        if self::hash(&def) != hash {
+            return Err(invalid(format!(
+                "definition {} does not match its hash",
+                hash
+            )));
+        }
+
+        Ok(def)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let mut txt = String::new();
+        for (name, hash) in &self.names {
+            txt.push_str(&format!("{} {}\n", hash, name));
+        }
+
+        fs::write(self.dir.join("names"), txt)
+    }
+}
+
+// SHA-256 digest of `data`
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
@@ -0,0 +1,520 @@
+// Railroad diagrams of the grammar.
+//
+// The rules are read with `pest_meta`, the same parser `pest_derive` uses,
+// so the diagrams always show the grammar the language is built from. Every
+// rule becomes one SVG, and an HTML index shows them all with the rules they
+// use linked to each other.
+//
+// Implicit whitespace and comments between the items of non-atomic rules are
+// left out, as they would appear between every two items.
+
+use pest_meta::ast::{Expr, Rule, RuleType};
+use pest_meta::parser::{self, consume_rules};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// The grammar the parser is derived from
+pub const GRAMMAR: &str = include_str!("grammar.pest");
+
+// Width of a character of the diagram font
+const CHAR: f64 = 8.0;
+// Horizontal padding around the text of a box
+const PAD: f64 = 10.0;
+// Half the height of a box
+const HALF: f64 = 11.0;
+// Space between items of a sequence
+const GAP: f64 = 10.0;
+// Radius of the arcs joining the branches of a choice or a loop
+const ARC: f64 = 10.0;
+// Vertical space between the branches of a choice
+const VGAP: f64 = 10.0;
+// Height of the label under a bounded repetition
+const LABEL: f64 = 14.0;
+// Margin around the whole diagram
+const MARGIN: f64 = 20.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Diagram {
+    // A literal piece of text, like "fun" or '0'..'9'
+    Terminal(String),
+    // A reference to another rule
+    NonTerminal(String),
+    // Something matched without being consumed, like a predicate
+    Special(String),
+    Sequence(Vec<Diagram>),
+    Choice(Vec<Diagram>),
+    Optional(Box<Diagram>),
+    // Repeated at least once, with a label telling the bounds if any
+    Repeat(Box<Diagram>, Option<String>),
+    // Matches without consuming anything
+    Skip,
+}
+
+#[derive(Debug, Clone)]
+pub struct RuleDiagram {
+    pub name: String,
+    pub ty: RuleType,
+    pub diagram: Diagram,
+}
+
+#[derive(Debug, Clone)]
+pub struct GrammarErr {
+    pub msg: String,
+}
+
+// Reads the rules of a pest grammar and turns each into a diagram
+pub fn rules(grammar: &str) -> Result<Vec<RuleDiagram>, GrammarErr> {
+    let pairs = parser::parse(parser::Rule::grammar_rules, grammar)
+        .map_err(|e| GrammarErr { msg: e.to_string() })?;
+
+    let rules = consume_rules(pairs).map_err(|errs| GrammarErr {
+        msg: errs
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join("\n"),
+    })?;
+
+    Ok(rules
+        .into_iter()
+        .map(|Rule { name, ty, expr }| RuleDiagram {
+            name,
+            ty,
+            diagram: diagram(expr),
+        })
+        .collect())
+}
+
+// Converts a pest expression, flattening the binary sequences and choices
+// pest builds into lists
+pub fn diagram(expr: Expr) -> Diagram {
+    match expr {
+        Expr::Str(s) => Diagram::Terminal(format!("{:?}", s)),
+        Expr::Insens(s) => Diagram::Terminal(format!("^{:?}", s)),
+        Expr::Range(start, end) => Diagram::Terminal(format!("'{}'..'{}'", start, end)),
+        Expr::Ident(name) => Diagram::NonTerminal(name),
+        Expr::PeekSlice(start, end) => Diagram::Special(match end {
+            Some(end) => format!("PEEK[{}..{}]", start, end),
+            None => format!("PEEK[{}..]", start),
+        }),
+        Expr::PosPred(expr) => Diagram::Special(format!("&{}", text(&expr))),
+        Expr::NegPred(expr) => Diagram::Special(format!("!{}", text(&expr))),
+        Expr::Seq(lhs, rhs) => {
+            let mut items = vec![];
+            flatten(*lhs, &mut items, true);
+            flatten(*rhs, &mut items, true);
+            Diagram::Sequence(items)
+        }
+        Expr::Choice(lhs, rhs) => {
+            let mut items = vec![];
+            flatten(*lhs, &mut items, false);
+            flatten(*rhs, &mut items, false);
+            Diagram::Choice(items)
+        }
+        Expr::Opt(expr) => Diagram::Optional(Box::new(diagram(*expr))),
+        Expr::Rep(expr) => {
+            Diagram::Optional(Box::new(Diagram::Repeat(Box::new(diagram(*expr)), None)))
+        }
+        Expr::RepOnce(expr) => Diagram::Repeat(Box::new(diagram(*expr)), None),
+        Expr::RepExact(expr, n) => {
+            Diagram::Repeat(Box::new(diagram(*expr)), Some(format!("{} times", n)))
+        }
+        Expr::RepMin(expr, min) => {
+            let label = Some(format!("at least {}", min));
+            match min {
+                0 => Diagram::Optional(Box::new(Diagram::Repeat(Box::new(diagram(*expr)), None))),
+                _ => Diagram::Repeat(Box::new(diagram(*expr)), label),
+            }
+        }
+        Expr::RepMax(expr, max) => Diagram::Optional(Box::new(Diagram::Repeat(
+            Box::new(diagram(*expr)),
+            Some(format!("at most {}", max)),
+        ))),
+        Expr::RepMinMax(expr, min, max) => {
+            let rep = Diagram::Repeat(
+                Box::new(diagram(*expr)),
+                Some(format!("{} to {} times", min, max)),
+            );
+            match min {
+                0 => Diagram::Optional(Box::new(rep)),
+                _ => rep,
+            }
+        }
+        Expr::Skip(strs) => Diagram::Special(format!(
+            "skip until {}",
+            strs.iter()
+                .map(|s| format!("{:?}", s))
+                .collect::<Vec<_>>()
+                .join(" | ")
+        )),
+        Expr::Push(expr) => Diagram::Special(format!("PUSH({})", text(&expr))),
+    }
+}
+
+fn flatten(expr: Expr, items: &mut Vec<Diagram>, seq: bool) {
+    match (expr, seq) {
+        (Expr::Seq(lhs, rhs), true) | (Expr::Choice(lhs, rhs), false) => {
+            flatten(*lhs, items, seq);
+            flatten(*rhs, items, seq);
+        }
+        (expr, _) => items.push(diagram(expr)),
+    }
+}
+
+// Writes an expression back as pest source, used to label predicates
+fn text(expr: &Expr) -> String {
+    match expr {
+        Expr::Str(s) => format!("{:?}", s),
+        Expr::Insens(s) => format!("^{:?}", s),
+        Expr::Range(start, end) => format!("'{}'..'{}'", start, end),
+        Expr::Ident(name) => name.clone(),
+        Expr::Seq(lhs, rhs) => format!("({} ~ {})", text(lhs), text(rhs)),
+        Expr::Choice(lhs, rhs) => format!("({} | {})", text(lhs), text(rhs)),
+        Expr::Opt(expr) => format!("{}?", text(expr)),
+        Expr::Rep(expr) => format!("{}*", text(expr)),
+        Expr::RepOnce(expr) => format!("{}+", text(expr)),
+        Expr::PosPred(expr) => format!("&{}", text(expr)),
+        Expr::NegPred(expr) => format!("!{}", text(expr)),
+        expr => format!("{:?}", expr),
+    }
+}
+
+// Size of a drawn diagram: its width, and its height above and below the
+// line entering and leaving it
+struct Size {
+    width: f64,
+    up: f64,
+    down: f64,
+}
+
+fn size(diagram: &Diagram) -> Size {
+    match diagram {
+        Diagram::Terminal(label) | Diagram::NonTerminal(label) | Diagram::Special(label) => Size {
+            width: label.chars().count() as f64 * CHAR + 2.0 * PAD,
+            up: HALF,
+            down: HALF,
+        },
+        Diagram::Sequence(items) => {
+            let sizes: Vec<_> = items.iter().map(size).collect();
+            Size {
+                width: sizes.iter().map(|s| s.width).sum::<f64>()
+                    + GAP * (sizes.len().max(1) - 1) as f64,
+                up: sizes.iter().map(|s| s.up).fold(0.0, f64::max),
+                down: sizes.iter().map(|s| s.down).fold(0.0, f64::max),
+            }
+        }
+        Diagram::Choice(items) => {
+            let sizes: Vec<_> = items.iter().map(size).collect();
+            let first = sizes.first().map_or(0.0, |s| s.down);
+            Size {
+                width: sizes.iter().map(|s| s.width).fold(0.0, f64::max) + 4.0 * ARC,
+                up: sizes.first().map_or(0.0, |s| s.up),
+                down: first
+                    + sizes
+                        .iter()
+                        .skip(1)
+                        .map(|s| VGAP + s.up.max(ARC) + s.down)
+                        .sum::<f64>(),
+            }
+        }
+        Diagram::Optional(item) => size(&Diagram::Choice(vec![Diagram::Skip, *item.clone()])),
+        Diagram::Repeat(item, label) => {
+            let inner = size(item);
+            let (label, below) = match label {
+                Some(label) => (label.chars().count() as f64 * CHAR, LABEL),
+                None => (0.0, 0.0),
+            };
+            Size {
+                width: inner.width.max(label) + 2.0 * ARC,
+                up: inner.up,
+                down: inner.down.max(ARC) + 2.0 * ARC + below,
+            }
+        }
+        Diagram::Skip => Size {
+            width: 0.0,
+            up: 0.0,
+            down: 0.0,
+        },
+    }
+}
+
+// Draws `diagram` with its entry at (x, y), appending the SVG to `out`.
+// `link` gives the target of a rule reference, if the rule is known.
+fn draw(
+    diagram: &Diagram,
+    x: f64,
+    y: f64,
+    link: &dyn Fn(&str) -> Option<String>,
+    out: &mut String,
+) {
+    let width = size(diagram).width;
+
+    match diagram {
+        Diagram::Terminal(label) => draw_box(label, "terminal", 12.0, x, y, width, out),
+        Diagram::Special(label) => draw_box(label, "special", 0.0, x, y, width, out),
+        Diagram::NonTerminal(name) => match link(name) {
+            Some(href) => {
+                out.push_str(&format!("<a href=\"{}\">", escape(&href)));
+                draw_box(name, "nonterminal", 0.0, x, y, width, out);
+                out.push_str("</a>\n");
+            }
+            None => draw_box(name, "nonterminal", 0.0, x, y, width, out),
+        },
+        Diagram::Sequence(items) => {
+            let mut x = x;
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    line(x, y, x + GAP, y, out);
+                    x += GAP;
+                }
+                draw(item, x, y, link, out);
+                x += size(item).width;
+            }
+        }
+        Diagram::Choice(items) => {
+            let end = x + width;
+            let mut branch_y = y;
+            for (i, item) in items.iter().enumerate() {
+                let item_size = size(item);
+                if i > 0 {
+                    branch_y += VGAP + item_size.up.max(ARC);
+                    // Down from the main line on the left, back up on the right
+                    out.push_str(&format!(
+                        "<path d=\"M{} {} q{} 0 {} {} v{} q0 {} {} {}\"/>\n",
+                        x,
+                        y,
+                        ARC,
+                        ARC,
+                        ARC,
+                        branch_y - y - 2.0 * ARC,
+                        ARC,
+                        ARC,
+                        ARC
+                    ));
+                    out.push_str(&format!(
+                        "<path d=\"M{} {} q{} 0 {} {} v{} q0 {} {} {}\"/>\n",
+                        end - 2.0 * ARC,
+                        branch_y,
+                        ARC,
+                        ARC,
+                        -ARC,
+                        -(branch_y - y - 2.0 * ARC),
+                        -ARC,
+                        ARC,
+                        -ARC
+                    ));
+                }
+                if i == 0 {
+                    line(x, y, x + 2.0 * ARC, y, out);
+                }
+                draw(item, x + 2.0 * ARC, branch_y, link, out);
+                let stop = if i > 0 { end - 2.0 * ARC } else { end };
+                line(
+                    x + 2.0 * ARC + item_size.width,
+                    branch_y,
+                    stop,
+                    branch_y,
+                    out,
+                );
+                branch_y += item_size.down;
+            }
+        }
+        Diagram::Optional(item) => draw(
+            &Diagram::Choice(vec![Diagram::Skip, *item.clone()]),
+            x,
+            y,
+            link,
+            out,
+        ),
+        Diagram::Repeat(item, label) => {
+            let inner = size(item);
+            let end = x + width;
+            line(x, y, x + ARC, y, out);
+            draw(item, x + ARC, y, link, out);
+            line(x + ARC + inner.width, y, end, y, out);
+
+            // The loop goes back under the item, from its exit to its entry
+            let back = y + inner.down.max(ARC) + ARC;
+            out.push_str(&format!(
+                "<path d=\"M{} {} q{} 0 {} {} v{} q0 {} {} {} h{} q{} 0 {} {} v{} q0 {} {} {}\"/>\n",
+                end - ARC,
+                y,
+                ARC,
+                ARC,
+                ARC,
+                back - y - 2.0 * ARC,
+                ARC,
+                -ARC,
+                ARC,
+                -(width - 2.0 * ARC),
+                -ARC,
+                -ARC,
+                -ARC,
+                -(back - y - 2.0 * ARC),
+                -ARC,
+                ARC,
+                -ARC
+            ));
+
+            if let Some(label) = label {
+                out.push_str(&format!(
+                    "<text class=\"label\" x=\"{}\" y=\"{}\">{}</text>\n",
+                    x + width / 2.0,
+                    back + LABEL,
+                    escape(label)
+                ));
+            }
+        }
+        Diagram::Skip => {}
+    }
+}
+
+fn draw_box(label: &str, class: &str, radius: f64, x: f64, y: f64, width: f64, out: &mut String) {
+    out.push_str(&format!(
+        "<g class=\"{}\"><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"{}\"/>\
+         <text x=\"{}\" y=\"{}\">{}</text></g>\n",
+        class,
+        x,
+        y - HALF,
+        width,
+        2.0 * HALF,
+        radius,
+        x + width / 2.0,
+        y + 4.0,
+        escape(label)
+    ));
+}
+
+fn line(x1: f64, y1: f64, x2: f64, y2: f64, out: &mut String) {
+    if x1 != x2 || y1 != y2 {
+        out.push_str(&format!("<path d=\"M{} {} L{} {}\"/>\n", x1, y1, x2, y2));
+    }
+}
+
+fn escape(txt: &str) -> String {
+    txt.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const STYLE: &str = "<style>
+path { fill: none; stroke: #333; stroke-width: 1.5; }
+rect { stroke: #333; stroke-width: 1.5; }
+text { font: 13px monospace; text-anchor: middle; }
+.terminal rect { fill: #fff4c8; }
+.nonterminal rect { fill: #d8ecff; }
+.special rect { fill: #eee; stroke-dasharray: 4 2; }
+.label { font-size: 11px; fill: #666; }
+a:hover rect { fill: #a8d0ff; }
+</style>
+";
+
+// Renders one diagram as a standalone SVG, with a start and an end mark
+pub fn svg(diagram: &Diagram, link: &dyn Fn(&str) -> Option<String>) -> String {
+    let Size { width, up, down } = size(diagram);
+    let y = MARGIN + up.max(HALF);
+    let start = MARGIN + GAP;
+
+    let mut out = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n",
+        width + 2.0 * (MARGIN + 2.0 * GAP),
+        y + down.max(HALF) + MARGIN
+    );
+    out.push_str(STYLE);
+
+    out.push_str(&format!(
+        "<path d=\"M{} {} v{} M{} {} h{}\"/>\n",
+        MARGIN,
+        y - HALF / 2.0,
+        HALF,
+        MARGIN,
+        y,
+        GAP
+    ));
+    draw(diagram, start, y, link, &mut out);
+    let end = start + width;
+    out.push_str(&format!(
+        "<path d=\"M{} {} h{} v{} v{}\"/>\n",
+        end,
+        y,
+        GAP,
+        -HALF / 2.0,
+        HALF
+    ));
+
+    out.push_str("</svg>\n");
+    out
+}
+
+fn rule_type(ty: RuleType) -> &'static str {
+    match ty {
+        RuleType::Normal => "",
+        RuleType::Silent => "silent",
+        RuleType::Atomic => "atomic",
+        RuleType::CompoundAtomic => "compound atomic",
+        RuleType::NonAtomic => "non-atomic",
+    }
+}
+
+// Renders the index of all the rules, with their diagrams inlined so that
+// references jump to the rule they name
+pub fn index(rules: &[RuleDiagram]) -> String {
+    let names: Vec<_> = rules.iter().map(|r| r.name.as_str()).collect();
+    let link = |name: &str| names.contains(&name).then(|| format!("#{}", name));
+
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Bang grammar</title>\n</head>\n<body>\n<h1>Bang grammar</h1>\n<ul>\n",
+    );
+
+    for rule in rules {
+        out.push_str(&format!(
+            "<li><a href=\"#{0}\">{0}</a></li>\n",
+            escape(&rule.name)
+        ));
+    }
+    out.push_str("</ul>\n");
+
+    for rule in rules {
+        out.push_str(&format!("<h2 id=\"{0}\">{0}", escape(&rule.name)));
+        match rule_type(rule.ty) {
+            "" => {}
+            ty => out.push_str(&format!(" <small>({})</small>", ty)),
+        }
+        out.push_str(&format!(
+            "</h2>\n<p><a href=\"{}.svg\">svg</a></p>\n",
+            escape(&rule.name)
+        ));
+        out.push_str(&svg(&rule.diagram, &link));
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+// Writes `<rule>.svg` for every rule of `grammar` and an `index.html` into
+// `dir`, returning the names of the rules
+pub fn write_diagrams(grammar: &str, dir: &Path) -> io::Result<Vec<String>> {
+    let rules = rules(grammar).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.msg))?;
+    let names: Vec<_> = rules.iter().map(|r| r.name.clone()).collect();
+    let link = |name: &str| {
+        names
+            .iter()
+            .any(|n| n == name)
+            .then(|| format!("{}.svg", name))
+    };
+
+    fs::create_dir_all(dir)?;
+
+    for rule in &rules {
+        fs::write(
+            dir.join(format!("{}.svg", rule.name)),
+            svg(&rule.diagram, &link),
+        )?;
+    }
+    fs::write(dir.join("index.html"), index(&rules))?;
+
+    Ok(names)
+}
@@ -1,333 +1,1007 @@
-use crate::parse::AstNode;
+use crate::codebase::{refs, restore, Codebase};
+use crate::fmt::format;
+use crate::num::{Num, NumErr, MAX_POW_BITS};
+use crate::parse::{AstNode, Node};
+use crate::serial::kind;
+use crate::span::Span;
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::LinkedList;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
 #[derive(Debug, Clone, Ord, Eq, PartialEq, PartialOrd)]
 pub enum Value {
     Map(BTreeMap<Value, Value>),
-    Num(i32),
-    Fun(LinkedList<AstNode>, LinkedList<AstNode>, Rc<RefCell<Env>>),
+    Num(Num),
+    Fun(LinkedList<Node>, LinkedList<Node>, Env),
     String(String),
     Bool(bool),
+    // A value of a variant of a declared type, with its tag and named fields
+    Data(String, Vec<(String, Value)>),
+    // A declared brand, with its name and the type of the values it wraps
+    Brand(String, String),
+    // A value wrapped with a brand, it only mixes with values of that brand
+    Branded(String, Box<Value>),
     None,
 }
 
+// The types a brand can wrap values of
+const BRANDABLE: &[&str] = &["Num", "Str", "Bool", "Map", "Fun"];
+
+// The name of the type of `val`, that of its brand for a branded value
+fn type_name(val: &Value) -> &str {
+    match val {
+        Value::Map(_) => "Map",
+        Value::Num(_) => "Num",
+        Value::Fun(_, _, _) => "Fun",
+        Value::String(_) => "Str",
+        Value::Bool(_) => "Bool",
+        Value::Data(tag, _) => tag,
+        Value::Brand(_, _) => "Brand",
+        Value::Branded(brand, _) => brand,
+        Value::None => "None",
+    }
+}
+
+// How values are printed, a branded value shows as its brand applied to the
+// value it wraps, like `UserId(5)`, and a value of a variant as its
+// constructor applied to its fields, like `Some(1)`
+pub fn show(val: &Value) -> String {
+    match val {
+        Value::Num(num) => num.to_string(),
+        Value::String(str) => str.clone(),
+        Value::Bool(b) => b.to_string(),
+        Value::None => "none".to_string(),
+        Value::Brand(name, base) => format!("brand {} = {}", name, base),
+        Value::Branded(brand, val) => format!("{}({})", brand, show(val)),
+        Value::Data(tag, fields) if fields.is_empty() => tag.clone(),
+        Value::Data(tag, fields) => {
+            let fields: Vec<_> = fields.iter().map(|(_, val)| show(val)).collect();
+            format!("{}({})", tag, fields.join(", "))
+        }
+        Value::Map(map) => {
+            let items: Vec<_> = map
+                .iter()
+                .map(|(key, val)| format!("{}: {}, ", show(key), show(val)))
+                .collect();
+            format!("{{ {}}}", items.concat())
+        }
+        val => format!("{:?}", val),
+    }
+}
+
+// What went wrong when running a program, so that callers can tell errors
+// apart without going through their message
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, PartialEq, Eq)]
+pub enum ErrKind {
+    // an operand or callee of the wrong type
+    TypeError,
+    // a name that is not bound
+    NameError,
+    // a function given more parameters than it takes
+    ArityError,
+    // a value no branch of a match matches
+    MatchError,
+    // a value that is not one of its type
+    ValueError,
+    // a division by zero, or a power too large or not exact
+    ArithError,
+    // a definition of the codebase that cannot be loaded
+    LoadError,
+    // code that failed to parse, or that cannot be evaluated
+    SyntaxError,
+}
+
+impl std::fmt::Display for ErrKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
 #[derive(Debug, Clone, Ord, PartialOrd, PartialEq, Eq)]
-pub struct RunErr(pub String);
+pub struct RunErr {
+    pub kind: ErrKind,
+    pub msg: String,
+    pub span: Span,
+}
+
+impl RunErr {
+    pub fn new(kind: ErrKind, msg: String, span: Span) -> RunErr {
+        RunErr { kind, msg, span }
+    }
+}
+
+// The error for `node` being in a place the parser never puts a node of its
+// kind, which a tree loaded as data can still do
+fn misplaced(node: &Node, place: &str) -> RunErr {
+    RunErr::new(
+        ErrKind::SyntaxError,
+        format!("Error: a {} node cannot be {}", kind(&node.ast), place),
+        node.span,
+    )
+}
+
+// A scope of variables. Scopes are shared rather than copied: a child scope
+// and the functions created in a scope point to it. A function sees a name
+// as it was bound when the function was created, and names bound only later
+// on as they are when it is called, like functions defined further down a
+// program. Binding a name again shadows it for the code that follows, so
+// every binding of a name is kept along with the time it was made at.
+// A scope holding a function that points back to it would never be freed,
+// so such a function is bound without its scope, see `Binding`. Functions
+// stored in a map, or closing over a scope of a match branch, still make
+// their scope hold itself
+#[derive(Clone)]
+pub struct Env(Rc<Frame>, usize);
+
+struct Frame {
+    vars: RefCell<BTreeMap<String, Vec<(usize, Binding)>>>,
+    // the time of the last binding made in the scope
+    time: Cell<usize>,
+    parent: Option<Env>,
+}
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Env {
-    env: BTreeMap<String, Rc<RefCell<Value>>>,
-    parent: Option<Box<Env>>,
+enum Binding {
+    Val(Value),
+    // A function closing over the scope it is bound in, with its arguments,
+    // body and the time it sees the scope at. The scope is given back when
+    // the function is looked up
+    Own(LinkedList<Node>, LinkedList<Node>, usize),
 }
 
 impl Env {
-    fn new() -> Env {
-        Env {
-            env: BTreeMap::new(),
-            parent: None,
+    // A function created in `self`, a scope only its expression sees, closes
+    // over the parent scope instead when nothing was bound in `self`. This is
+    // the same to the function, and lets the scope it is bound in recognize
+    // it as its own
+    fn settle(&self, val: Value) -> Value {
+        match (val, &self.0.parent) {
+            (Value::Fun(args, eqv, env), Some(parent))
+                if Rc::ptr_eq(&env.0, &self.0) && self.0.vars.borrow().is_empty() =>
+            {
+                Value::Fun(args, eqv, parent.capture())
+            }
+            (val, _) => val,
         }
     }
 
+    fn new() -> Env {
+        Env(
+            Rc::new(Frame {
+                vars: RefCell::new(BTreeMap::new()),
+                time: Cell::new(0),
+                parent: None,
+            }),
+            usize::MAX,
+        )
+    }
+
     fn new_child(&self) -> Env {
-        Env {
-            env: BTreeMap::new(),
-            parent: Some(Box::new(self.clone())),
-        }
+        Env(
+            Rc::new(Frame {
+                vars: RefCell::new(BTreeMap::new()),
+                time: Cell::new(0),
+                parent: Some(self.clone()),
+            }),
+            usize::MAX,
+        )
+    }
+
+    // The scope as a function created now sees it
+    fn capture(&self) -> Env {
+        Env(self.0.clone(), self.1.min(self.0.time.get()))
+    }
+
+    // The scope as a function sees it once it is bound in the scope, so that
+    // it can call itself
+    fn capture_next(&self) -> Env {
+        Env(self.0.clone(), self.1.min(self.0.time.get() + 1))
     }
 
     fn get(&self, idt: &str) -> Option<Value> {
-        match self.env.get(idt) {
-            Some(val) => Some(val.borrow().clone()),
-            None => match &self.parent {
-                Some(env) => env.get(idt),
-                None => None,
-            },
+        let vars = self.0.vars.borrow();
+        let bindings = match vars.get(idt) {
+            Some(bindings) => bindings,
+            None => {
+                return match &self.0.parent {
+                    Some(env) => env.get(idt),
+                    None => None,
+                }
+            }
+        };
+
+        // the binding seen at the time of `self`, or the last one when the
+        // name was not bound yet then
+        let binding = bindings
+            .iter()
+            .rev()
+            .find(|(time, _)| *time <= self.1)
+            .or(bindings.last())
+            .map(|(_, binding)| binding)?;
+
+        match binding {
+            Binding::Val(val) => Some(val.clone()),
+            Binding::Own(args, eqv, time) => Some(Value::Fun(
+                args.clone(),
+                eqv.clone(),
+                Env(self.0.clone(), *time),
+            )),
         }
     }
 
-    fn insert(&mut self, idt: String, val: Rc<RefCell<Value>>) {
-        self.env.insert(idt, val);
+    fn insert(&self, idt: String, val: Value) {
+        let binding = match val {
+            Value::Fun(args, eqv, env) if Rc::ptr_eq(&env.0, &self.0) => {
+                Binding::Own(args, eqv, env.1)
+            }
+            val => Binding::Val(val),
+        };
+
+        let time = self.0.time.get() + 1;
+        self.0.time.set(time);
+        self.0
+            .vars
+            .borrow_mut()
+            .entry(idt)
+            .or_default()
+            .push((time, binding));
+    }
+}
+
+// A function is bound in the scope it closes over, so scopes compare and
+// print by identity and the time they are seen at, as going through their
+// content would never end
+impl PartialEq for Env {
+    fn eq(&self, other: &Env) -> bool {
+        Rc::ptr_eq(&self.0, &other.0) && self.1 == other.1
+    }
+}
+
+impl Eq for Env {}
+
+impl PartialOrd for Env {
+    fn partial_cmp(&self, other: &Env) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Env {
+    fn cmp(&self, other: &Env) -> std::cmp::Ordering {
+        (Rc::as_ptr(&self.0), self.1).cmp(&(Rc::as_ptr(&other.0), other.1))
+    }
+}
+
+impl std::fmt::Debug for Env {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Env")
     }
 }
 
-pub fn eval_prgm(pair: AstNode) -> Result<Value, RunErr> {
+pub fn eval_prgm(pair: Node) -> Result<Value, RunErr> {
+    eval_prgm_in(pair, &mut Env::new())
+}
+
+// Evaluates a program whose free names can be definitions of `codebase`,
+// these are loaded into the environment before the program runs
+pub fn eval_prgm_with(pair: Node, codebase: &Codebase) -> Result<Value, RunErr> {
     let mut env = Env::new();
-    let mut val = Value::Num(-1);
+    let mut loaded = BTreeSet::new();
+
+    for (name, span) in refs(&pair) {
+        load_def(&name, span, codebase, &mut env, &mut loaded)?;
+    }
+
+    eval_prgm_in(pair, &mut env)
+}
+
+// Binds `name` to the codebase definition it refers to, after loading the
+// definitions that one refers to in turn. Definitions are also bound to their
+// `#<hash>`, the name other definitions know them by. Errors point at `span`,
+// the use of the name in the program that led to the load
+fn load_def(
+    name: &str,
+    span: Span,
+    codebase: &Codebase,
+    env: &mut Env,
+    loaded: &mut BTreeSet<String>,
+) -> Result<(), RunErr> {
+    let hash = match codebase.hash_of(name) {
+        Some(hash) => hash,
+        None => return Ok(()),
+    };
+    let href = format!("#{}", hash);
+
+    if loaded.insert(hash.clone()) {
+        let def = match codebase.load(&hash) {
+            Ok(def) => restore(&def),
+            Err(e) => {
+                return Err(RunErr::new(
+                    ErrKind::LoadError,
+                    format!("Error: cannot load definition {}: {}", name, e),
+                    span,
+                ))
+            }
+        };
+
+        for dep in refs(&def).into_keys() {
+            load_def(&dep, span, codebase, env, loaded)?;
+        }
+
+        let mut denv = env.new_child();
+        let val = match def.ast {
+            AstNode::Expr(expr) => eval_expr(*expr, &mut denv)?,
+            _ => {
+                return Err(RunErr::new(
+                    ErrKind::LoadError,
+                    format!("Error: cannot load definition {}: not an expression", name),
+                    span,
+                ))
+            }
+        };
+
+        env.insert(href.clone(), val);
+    }
+
+    if let Some(val) = env.get(&href) {
+        env.insert(name.to_string(), val);
+    }
+
+    Ok(())
+}
+
+fn eval_prgm_in(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let mut val = Value::Num(Num::from(-1));
 
     // insert builtin functions to the environment?
     // add_builtins(&mut env);
 
-    let lst = match pair {
+    let lst = match pair.ast {
         AstNode::Program(lst) => lst,
-        _ => {
-            unreachable!()
-        }
+        _ => return Err(misplaced(&pair, "the root of a program")),
     };
 
     for pair in lst {
-        val = match pair {
-            AstNode::Expr(expr) => eval_expr(*expr, &mut env)?,
-            AstNode::EOI => continue,
-            _ => {
-                unreachable!()
+        val = match pair.ast {
+            AstNode::Expr(expr) => eval_expr(*expr, env)?,
+            AstNode::EOI | AstNode::Comment(_, _) => continue,
+            AstNode::Error => {
+                return Err(RunErr::new(
+                    ErrKind::SyntaxError,
+                    "Error: cannot evaluate code that failed to parse".to_string(),
+                    pair.span,
+                ))
             }
+            _ => eval_expr(pair, env)?,
         };
     }
 
     Ok(val)
 }
 
-fn eval_expr(pair: AstNode, env: &mut Env) -> Result<Value, RunErr> {
-    match pair {
-        AstNode::BinAdd(_, _, _) => eval_binadd(pair, env),
-        AstNode::BinMul(_, _, _) => eval_binmul(pair, env),
-        AstNode::BinPow(_, _, _) => eval_binpow(pair, env),
-        AstNode::Num(_) => eval_num(pair),
-        AstNode::Let(_, _) => eval_ltd(pair, env),
-        AstNode::Idt(_) => eval_idt(pair, env),
-        AstNode::Expr(expr) => eval_expr(*expr, env),
-        AstNode::Fun(_, _, _) => eval_fun(pair, env),
-        AstNode::Funcall(_, _) => eval_funcall(pair, env),
-        AstNode::Match(_, _) => eval_match(pair, env),
+type Eval = fn(Node, &mut Env) -> Result<Value, RunErr>;
+
+// Picks the function evaluating `pair` before calling it, a single call keeps
+// the frame of this function small as evaluation recurses through it
+fn eval_expr(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let eval: Eval = match &pair.ast {
+        AstNode::BinAdd(_, _, _) => eval_binadd,
+        AstNode::BinMul(_, _, _) => eval_binmul,
+        AstNode::BinPow(_, _, _) => eval_binpow,
+        AstNode::BinCmp(_, _, _) => eval_bincmp,
+        AstNode::BinAnd(_, _, _) => eval_binand,
+        AstNode::BinOr(_, _, _) => eval_binor,
+        AstNode::Not(_) => eval_not,
+        AstNode::Bool(b) => return Ok(Value::Bool(*b)),
+        AstNode::None => return Ok(Value::None),
+        AstNode::Num(_) => |pair, _| eval_num(pair),
+        AstNode::Let(_, _) => eval_ltd,
+        AstNode::Idt(_) => eval_idt,
+        AstNode::Expr(_) => |pair, env| match pair.ast {
+            AstNode::Expr(expr) => eval_expr(*expr, env),
+            _ => unreachable!(),
+        },
+        AstNode::Paren(_) => |pair, env| match pair.ast {
+            AstNode::Paren(expr) => {
+                let mut scope = env.new_child();
+                let val = eval_expr(*expr, &mut scope)?;
+                Ok(scope.settle(val))
+            }
+            _ => unreachable!(),
+        },
+        AstNode::Fun(_, _, _) => eval_fun,
+        AstNode::Type(_, _) => eval_type,
+        AstNode::Variant(_, _) => eval_variant,
+        AstNode::Brand(_, _) => eval_brand,
+        AstNode::Lambda(_, _) => eval_lambda,
+        AstNode::Funcall(_, _) => eval_funcall,
+        AstNode::Call(_, _) => eval_call,
+        AstNode::Pipe(_, _) => eval_pipe,
+        AstNode::Match(_, _) => eval_match,
         // Contiune AstNode for the map
-        AstNode::Map(_) => eval_map(pair, env),
-        AstNode::Str(_) => eval_str(pair),
-        _ => {
-            unreachable!()
+        AstNode::Map(_) => eval_map,
+        AstNode::Str(_) => |pair, _| eval_str(pair),
+        ast => {
+            return Err(RunErr::new(
+                ErrKind::SyntaxError,
+                format!("Error: cannot evaluate a {} node", kind(ast)),
+                pair.span,
+            ))
         }
-    }
+    };
+
+    eval(pair, env)
 }
 
-fn eval_map(pair: AstNode, env: &mut Env) -> Result<Value, RunErr> {
-    let map = match pair {
+fn eval_map(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let map = match pair.ast {
         AstNode::Map(map) => map,
         _ => unreachable!(),
     };
 
+    #[allow(clippy::mutable_key_type)]
     let mut newmap = BTreeMap::new();
 
-
     for (key, value) in map.iter() {
         // TODO: Make sure the idt is only converted to a string if it has not been used before
         // (i.e. does not exist in the environment)
-        match key {
-            AstNode::Idt(idt) => newmap.insert(Value::String(idt.clone()), eval_expr(value.clone(), env)?),
+        match &key.ast {
+            AstNode::Idt(idt) => {
+                newmap.insert(Value::String(idt.clone()), eval_expr(value.clone(), env)?)
+            }
             _ => newmap.insert(eval_expr(key.clone(), env)?, eval_expr(value.clone(), env)?),
         };
     }
 
-
     Ok(Value::Map(newmap))
 }
 
-fn eval_match(pair: AstNode, env: &mut Env) -> Result<Value, RunErr> {
-    let (expr, cases) = match pair {
+fn eval_match(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let span = pair.span;
+
+    let (expr, cases) = match pair.ast {
         AstNode::Match(expr, cases) => (expr, cases),
         _ => {
             unreachable!()
         }
     };
 
-    let expr = match *expr {
+    let expr = match expr.ast {
         AstNode::Expr(expr) => *expr,
-        _ => {
-            unreachable!()
-        }
+        _ => return Err(misplaced(&expr, "the subject of a match")),
     };
 
-    let expr = eval_expr(expr, &mut env.new_child())?;
+    let val = eval_expr(expr, &mut env.new_child())?;
+
+    // the branch is picked apart from evaluating it, which keeps this frame
+    // small for recursive functions going through a match
+    match select_branch(cases, &val, env)? {
+        Some((eqv, mut benv)) => eval_expr(eqv, &mut benv),
+        None => Err(RunErr::new(
+            ErrKind::MatchError,
+            format!("Error: no branch of the match matches {}", show(&val)),
+            span,
+        )),
+    }
+}
 
-    let res = cases.into_iter().find_map(|case| {
-        let (pat, eqv) = match case {
+// Finds the first branch whose pattern matches `val` and whose guard holds,
+// along with the env holding the names the pattern binds
+fn select_branch(
+    cases: LinkedList<Node>,
+    val: &Value,
+    env: &Env,
+) -> Result<Option<(Node, Env)>, RunErr> {
+    for case in cases {
+        let (pat, eqv) = match case.ast {
             AstNode::Branch(pat, eqv) => (pat, eqv),
-            _ => {
-                unreachable!()
-            }
+            AstNode::Comment(_, _) => continue,
+            _ => return Err(misplaced(&case, "a case of a match")),
         };
 
-        let pat = match *pat {
-            AstNode::Expr(new) => *new,
-            AstNode::Default => {
-                return Some(eval_expr(*eqv, &mut env.new_child()));
+        let (pat, guard) = match pat.ast {
+            AstNode::Guard(pat, guard) => (*pat, Some(*guard)),
+            _ => (*pat, None),
+        };
+
+        // the names the pattern binds are seen by the guard and the branch
+        let mut benv = env.new_child();
+
+        if !match_pattern(&pat, val, &mut benv)? {
+            continue;
+        }
+
+        if let Some(guard) = guard {
+            if !eval_cond(guard, &mut benv)? {
+                continue;
             }
-            _ => {
-                unreachable!()
+        }
+
+        return Ok(Some((*eqv, benv)));
+    }
+
+    Ok(None)
+}
+
+// Matches `val` against `pat`, binding the names of the pattern in `env`.
+// List patterns match lists of their length while map patterns match any map
+// holding their keys. Expressions used as patterns match the value they give
+fn match_pattern(pat: &Node, val: &Value, env: &mut Env) -> Result<bool, RunErr> {
+    match (&pat.ast, val) {
+        (AstNode::Default, _) => Ok(true),
+        (AstNode::Bind(idt), _) => {
+            env.insert(idt.clone(), val.clone());
+            Ok(true)
+        }
+        (AstNode::Map(pats), Value::Map(map)) => {
+            let is_list = pats.keys().all(|key| matches!(key.ast, AstNode::Num(_)));
+
+            if is_list && pats.len() != map.len() {
+                return Ok(false);
             }
-        };
 
-        let pat = eval_expr(pat, &mut env.new_child());
+            for (key, pat) in pats {
+                let key = match &key.ast {
+                    AstNode::Idt(idt) => Value::String(idt.clone()),
+                    _ => eval_expr(key.clone(), env)?,
+                };
 
-        match (&expr, &pat) {
-            (&Value::Num(a), Ok(Value::Num(b))) if a.eq(b) => {
-                Some(eval_expr(*eqv, &mut env.new_child()))
+                match map.get(&key) {
+                    Some(val) if match_pattern(pat, val, env)? => {}
+                    _ => return Ok(false),
+                }
             }
-            _ => None,
+
+            Ok(true)
         }
-    });
+        (AstNode::Map(_), _) => Ok(false),
+        // a tag alone matches the variant whatever its fields
+        (AstNode::Variant(tag, pats), Value::Data(vtag, fields)) => {
+            if tag != vtag || (!pats.is_empty() && pats.len() != fields.len()) {
+                return Ok(false);
+            }
+
+            for (pat, (_, val)) in pats.iter().zip(fields) {
+                if !match_pattern(pat, val, env)? {
+                    return Ok(false);
+                }
+            }
 
-    Ok(res.unwrap()?)
+            Ok(true)
+        }
+        (AstNode::Variant(_, _), _) => Ok(false),
+        _ => Ok(eval_expr(pat.clone(), &mut env.new_child())? == *val),
+    }
 }
 
-fn eval_ltd(pair: AstNode, env: &mut Env) -> Result<Value, RunErr> {
-    let val = match pair {
+fn eval_ltd(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    match pair.ast {
         AstNode::Let(idt, eqv) => {
-            if let Ok(val) = eval_expr(*eqv, &mut env.new_child()) {
-                env.insert(idt.to_string(), Rc::new(RefCell::new(val.clone())));
-                Ok(val)
-            } else {
-                Err(RunErr("Error when evaluating val of let".to_string()))
-            }
+            let mut scope = env.new_child();
+            let val = eval_expr(*eqv, &mut scope)?;
+            let val = scope.settle(val);
+            env.insert(idt.to_string(), val.clone());
+            Ok(val)
+        }
+        _ => {
+            unreachable!()
+        }
+    }
+}
+
+fn eval_fun(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    match pair.ast {
+        AstNode::Fun(idt, args, eqv) => {
+            // the function closes over the scope it is bound in, which lets
+            // it call itself and the functions defined after it
+            let fun = Value::Fun(args, eqv, env.capture_next());
+            env.insert(idt.to_string(), fun.clone());
 
-            // eval_expr(*body, &mut env.new_child())?
+            Ok(fun)
         }
         _ => {
             unreachable!()
         }
+    }
+}
+
+// Binds the constructors of the variants of a type declaration: a variant
+// without fields is a value, the others are functions taking the fields
+fn eval_type(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let variants = match pair.ast {
+        AstNode::Type(_, variants) => variants,
+        _ => {
+            unreachable!()
+        }
     };
 
-    val
+    for variant in variants {
+        let (tag, fields) = match &variant.ast {
+            AstNode::Variant(tag, fields) => (tag.clone(), fields.clone()),
+            _ => return Err(misplaced(&variant, "a variant of a type")),
+        };
+
+        let val = if fields.is_empty() {
+            Value::Data(tag.clone(), vec![])
+        } else {
+            // the body of a constructor is its variant, see `eval_variant`
+            Value::Fun(fields, LinkedList::from([variant]), env.capture())
+        };
+
+        env.insert(tag, val);
+    }
+
+    Ok(Value::None)
 }
 
-fn eval_fun(pair: AstNode, env: &mut Env) -> Result<Value, RunErr> {
-    match pair {
-        AstNode::Fun(idt, args, eqv) => {
-            let fenv = Rc::new(RefCell::new(env.new_child()));
+// Binds a brand to its name, for `wrap` and `unwrap` to be given
+fn eval_brand(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let (name, base) = match pair.ast {
+        AstNode::Brand(name, base) => (name, base),
+        _ => {
+            unreachable!()
+        }
+    };
+
+    if !BRANDABLE.contains(&base.as_str()) {
+        return Err(RunErr::new(
+            ErrKind::TypeError,
+            format!(
+                "Error: brand {} cannot wrap {}, only {}",
+                name,
+                base,
+                BRANDABLE.join(", ")
+            ),
+            pair.span,
+        ));
+    }
 
-            // for body in eqv.clone() {
-            //     dbg!(&body);
-            // }
-            let fun = Rc::new(RefCell::new(Value::Fun(args, eqv, fenv.clone())));
+    env.insert(name.clone(), Value::Brand(name, base));
 
-            fenv.borrow_mut().insert(idt.to_string(), fun.clone());
+    Ok(Value::None)
+}
 
-            env.insert(idt.to_string(), fun);
+// Builds the value of a variant from its fields bound in `env`, as they are
+// when its constructor is called
+fn eval_variant(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let (tag, fields) = match pair.ast {
+        AstNode::Variant(tag, fields) => (tag, fields),
+        _ => {
+            unreachable!()
+        }
+    };
 
-            Ok(Value::Num(0))
+    let mut vals = Vec::new();
+    for field in fields {
+        if let AstNode::Idt(idt) = field.ast {
+            let val = env.get(&idt).unwrap_or(Value::None);
+            vals.push((idt, val));
         }
+    }
+
+    Ok(Value::Data(tag, vals))
+}
+
+fn eval_lambda(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    match pair.ast {
+        AstNode::Lambda(args, eqv) => Ok(Value::Fun(args, eqv, env.capture())),
         _ => {
             unreachable!()
         }
     }
 }
 
-fn check_builtins(
-    idt: String,
-) -> Option<fn(LinkedList<AstNode>, &mut Env) -> Result<Value, RunErr>> {
-    let mut builtins: HashMap<String, fn(LinkedList<AstNode>, &mut Env) -> Result<Value, RunErr>> =
-        HashMap::new();
+type Builtin = fn(LinkedList<Value>, Span) -> Result<Value, RunErr>;
 
-    builtins.insert(
-        "put".to_string(),
-        |params: LinkedList<AstNode>, env: &mut Env| {
-            for param in params.iter() {
-                let param = match param {
-                    AstNode::Expr(expr) => *(*expr).clone(),
-                    _ => {
-                        unreachable!()
-                    }
-                };
+fn check_builtins(idt: String) -> Option<Builtin> {
+    let mut builtins: HashMap<String, Builtin> = HashMap::new();
 
-                let val = eval_expr(param, &mut env.new_child())?;
-                println!("{:?}", val);
-            }
+    builtins.insert("put".to_string(), |params: LinkedList<Value>, _| {
+        for val in params.iter() {
+            println!("{:?}", val);
+        }
 
-            return Ok(Value::None);
-        },
-    );
-
-    builtins.insert(
-        "put".to_string(),
-        |params: LinkedList<AstNode>, env: &mut Env| {
-            for param in params.iter() {
-                let param = match param {
-                    AstNode::Expr(expr) => *(*expr).clone(),
-                    _ => {
-                        unreachable!()
-                    }
-                };
+        Ok(Value::None)
+    });
 
-                let val = eval_expr(param, &mut env.new_child())?;
-                println!("{:?}", val);
-            }
+    builtins.insert("wrap".to_string(), |params: LinkedList<Value>, span| {
+        let (name, base, val) = brand_params("wrap", params, span)?;
+
+        if type_name(&val) != base {
+            return Err(RunErr::new(
+                ErrKind::TypeError,
+                format!(
+                    "Error: cannot wrap {} as {}, it wraps values of type {}",
+                    show(&val),
+                    name,
+                    base
+                ),
+                span,
+            ));
+        }
 
-            return Ok(Value::None);
-        },
-    );
+        Ok(Value::Branded(name, Box::new(val)))
+    });
+
+    builtins.insert("unwrap".to_string(), |params: LinkedList<Value>, span| {
+        let (name, _, val) = brand_params("unwrap", params, span)?;
+
+        match val {
+            Value::Branded(brand, val) if brand == name => Ok(*val),
+            val => Err(RunErr::new(
+                ErrKind::TypeError,
+                format!("Error: cannot unwrap {} as {}", show(&val), name),
+                span,
+            )),
+        }
+    });
 
-    match builtins.get(&idt) {
-        Some(&fun) => Some(fun),
-        None => None,
+    builtins.get(&idt).copied()
+}
+
+// The brand and value `wrap` and `unwrap` are given
+fn brand_params(
+    builtin: &str,
+    params: LinkedList<Value>,
+    span: Span,
+) -> Result<(String, String, Value), RunErr> {
+    let mut params = params.into_iter();
+
+    match (params.next(), params.next(), params.next()) {
+        (Some(Value::Brand(name, base)), Some(val), None) => Ok((name, base, val)),
+        (Some(brand), Some(_), None) => Err(RunErr::new(
+            ErrKind::TypeError,
+            format!("Error: {} expects a brand, got {}", builtin, show(&brand)),
+            span,
+        )),
+        _ => Err(RunErr::new(
+            ErrKind::ArityError,
+            format!("Error: {} takes a brand and a value", builtin),
+            span,
+        )),
     }
 }
 
-fn eval_funcall(pair: AstNode, env: &mut Env) -> Result<Value, RunErr> {
-    match pair {
+fn eval_funcall(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let span = pair.span;
+
+    match pair.ast {
         AstNode::Funcall(idt, params) => {
-            let builtin = check_builtins(idt.clone());
-            match builtin {
-                Some(fun) => return fun(params, env),
-                None => {}
+            let mut vals = LinkedList::new();
+
+            for param in params {
+                vals.push_back(eval_expr(param, &mut env.new_child())?);
             }
 
-            let fun = match env.get(&idt) {
-                Some(val) => val,
-                None => return Err(RunErr(format!("Error function {} is not defined", &idt))),
-            };
-
-            match fun {
-                Value::Fun(args, eqv, fenv) => {
-                    let mut new_env = fenv.borrow_mut().new_child();
-
-                    for (arg, param) in args.iter().zip(params.iter()) {
-                        let arg = match arg {
-                            AstNode::Idt(idt) => idt,
-                            _ => {
-                                unreachable!()
-                            }
-                        };
-
-                        let param = match param {
-                            AstNode::Expr(expr) => *(*expr).clone(),
-                            _ => {
-                                unreachable!()
-                            }
-                        };
-
-                        let val = eval_expr(param, &mut env.new_child())?;
-                        new_env.insert(arg.to_string(), Rc::new(RefCell::new(val)));
-                    }
+            call_fun(&idt, vals, span, env)
+        }
+        _ => {
+            unreachable!()
+        }
+    }
+}
 
-                    new_env.insert(
-                        idt.to_string(),
-                        Rc::new(RefCell::new(Value::Fun(args, eqv.clone(), fenv))),
-                    );
+fn eval_call(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let span = pair.span;
 
-                    let mut res = Err(RunErr("Error when evaluating function".to_string()));
+    match pair.ast {
+        AstNode::Call(callee, params) => {
+            // the callee is kept to name it in errors, which is only done
+            // when there is one as formatting it is costly
+            let fun = eval_expr((*callee).clone(), &mut env.new_child())?;
 
-                    for body in eqv {
-                        res = eval_expr(body, &mut new_env);
-                    }
+            let mut vals = LinkedList::new();
+            for param in params {
+                vals.push_back(eval_expr(param, &mut env.new_child())?);
+            }
+
+            apply(fun, vals, span, &|| callee_name(&callee))
+        }
+        _ => {
+            unreachable!()
+        }
+    }
+}
+
+// How errors refer to the function `callee` evaluates to, its source when that
+// fits on a line
+fn callee_name(callee: &Node) -> Option<String> {
+    let txt = format(callee);
+    let txt = txt.trim_end();
+
+    if txt.contains('\n') || txt.len() > 40 {
+        None
+    } else {
+        Some(txt.to_string())
+    }
+}
+
+// Calls the builtin or user defined function bound to `idt` with already
+// evaluated parameters, `span` being that of the call
+fn call_fun(
+    idt: &str,
+    params: LinkedList<Value>,
+    span: Span,
+    env: &mut Env,
+) -> Result<Value, RunErr> {
+    if let Some(fun) = check_builtins(idt.to_string()) {
+        return fun(params, span);
+    }
+
+    let fun = match env.get(idt) {
+        Some(val) => val,
+        None => {
+            return Err(RunErr::new(
+                ErrKind::NameError,
+                format!("Error function {} is not defined", idt),
+                span,
+            ))
+        }
+    };
+
+    apply(fun, params, span, &|| Some(idt.to_string()))
+}
+
+// Calls the function value `fun` with already evaluated parameters, `name`
+// giving how the call refers to the function if it has a short one. Given
+// fewer parameters than it takes, the function is partially applied: the
+// parameters are bound and a function taking the remaining ones is returned
+fn apply(
+    fun: Value,
+    params: LinkedList<Value>,
+    span: Span,
+    name: &dyn Fn() -> Option<String>,
+) -> Result<Value, RunErr> {
+    match fun {
+        Value::Fun(mut args, eqv, fenv) => {
+            if params.len() > args.len() {
+                return Err(RunErr::new(
+                    ErrKind::ArityError,
+                    format!(
+                        "Error: {} takes {} argument{}, got {}",
+                        match name() {
+                            Some(name) => format!("function {}", name),
+                            None => "anonymous function".to_string(),
+                        },
+                        args.len(),
+                        if args.len() == 1 { "" } else { "s" },
+                        params.len()
+                    ),
+                    span,
+                ));
+            }
 
-                    res
+            let mut new_env = fenv.new_child();
+
+            let rest = args.split_off(params.len());
+            for (arg, val) in args.into_iter().zip(params) {
+                match arg.ast {
+                    AstNode::Idt(idt) => new_env.insert(idt, val),
+                    _ => return Err(misplaced(&arg, "an argument of a function")),
                 }
-                _ => {
-                    unreachable!()
+            }
+            let args = rest;
+
+            if !args.is_empty() {
+                return Ok(Value::Fun(args, eqv, new_env.capture()));
+            }
+
+            let mut res = Err(RunErr::new(
+                ErrKind::SyntaxError,
+                "Error when evaluating function".to_string(),
+                span,
+            ));
+
+            for body in eqv {
+                if let AstNode::Comment(_, _) = body.ast {
+                    continue;
                 }
+
+                res = eval_expr(body, &mut new_env);
             }
+
+            res
         }
+        val => Err(RunErr::new(
+            ErrKind::TypeError,
+            format!(
+                "Error: cannot call {}, it is a {} and not a function",
+                show(&val),
+                type_name(&val)
+            ),
+            span,
+        )),
+    }
+}
+
+fn eval_pipe(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let (head, stages) = match pair.ast {
+        AstNode::Pipe(head, stages) => (head, stages),
         _ => {
             unreachable!()
         }
+    };
+
+    let val = eval_expr(*head, &mut env.new_child())?;
+
+    eval_stages(val, stages, env)
+}
+
+// Threads `val` through each stage of a pipeline, the output of one stage
+// being the first parameter of the next
+#[allow(clippy::mutable_key_type)]
+fn eval_stages(val: Value, stages: LinkedList<Node>, env: &mut Env) -> Result<Value, RunErr> {
+    let mut val = val;
+
+    for stage in stages {
+        let span = stage.span;
+
+        val = match stage.ast {
+            AstNode::Idt(idt) => call_fun(&idt, LinkedList::from([val]), span, env)?,
+            AstNode::Funcall(idt, params) => {
+                let mut vals = LinkedList::from([val]);
+
+                for param in params {
+                    vals.push_back(eval_expr(param, &mut env.new_child())?);
+                }
+
+                call_fun(&idt, vals, span, env)?
+            }
+            AstNode::Lambda(_, _) => {
+                let fun = eval_lambda(stage, env)?;
+                apply(fun, LinkedList::from([val]), span, &|| None)?
+            }
+            AstNode::Each(stages) => {
+                let map = match val {
+                    Value::Map(map) => map,
+                    val => {
+                        return Err(RunErr::new(
+                            ErrKind::TypeError,
+                            format!(
+                                "Error: cannot map over {}, it is a {} and not a list or map",
+                                show(&val),
+                                type_name(&val)
+                            ),
+                            span,
+                        ))
+                    }
+                };
+
+                let mut newmap = BTreeMap::new();
+
+                for (key, val) in map {
+                    newmap.insert(key, eval_stages(val, stages.clone(), env)?);
+                }
+
+                Value::Map(newmap)
+            }
+            AstNode::Fork(arms) => {
+                let mut map = BTreeMap::new();
+
+                for (key, stages) in arms {
+                    let key = match key.ast {
+                        AstNode::Idt(idt) => Value::String(idt),
+                        _ => return Err(misplaced(&key, "a key of a fork")),
+                    };
+
+                    map.insert(key, eval_stages(val.clone(), stages, env)?);
+                }
+
+                Value::Map(map)
+            }
+            _ => return Err(misplaced(&stage, "a stage of a pipeline")),
+        };
     }
+
+    Ok(val)
 }
 
-fn eval_idt(pair: AstNode, env: &mut Env) -> Result<Value, RunErr> {
-    let idt = match pair {
+fn eval_idt(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let span = pair.span;
+
+    let idt = match pair.ast {
         AstNode::Idt(idt) => idt,
         _ => {
             unreachable!()
@@ -336,12 +1010,18 @@ fn eval_idt(pair: AstNode, env: &mut Env) -> Result<Value, RunErr> {
 
     match env.get(&idt) {
         Some(val) => Ok(val.clone()),
-        None => Err(RunErr(format!("Error: identifier {} is not defined", &idt))),
+        None => Err(RunErr::new(
+            ErrKind::NameError,
+            format!("Error: identifier {} is not defined", &idt),
+            span,
+        )),
     }
 }
 
-fn eval_binadd(pair: AstNode, env: &mut Env) -> Result<Value, RunErr> {
-    let (lhs, op, rhs) = match pair {
+fn eval_binadd(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let span = pair.span;
+
+    let (lhs, op, rhs) = match pair.ast {
         AstNode::BinAdd(lhs, op, rhs) => (lhs, op, rhs),
         _ => {
             unreachable!()
@@ -351,17 +1031,16 @@ fn eval_binadd(pair: AstNode, env: &mut Env) -> Result<Value, RunErr> {
     match (
         eval_expr(*lhs, &mut env.new_child()),
         eval_expr(*rhs, &mut env.new_child()),
-        *op,
     ) {
-        (Ok(Value::Num(lhs)), Ok(Value::Num(rhs)), AstNode::Add) => Ok(Value::Num(lhs + rhs)),
-        (Ok(Value::Num(lhs)), Ok(Value::Num(rhs)), AstNode::Sub) => Ok(Value::Num(lhs - rhs)),
-        (_, Err(e), _) => Err(e),
-        _ => unreachable!(),
+        (Ok(lhs), Ok(rhs)) => arith(lhs, op.ast, rhs, span),
+        (Err(e), _) | (_, Err(e)) => Err(e),
     }
 }
 
-fn eval_binmul(pair: AstNode, env: &mut Env) -> Result<Value, RunErr> {
-    let (lhs, op, rhs) = match pair {
+fn eval_binmul(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let span = pair.span;
+
+    let (lhs, op, rhs) = match pair.ast {
         AstNode::BinMul(lhs, op, rhs) => (lhs, op, rhs),
         _ => {
             unreachable!()
@@ -371,17 +1050,16 @@ fn eval_binmul(pair: AstNode, env: &mut Env) -> Result<Value, RunErr> {
     match (
         eval_expr(*lhs, &mut env.new_child()),
         eval_expr(*rhs, &mut env.new_child()),
-        *op,
     ) {
-        (Ok(Value::Num(lhs)), Ok(Value::Num(rhs)), AstNode::Mul) => Ok(Value::Num(lhs * rhs)),
-        (Ok(Value::Num(lhs)), Ok(Value::Num(rhs)), AstNode::Div) => Ok(Value::Num(lhs / rhs)),
-        (_, Err(e), _) => Err(e),
-        _ => unreachable!(),
+        (Ok(lhs), Ok(rhs)) => arith(lhs, op.ast, rhs, span),
+        (Err(e), _) | (_, Err(e)) => Err(e),
     }
 }
 
-fn eval_binpow(pair: AstNode, env: &mut Env) -> Result<Value, RunErr> {
-    let (lhs, op, rhs) = match pair {
+fn eval_binpow(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let span = pair.span;
+
+    let (lhs, op, rhs) = match pair.ast {
         AstNode::BinPow(lhs, op, rhs) => (lhs, op, rhs),
         _ => {
             unreachable!()
@@ -391,29 +1069,244 @@ fn eval_binpow(pair: AstNode, env: &mut Env) -> Result<Value, RunErr> {
     match (
         eval_expr(*lhs, &mut env.new_child()),
         eval_expr(*rhs, &mut env.new_child()),
-        *op,
     ) {
-        (Ok(Value::Num(lhs)), Ok(Value::Num(rhs)), AstNode::Pow) => {
-            Ok(Value::Num(lhs.pow(rhs as u32)))
+        (Ok(lhs), Ok(rhs)) => arith(lhs, op.ast, rhs, span),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+    }
+}
+
+// Applies the arithmetic operator `op`, once both operands are evaluated so
+// that the frames of the recursive evaluation stay small
+fn arith(lhs: Value, op: AstNode, rhs: Value, span: Span) -> Result<Value, RunErr> {
+    let (lhs, rhs, brand) = unbrand(lhs, rhs, &op, span)?;
+
+    let val = match (lhs, rhs, op) {
+        (Value::Num(lhs), Value::Num(rhs), op) => Value::Num(num_arith(lhs, &op, rhs, span)?),
+        (lhs, rhs, op) => {
+            return Err(RunErr::new(
+                ErrKind::TypeError,
+                format!(
+                    "Error: cannot {} {} and {}, only numbers can be",
+                    verb(&op),
+                    type_name(&lhs),
+                    type_name(&rhs)
+                ),
+                span,
+            ))
         }
-        (_, Err(e), _) => Err(e),
-        _ => unreachable!(),
+    };
+
+    Ok(rebrand(brand, val))
+}
+
+// Applies `op` to numbers. Integers grow as large as they need, and a
+// division that does not come out even gives an exact fraction
+fn num_arith(lhs: Num, op: &AstNode, rhs: Num, span: Span) -> Result<Num, RunErr> {
+    let res = match op {
+        AstNode::Add => Ok(lhs.add(&rhs)),
+        AstNode::Sub => Ok(lhs.sub(&rhs)),
+        AstNode::Mul => Ok(lhs.mul(&rhs)),
+        AstNode::Div => lhs.div(&rhs),
+        AstNode::Pow => lhs.pow(&rhs),
+        op => {
+            return Err(RunErr::new(
+                ErrKind::SyntaxError,
+                format!("Error: cannot evaluate a {} operator", kind(op)),
+                span,
+            ))
+        }
+    };
+
+    res.map_err(|err| {
+        let msg = match (err, op) {
+            (NumErr::DivByZero, AstNode::Div) => format!("Error: cannot divide {} by zero", lhs),
+            (NumErr::DivByZero, _) => {
+                format!("Error: cannot raise {} to {}, it divides by zero", lhs, rhs)
+            }
+            (NumErr::TooLarge, _) => format!(
+                "Error: {} ** {} is too large, powers go up to {} bits",
+                lhs, rhs, MAX_POW_BITS
+            ),
+            (NumErr::FracPower, _) => format!(
+                "Error: cannot raise {} to {}, powers have to be integers",
+                lhs, rhs
+            ),
+        };
+
+        RunErr::new(ErrKind::ArithError, msg, span)
+    })
+}
+
+// The operands of `op` without their brand, along with the brand when both
+// have the same one. Values of a brand only mix with values of that brand
+fn unbrand(
+    lhs: Value,
+    rhs: Value,
+    op: &AstNode,
+    span: Span,
+) -> Result<(Value, Value, Option<String>), RunErr> {
+    match (lhs, rhs) {
+        (Value::Branded(a, lhs), Value::Branded(b, rhs)) if a == b => Ok((*lhs, *rhs, Some(a))),
+        (lhs, rhs)
+            if matches!(lhs, Value::Branded(_, _)) || matches!(rhs, Value::Branded(_, _)) =>
+        {
+            Err(RunErr::new(
+                ErrKind::TypeError,
+                format!(
+                    "Error: cannot {} {} and {}, branded values only mix with values of their brand",
+                    verb(op),
+                    show(&lhs),
+                    show(&rhs),
+                ),
+                span,
+            ))
+        }
+        (lhs, rhs) => Ok((lhs, rhs, None)),
+    }
+}
+
+// How errors name what the operator `op` does
+fn verb(op: &AstNode) -> &'static str {
+    match op {
+        AstNode::Add => "add",
+        AstNode::Sub => "subtract",
+        AstNode::Mul => "multiply",
+        AstNode::Div => "divide",
+        AstNode::Pow => "raise",
+        _ => "compare",
+    }
+}
+
+// Wraps the result of an operation on values of a brand back in it
+fn rebrand(brand: Option<String>, val: Value) -> Value {
+    match brand {
+        Some(brand) => Value::Branded(brand, Box::new(val)),
+        None => val,
     }
 }
 
-fn eval_num(pair: AstNode) -> Result<Value, RunErr> {
-    let num = match pair {
+fn eval_bincmp(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let span = pair.span;
+
+    let (lhs, op, rhs) = match pair.ast {
+        AstNode::BinCmp(lhs, op, rhs) => (lhs, op, rhs),
+        _ => {
+            unreachable!()
+        }
+    };
+
+    match (
+        eval_expr(*lhs, &mut env.new_child()),
+        eval_expr(*rhs, &mut env.new_child()),
+    ) {
+        (Ok(lhs), Ok(rhs)) => compare(lhs, op.ast, rhs, span),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+    }
+}
+
+// Applies the comparison operator `op` to evaluated operands
+fn compare(lhs: Value, op: AstNode, rhs: Value, span: Span) -> Result<Value, RunErr> {
+    let (lhs, rhs, _) = unbrand(lhs, rhs, &op, span)?;
+
+    let ord = match (&lhs, &rhs) {
+        (Value::Num(a), Value::Num(b)) => Some(a.cmp(b)),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+
+    match (op, ord) {
+        (AstNode::Eq, _) => Ok(Value::Bool(lhs == rhs)),
+        (AstNode::Ne, _) => Ok(Value::Bool(lhs != rhs)),
+        (AstNode::Lt, Some(ord)) => Ok(Value::Bool(ord.is_lt())),
+        (AstNode::Le, Some(ord)) => Ok(Value::Bool(ord.is_le())),
+        (AstNode::Gt, Some(ord)) => Ok(Value::Bool(ord.is_gt())),
+        (AstNode::Ge, Some(ord)) => Ok(Value::Bool(ord.is_ge())),
+        _ => Err(RunErr::new(
+            ErrKind::TypeError,
+            format!(
+                "Error: cannot order {} and {}, only numbers and strings can be",
+                type_name(&lhs),
+                type_name(&rhs)
+            ),
+            span,
+        )),
+    }
+}
+
+// Evaluates `pair` expecting a bool, as used by the operands of `and`, `or`
+// and `not`
+fn eval_cond(pair: Node, env: &mut Env) -> Result<bool, RunErr> {
+    let span = pair.span;
+
+    match eval_expr(pair, &mut env.new_child())? {
+        Value::Bool(b) => Ok(b),
+        val => Err(RunErr::new(
+            ErrKind::TypeError,
+            format!(
+                "Error: expected a Bool, got {} {}",
+                type_name(&val),
+                show(&val)
+            ),
+            span,
+        )),
+    }
+}
+
+fn eval_binand(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let (lhs, rhs) = match pair.ast {
+        AstNode::BinAnd(lhs, _, rhs) => (lhs, rhs),
+        _ => {
+            unreachable!()
+        }
+    };
+
+    // rhs is only evaluated when lhs holds
+    Ok(Value::Bool(eval_cond(*lhs, env)? && eval_cond(*rhs, env)?))
+}
+
+fn eval_binor(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let (lhs, rhs) = match pair.ast {
+        AstNode::BinOr(lhs, _, rhs) => (lhs, rhs),
+        _ => {
+            unreachable!()
+        }
+    };
+
+    // rhs is only evaluated when lhs does not hold
+    Ok(Value::Bool(eval_cond(*lhs, env)? || eval_cond(*rhs, env)?))
+}
+
+fn eval_not(pair: Node, env: &mut Env) -> Result<Value, RunErr> {
+    let expr = match pair.ast {
+        AstNode::Not(expr) => expr,
+        _ => {
+            unreachable!()
+        }
+    };
+
+    Ok(Value::Bool(!eval_cond(*expr, env)?))
+}
+
+fn eval_num(pair: Node) -> Result<Value, RunErr> {
+    let num = match pair.ast {
         AstNode::Num(num) => num,
         _ => {
             unreachable!()
         }
     };
 
-    Ok(Value::Num(num.parse::<i32>().unwrap()))
+    match Num::parse(&num) {
+        Some(num) => Ok(Value::Num(num)),
+        None => Err(RunErr::new(
+            ErrKind::ValueError,
+            format!("Error: {} is not a number", num),
+            pair.span,
+        )),
+    }
 }
 
-fn eval_str(pair: AstNode) -> Result<Value, RunErr> {
-    let str = match pair {
+fn eval_str(pair: Node) -> Result<Value, RunErr> {
+    let str = match pair.ast {
         AstNode::Str(str) => str,
         _ => {
             unreachable!()
@@ -0,0 +1,328 @@
+use crate::parse::{AstNode, Node};
+
+const INDENT: &str = "  ";
+const WIDTH: usize = 80;
+
+// Renders a parsed program back into canonical Bang source
+pub fn format(node: &Node) -> String {
+    let mut out = String::new();
+
+    match &node.ast {
+        AstNode::Program(lst) => fmt_block(lst.iter(), 0, &mut out),
+        _ => {
+            out.push_str(&fmt_expr(node, 0, 0));
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+fn indent(depth: usize) -> String {
+    INDENT.repeat(depth)
+}
+
+// Column reached after writing `txt` from column `col`
+fn end_col(col: usize, txt: &str) -> usize {
+    match txt.rfind('\n') {
+        Some(i) => txt.len() - i - 1,
+        None => col + txt.len(),
+    }
+}
+
+// Writes each statement of a program, function body or match on its own line.
+// Top-level statements are separated by a blank line, unless the first one is
+// a comment on its own line, which then stays attached to the statement below
+fn fmt_block<'a>(lst: impl Iterator<Item = &'a Node>, depth: usize, out: &mut String) {
+    let mut prev: Option<&Node> = None;
+
+    for node in lst {
+        match &node.ast {
+            AstNode::EOI => continue,
+            AstNode::Comment(text, true) if out.ends_with('\n') => {
+                out.pop();
+                out.push(' ');
+                out.push_str(text);
+                out.push('\n');
+            }
+            _ => {
+                let after_comment =
+                    matches!(prev.map(|node| &node.ast), Some(AstNode::Comment(_, false)));
+
+                if depth == 0 && prev.is_some() && !after_comment {
+                    out.push('\n');
+                }
+
+                out.push_str(&indent(depth));
+                out.push_str(&fmt_expr(node, depth, indent(depth).len()));
+                out.push('\n');
+            }
+        }
+
+        prev = Some(node);
+    }
+}
+
+// Lays out comma separated `items` between `open` and `close`, on one line
+// when they fit from column `col` or one item per line otherwise. Map and list
+// literals need a comma after every item, which is what `trailing` is for
+fn fmt_seq(
+    items: Vec<String>,
+    open: &str,
+    close: &str,
+    trailing: bool,
+    depth: usize,
+    col: usize,
+) -> String {
+    if items.is_empty() {
+        return format!("{}{}", open.trim(), close.trim());
+    }
+
+    let line = format!(
+        "{}{}{}{}",
+        open,
+        items.join(", "),
+        if trailing { "," } else { "" },
+        close
+    );
+
+    if col + line.len() <= WIDTH && !line.contains('\n') {
+        return line;
+    }
+
+    let mut out = open.trim_end().to_string();
+    for (i, item) in items.iter().enumerate() {
+        out.push('\n');
+        out.push_str(&indent(depth + 1));
+        out.push_str(item);
+        if trailing || i + 1 < items.len() {
+            out.push(',');
+        }
+    }
+    out.push('\n');
+    out.push_str(&indent(depth));
+    out.push_str(close.trim_start());
+
+    out
+}
+
+pub(crate) fn fmt_op(node: &Node) -> &'static str {
+    match node.ast {
+        AstNode::Add => "+",
+        AstNode::Sub => "-",
+        AstNode::Mul => "*",
+        AstNode::Div => "/",
+        AstNode::Pow => "**",
+        AstNode::Eq => "==",
+        AstNode::Ne => "!=",
+        AstNode::Lt => "<",
+        AstNode::Le => "<=",
+        AstNode::Gt => ">",
+        AstNode::Ge => ">=",
+        AstNode::And => "and",
+        AstNode::Or => "or",
+        _ => {
+            unreachable!()
+        }
+    }
+}
+
+fn fmt_stages<'a>(stages: impl Iterator<Item = &'a Node>, depth: usize, col: usize) -> Vec<String> {
+    stages.map(|stage| fmt_expr(stage, depth, col)).collect()
+}
+
+// Renders an expression starting at column `col`, lines after the first one
+// are indented for `depth`
+fn fmt_expr(node: &Node, depth: usize, col: usize) -> String {
+    match &node.ast {
+        AstNode::Expr(expr) => fmt_expr(expr, depth, col),
+        AstNode::Paren(expr) => format!("({})", fmt_expr(expr, depth, col + 1)),
+        AstNode::Idt(idt) => idt.clone(),
+        AstNode::Num(num) => num.clone(),
+        AstNode::Str(str) => str.clone(),
+        AstNode::Bool(b) => b.to_string(),
+        AstNode::Default => "_".to_string(),
+        AstNode::None => "none".to_string(),
+        AstNode::Bind(idt) => idt.clone(),
+        AstNode::Comment(text, _) => text.clone(),
+        AstNode::Map(map) => {
+            let is_list = map.keys().all(|key| matches!(key.ast, AstNode::Num(_)));
+
+            let items = if is_list {
+                // list keys are numbers as strings, which would sort 10 before 2
+                let mut items: Vec<_> = map.iter().collect();
+                items.sort_by_key(|(key, _)| match &key.ast {
+                    AstNode::Num(num) => num.parse::<usize>().unwrap_or(0),
+                    _ => 0,
+                });
+                items
+                    .into_iter()
+                    .map(|(_, val)| fmt_expr(val, depth + 1, col))
+                    .collect()
+            } else {
+                map.iter()
+                    .map(|(key, val)| {
+                        let key = fmt_expr(key, depth + 1, col);
+                        let val = fmt_expr(val, depth + 1, col + key.len() + 2);
+                        format!("{}: {}", key, val)
+                    })
+                    .collect()
+            };
+
+            fmt_seq(items, "{ ", " }", true, depth, col)
+        }
+        AstNode::Let(idt, expr) => {
+            let prefix = format!("let {} = ", idt);
+            let expr = fmt_expr(expr, depth, col + prefix.len());
+            format!("{}{}", prefix, expr)
+        }
+        AstNode::Fun(idt, args, body) => {
+            let args: Vec<_> = args.iter().map(|arg| fmt_expr(arg, depth, col)).collect();
+
+            let mut out = format!("fun {}({})\n", idt, args.join(", "));
+            fmt_block(body.iter(), depth + 1, &mut out);
+            out.push_str(&indent(depth));
+            out.push_str("end");
+
+            out
+        }
+        AstNode::Lambda(args, body) => {
+            let args: Vec<_> = args.iter().map(|arg| fmt_expr(arg, depth, col)).collect();
+            let head = format!("fun({})", args.join(", "));
+
+            // a lambda of a single expression stays on one line when it fits
+            if let [expr] = body.iter().collect::<Vec<_>>()[..] {
+                let expr = fmt_expr(expr, depth, col + head.len() + 1);
+                let line = format!("{} {} end", head, expr);
+
+                if col + line.len() <= WIDTH && !line.contains('\n') {
+                    return line;
+                }
+            }
+
+            let mut out = format!("{}\n", head);
+            fmt_block(body.iter(), depth + 1, &mut out);
+            out.push_str(&indent(depth));
+            out.push_str("end");
+
+            out
+        }
+        AstNode::Call(callee, params) => {
+            let callee = match callee.ast {
+                AstNode::Paren(_) | AstNode::Funcall(_, _) | AstNode::Call(_, _) => {
+                    fmt_expr(callee, depth, col)
+                }
+                // only parenthesized expressions and calls can be called
+                _ => format!("({})", fmt_expr(callee, depth, col + 1)),
+            };
+            let col = end_col(col, &callee);
+            let params = fmt_stages(params.iter(), depth, col + 1);
+            format!("{}{}", callee, fmt_seq(params, "(", ")", false, depth, col))
+        }
+        AstNode::Type(name, variants) => {
+            let prefix = format!("type {} = ", name);
+            let variants: Vec<_> = variants
+                .iter()
+                .map(|variant| fmt_expr(variant, depth + 1, col))
+                .collect();
+            let line = format!("{}{}", prefix, variants.join(" | "));
+
+            if col + line.len() <= WIDTH {
+                return line;
+            }
+
+            // one variant per line, each after a leading `|`
+            let mut out = prefix.trim_end().to_string();
+            for variant in variants {
+                out.push('\n');
+                out.push_str(&indent(depth + 1));
+                out.push_str("| ");
+                out.push_str(&variant);
+            }
+
+            out
+        }
+        AstNode::Brand(name, base) => format!("brand {} = {}", name, base),
+        AstNode::Variant(tag, fields) if fields.is_empty() => tag.clone(),
+        AstNode::Variant(tag, fields) => {
+            let col = col + tag.len();
+            let fields = fmt_stages(fields.iter(), depth, col + 1);
+            format!("{}{}", tag, fmt_seq(fields, "(", ")", false, depth, col))
+        }
+        AstNode::Funcall(idt, params) => {
+            let col = col + idt.len();
+            let params = fmt_stages(params.iter(), depth, col + 1);
+            format!("{}{}", idt, fmt_seq(params, "(", ")", false, depth, col))
+        }
+        AstNode::Pipe(head, stages) => {
+            let head = fmt_expr(head, depth, col);
+            let stages = fmt_stages(stages.iter(), depth + 1, indent(depth + 1).len() + 3);
+            let line = format!("{} |> {}", head, stages.join(" |> "));
+
+            if col + line.len() <= WIDTH && !line.contains('\n') {
+                return line;
+            }
+
+            let mut out = head;
+            for stage in stages {
+                out.push('\n');
+                out.push_str(&indent(depth + 1));
+                out.push_str("|> ");
+                out.push_str(&stage);
+            }
+
+            out
+        }
+        AstNode::Each(stages) => {
+            format!(
+                "[{}]",
+                fmt_stages(stages.iter(), depth, col + 1).join(" |> ")
+            )
+        }
+        AstNode::Fork(arms) => {
+            let arms = arms
+                .iter()
+                .map(|(key, stages)| {
+                    let key = fmt_expr(key, depth + 1, col);
+                    let stages = fmt_stages(stages.iter(), depth + 1, col + key.len() + 2);
+                    format!("{}: {}", key, stages.join(" |> "))
+                })
+                .collect();
+
+            fmt_seq(arms, "{ ", " }", false, depth, col)
+        }
+        AstNode::BinAdd(lhs, op, rhs)
+        | AstNode::BinMul(lhs, op, rhs)
+        | AstNode::BinPow(lhs, op, rhs)
+        | AstNode::BinCmp(lhs, op, rhs)
+        | AstNode::BinAnd(lhs, op, rhs)
+        | AstNode::BinOr(lhs, op, rhs) => {
+            let lhs = fmt_expr(lhs, depth, col);
+            let op = fmt_op(op);
+            let rhs = fmt_expr(rhs, depth, end_col(col, &lhs) + op.len() + 2);
+            format!("{} {} {}", lhs, op, rhs)
+        }
+        AstNode::Not(expr) => format!("not {}", fmt_expr(expr, depth, col + 4)),
+        AstNode::Match(expr, cases) => {
+            let mut out = format!("match {}\n", fmt_expr(expr, depth, col + 6));
+            fmt_block(cases.iter(), depth + 1, &mut out);
+            out.push_str(&indent(depth));
+            out.push_str("end");
+
+            out
+        }
+        AstNode::Guard(pat, guard) => {
+            let pat = fmt_expr(pat, depth, col);
+            let guard = fmt_expr(guard, depth, end_col(col, &pat) + 4);
+            format!("{} if {}", pat, guard)
+        }
+        AstNode::Branch(pat, expr) => {
+            let pat = fmt_expr(pat, depth, col);
+            let expr = fmt_expr(expr, depth, end_col(col, &pat) + 4);
+            format!("{} => {}", pat, expr)
+        }
+        _ => {
+            unreachable!()
+        }
+    }
+}
@@ -0,0 +1,865 @@
+use crate::parse::{AstNode, Node};
+use crate::span::Span;
+use std::collections::{BTreeMap, BTreeSet, LinkedList};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeErr {
+    pub msg: String,
+    pub span: Span,
+}
+
+impl TypeErr {
+    pub fn new(msg: String, span: Span) -> TypeErr {
+        TypeErr { msg, span }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Type {
+    Num,
+    Str,
+    Bool,
+    None,
+    // maps and lists, whatever they hold
+    Map,
+    Fun(Vec<Type>, Box<Type>),
+    // either `none` or a value of the type it holds, which is never optional
+    Opt(Box<Type>),
+    // a value of a declared type, by the name of the type
+    Data(String),
+    // a value wrapped with a brand, by the name of the brand
+    Brand(String),
+    Var(usize),
+}
+
+// A type along with the variables that stand for any type in it, those of a
+// function that works on values of any type
+#[derive(Debug, Clone)]
+struct Scheme {
+    vars: Vec<usize>,
+    ty: Type,
+}
+
+impl Scheme {
+    fn mono(ty: Type) -> Scheme {
+        Scheme { vars: vec![], ty }
+    }
+}
+
+// Infers the types of a program, returning those of the names it binds at its
+// top level along with the type errors found on the way. Names bound nowhere
+// in the program, like definitions of a codebase, may be of any type
+pub fn infer(node: &Node) -> (BTreeMap<String, String>, Vec<TypeErr>) {
+    let mut infer = Infer {
+        vars: Vec::new(),
+        scopes: vec![BTreeMap::new()],
+        pending: vec![BTreeMap::new()],
+        tags: BTreeMap::new(),
+        brands: BTreeMap::new(),
+        errs: Vec::new(),
+    };
+
+    if let AstNode::Program(body) = &node.ast {
+        infer.block(body.iter());
+    }
+
+    let types = infer.scopes[0]
+        .iter()
+        .map(|(name, scheme)| (name.clone(), infer.show(&scheme.ty)))
+        .collect();
+
+    (types, infer.errs)
+}
+
+struct Infer {
+    // what each type variable was found to be
+    vars: Vec<Option<Type>>,
+    scopes: Vec<BTreeMap<String, Scheme>>,
+    // the functions of each scope used before they are defined, by the
+    // variable standing for their type until then
+    pending: Vec<BTreeMap<String, Type>>,
+    // the type each variant tag belongs to, with its number of fields
+    tags: BTreeMap<String, (String, usize)>,
+    // the type of the values each brand wraps
+    brands: BTreeMap<String, Type>,
+    errs: Vec<TypeErr>,
+}
+
+impl Infer {
+    fn fresh(&mut self) -> Type {
+        self.vars.push(None);
+        Type::Var(self.vars.len() - 1)
+    }
+
+    fn err(&mut self, msg: String, span: Span) {
+        self.errs.push(TypeErr::new(msg, span));
+    }
+
+    // `ty` with the variables found so far replaced, at its top only
+    fn resolve(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(var) => match &self.vars[*var] {
+                Some(ty) => self.resolve(ty),
+                None => ty.clone(),
+            },
+            ty => ty.clone(),
+        }
+    }
+
+    // `ty` with every variable found so far replaced
+    fn zonk(&self, ty: &Type) -> Type {
+        match self.resolve(ty) {
+            Type::Fun(params, ret) => Type::Fun(
+                params.iter().map(|param| self.zonk(param)).collect(),
+                Box::new(self.zonk(&ret)),
+            ),
+            Type::Opt(ty) => Type::Opt(Box::new(self.zonk(&ty))),
+            ty => ty,
+        }
+    }
+
+    fn occurs(&self, var: usize, ty: &Type) -> bool {
+        match self.resolve(ty) {
+            Type::Var(other) => other == var,
+            Type::Fun(params, ret) => {
+                params.iter().any(|param| self.occurs(var, param)) || self.occurs(var, &ret)
+            }
+            Type::Opt(ty) => self.occurs(var, &ty),
+            _ => false,
+        }
+    }
+
+    // Makes `a` and `b` the same type. Functions are curried, one taking two
+    // parameters is the same as one taking the first and giving a function
+    // taking the second
+    fn unify(&mut self, a: &Type, b: &Type) -> bool {
+        match (self.resolve(a), self.resolve(b)) {
+            (Type::Var(a), Type::Var(b)) if a == b => true,
+            (Type::Var(var), ty) | (ty, Type::Var(var)) => {
+                if self.occurs(var, &ty) {
+                    return false;
+                }
+                self.vars[var] = Some(ty);
+                true
+            }
+            (Type::Fun(pa, ra), Type::Fun(pb, rb)) => {
+                let len = pa.len().min(pb.len());
+                if len == 0 && pa.len() != pb.len() {
+                    return false;
+                }
+
+                let rest = |params: &[Type], ret: Box<Type>| match &params[len..] {
+                    [] => *ret,
+                    rest => Type::Fun(rest.to_vec(), ret),
+                };
+                let (ra, rb) = (rest(&pa, ra), rest(&pb, rb));
+
+                pa.iter().zip(&pb).all(|(a, b)| self.unify(a, b)) && self.unify(&ra, &rb)
+            }
+            (Type::Opt(a), Type::Opt(b)) => self.unify(&a, &b),
+            (a, b) => a == b,
+        }
+    }
+
+    // Unifies the type `found` of the node at `span` with the one it is
+    // expected to have, reporting when it cannot be
+    fn expect(&mut self, found: &Type, expected: &Type, span: Span) {
+        if !self.fits(found, expected) {
+            let msg = format!(
+                "Error: expected {}, found {}",
+                self.show(expected),
+                self.show(found)
+            );
+            self.err(msg, span);
+        }
+    }
+
+    // Whether values of type `found` can be given where `expected` ones are,
+    // unifying them. An optional type takes `none` and the values it holds but
+    // not the other way around, a branch where they are told apart is needed
+    fn fits(&mut self, found: &Type, expected: &Type) -> bool {
+        match (self.resolve(found), self.resolve(expected)) {
+            (Type::None, Type::Opt(_)) => true,
+            (Type::Var(_), _) | (Type::Opt(_), _) => self.unify(found, expected),
+            (found, Type::Opt(ty)) => self.unify(&found, &ty),
+            _ => self.unify(found, expected),
+        }
+    }
+
+    // The type of the values of both `a` and `b`, which is optional when one
+    // of them is `none`
+    fn join(&mut self, a: &Type, b: &Type) -> Option<Type> {
+        match (self.resolve(a), self.resolve(b)) {
+            (Type::None, Type::Var(_)) | (Type::Var(_), Type::None) => {
+                self.unify(a, b).then(|| a.clone())
+            }
+            (Type::None, Type::Opt(_)) => Some(b.clone()),
+            (Type::Opt(_), Type::None) => Some(a.clone()),
+            (Type::None, ty) | (ty, Type::None) if ty != Type::None => {
+                let opt = Type::Opt(Box::new(self.fresh()));
+                self.fits(&ty, &opt).then_some(opt)
+            }
+            (Type::Opt(_), Type::Opt(_)) => self.unify(a, b).then(|| a.clone()),
+            (Type::Opt(inner), ty) | (ty, Type::Opt(inner)) if !matches!(ty, Type::Var(_)) => {
+                self.unify(&inner, &ty).then_some(Type::Opt(inner))
+            }
+            _ => self.unify(a, b).then(|| a.clone()),
+        }
+    }
+
+    // The type of the values of type `ty` that are not `none`
+    fn some(&self, ty: &Type) -> Type {
+        match self.resolve(ty) {
+            Type::Opt(ty) => *ty,
+            _ => ty.clone(),
+        }
+    }
+
+    fn lookup(&mut self, name: &str) -> Option<Type> {
+        let scheme = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))?
+            .clone();
+
+        // every use of a function of any type may use it on other types
+        let mut map = BTreeMap::new();
+        for var in scheme.vars {
+            map.insert(var, self.fresh());
+        }
+        Some(self.subst(&scheme.ty, &map))
+    }
+
+    fn subst(&self, ty: &Type, map: &BTreeMap<usize, Type>) -> Type {
+        match self.resolve(ty) {
+            Type::Var(var) => map.get(&var).cloned().unwrap_or(Type::Var(var)),
+            Type::Fun(params, ret) => Type::Fun(
+                params.iter().map(|param| self.subst(param, map)).collect(),
+                Box::new(self.subst(&ret, map)),
+            ),
+            Type::Opt(ty) => Type::Opt(Box::new(self.subst(&ty, map))),
+            ty => ty,
+        }
+    }
+
+    fn free(&self, ty: &Type, out: &mut BTreeSet<usize>) {
+        match self.resolve(ty) {
+            Type::Var(var) => {
+                out.insert(var);
+            }
+            Type::Fun(params, ret) => {
+                params.iter().for_each(|param| self.free(param, out));
+                self.free(&ret, out);
+            }
+            Type::Opt(ty) => self.free(&ty, out),
+            _ => {}
+        }
+    }
+
+    // The scheme of `ty` where the variables no name in scope is typed with
+    // stand for any type
+    fn generalize(&self, ty: &Type) -> Scheme {
+        let mut bound = BTreeSet::new();
+        for scheme in self.scopes.iter().flat_map(|scope| scope.values()) {
+            let mut free = BTreeSet::new();
+            self.free(&scheme.ty, &mut free);
+            bound.extend(
+                free.difference(&scheme.vars.iter().copied().collect())
+                    .copied(),
+            );
+        }
+        for ty in self.pending.iter().flat_map(|pending| pending.values()) {
+            self.free(ty, &mut bound);
+        }
+
+        let mut free = BTreeSet::new();
+        self.free(ty, &mut free);
+
+        Scheme {
+            vars: free.difference(&bound).copied().collect(),
+            ty: self.zonk(ty),
+        }
+    }
+
+    fn bind(&mut self, name: &str, scheme: Scheme) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), scheme);
+    }
+
+    // Binds the function `name` of type `ty` once it is defined, along with
+    // the uses of it before that
+    fn define(&mut self, name: &str, ty: &Type, span: Span) {
+        if let Some(pending) = self.pending.last_mut().unwrap().remove(name) {
+            self.scopes.last_mut().unwrap().remove(name);
+            self.expect(ty, &pending, span);
+        }
+
+        let scheme = self.generalize(ty);
+        self.bind(name, scheme);
+    }
+
+    fn push(&mut self) {
+        self.scopes.push(BTreeMap::new());
+        self.pending.push(BTreeMap::new());
+    }
+
+    fn pop(&mut self) {
+        self.scopes.pop();
+        self.pending.pop();
+    }
+
+    fn scoped(&mut self, node: &Node) -> Type {
+        self.push();
+        let ty = self.expr(node);
+        self.pop();
+        ty
+    }
+
+    // The type of the last of a sequence of expressions, the functions they
+    // define being seen by those before them
+    fn block<'a>(&mut self, nodes: impl Iterator<Item = &'a Node> + Clone) -> Type {
+        for node in nodes.clone() {
+            if let Some(name) = defines_fun(node) {
+                if self.lookup_local(name).is_none() {
+                    let var = self.fresh();
+                    self.pending
+                        .last_mut()
+                        .unwrap()
+                        .insert(name.to_string(), var.clone());
+                    self.bind(name, Scheme::mono(var));
+                }
+            }
+        }
+
+        let mut ty = Type::None;
+        for node in nodes {
+            ty = self.expr(node);
+        }
+        ty
+    }
+
+    fn lookup_local(&self, name: &str) -> Option<&Scheme> {
+        self.scopes.last().unwrap().get(name)
+    }
+
+    fn fun(&mut self, args: &LinkedList<Node>, body: &LinkedList<Node>) -> Type {
+        self.push();
+
+        let mut params = Vec::new();
+        for arg in args {
+            let var = self.fresh();
+            if let AstNode::Idt(idt) = &arg.ast {
+                self.bind(idt, Scheme::mono(var.clone()));
+            }
+            params.push(var);
+        }
+
+        let ret = self.block(body.iter());
+        self.pop();
+
+        Type::Fun(params, Box::new(ret))
+    }
+
+    fn expr(&mut self, node: &Node) -> Type {
+        match &node.ast {
+            AstNode::Expr(expr) => self.expr(expr),
+            AstNode::Paren(expr) => self.scoped(expr),
+            AstNode::Num(_) => Type::Num,
+            AstNode::Str(_) => Type::Str,
+            AstNode::Bool(_) => Type::Bool,
+            AstNode::None => Type::None,
+            AstNode::Idt(idt) => match self.lookup(idt) {
+                Some(ty) => ty,
+                None => self.fresh(),
+            },
+            AstNode::Map(map) => {
+                for val in map.values() {
+                    self.scoped(val);
+                }
+                Type::Map
+            }
+            AstNode::Let(idt, expr) => {
+                let ty = self.scoped(expr);
+                self.define(idt, &ty, expr.span);
+                ty
+            }
+            AstNode::Fun(name, args, body) => {
+                let ty = self.fun(args, body);
+                self.define(name, &ty, node.span);
+                ty
+            }
+            AstNode::Lambda(args, body) => self.fun(args, body),
+            AstNode::Funcall(name, params) if name == "wrap" || name == "unwrap" => {
+                self.brand_call(name, params)
+            }
+            AstNode::Funcall(name, params) => {
+                let args = params
+                    .iter()
+                    .map(|param| (self.scoped(param), param.span))
+                    .collect();
+                self.call(name, args, node.span)
+            }
+            AstNode::Call(callee, params) => {
+                let fun = self.scoped(callee);
+                let args = params
+                    .iter()
+                    .map(|param| (self.scoped(param), param.span))
+                    .collect();
+                self.apply(fun, args, node.span, None)
+            }
+            AstNode::Pipe(head, stages) => {
+                let ty = self.scoped(head);
+                self.stages(ty, head.span, stages)
+            }
+            AstNode::BinAdd(lhs, _, rhs)
+            | AstNode::BinMul(lhs, _, rhs)
+            | AstNode::BinPow(lhs, _, rhs) => {
+                let (a, b) = (self.scoped(lhs), self.scoped(rhs));
+                if !(self.numeric(&a, lhs.span) & self.numeric(&b, rhs.span)) {
+                    return Type::Num;
+                }
+                self.expect(&b, &a, rhs.span);
+
+                // nothing tells values used only with arithmetic apart from
+                // numbers, they are taken to be numbers rather than branded
+                if let Type::Var(_) = self.resolve(&a) {
+                    self.unify(&a, &Type::Num);
+                }
+                a
+            }
+            AstNode::BinCmp(lhs, op, rhs) => {
+                let (a, b) = (self.scoped(lhs), self.scoped(rhs));
+                if !matches!(op.ast, AstNode::Eq | AstNode::Ne) {
+                    self.ordered(&a, lhs.span);
+                    self.ordered(&b, rhs.span);
+                }
+                self.expect(&b, &a, rhs.span);
+                Type::Bool
+            }
+            AstNode::BinAnd(lhs, _, rhs) | AstNode::BinOr(lhs, _, rhs) => {
+                for expr in [lhs, rhs] {
+                    let ty = self.scoped(expr);
+                    self.expect(&ty, &Type::Bool, expr.span);
+                }
+                Type::Bool
+            }
+            AstNode::Not(expr) => {
+                let ty = self.scoped(expr);
+                self.expect(&ty, &Type::Bool, expr.span);
+                Type::Bool
+            }
+            AstNode::Match(expr, cases) => self.matches(expr, cases),
+            AstNode::Type(name, variants) => {
+                self.declare(name, variants);
+                Type::None
+            }
+            AstNode::Brand(name, base) => {
+                let ty = match base.as_str() {
+                    "Num" => Type::Num,
+                    "Str" => Type::Str,
+                    "Bool" => Type::Bool,
+                    "Map" => Type::Map,
+                    // the functions of a brand may take any parameters
+                    "Fun" => self.fresh(),
+                    _ => {
+                        let msg = format!(
+                            "Error: brand {} cannot wrap {}, only Num, Str, Bool, Map, Fun",
+                            name, base
+                        );
+                        self.err(msg, node.span);
+                        self.fresh()
+                    }
+                };
+                self.brands.insert(name.clone(), ty);
+                Type::None
+            }
+            _ => self.fresh(),
+        }
+    }
+
+    // Whether arithmetic can be done on values of type `ty`, reporting when not
+    fn numeric(&mut self, ty: &Type, span: Span) -> bool {
+        match self.resolve(ty) {
+            Type::Num | Type::Var(_) => true,
+            Type::Brand(brand) if self.brands.get(&brand) == Some(&Type::Num) => true,
+            ty => {
+                self.expect(&ty, &Type::Num, span);
+                false
+            }
+        }
+    }
+
+    // Reports a type that cannot be ordered
+    fn ordered(&mut self, ty: &Type, span: Span) {
+        match self.resolve(ty) {
+            Type::Num | Type::Str | Type::Brand(_) | Type::Var(_) => {}
+            ty => {
+                let msg = format!("Error: cannot order values of type {}", self.show(&ty));
+                self.err(msg, span);
+            }
+        }
+    }
+
+    // Binds the constructors of the variants of a type, whose fields may be
+    // of any type
+    fn declare(&mut self, name: &str, variants: &LinkedList<Node>) {
+        for variant in variants {
+            let (tag, fields) = match &variant.ast {
+                AstNode::Variant(tag, fields) => (tag, fields),
+                _ => continue,
+            };
+
+            self.tags
+                .insert(tag.clone(), (name.to_string(), fields.len()));
+
+            let data = Type::Data(name.to_string());
+            let scheme = if fields.is_empty() {
+                Scheme::mono(data)
+            } else {
+                let params: Vec<_> = fields.iter().map(|_| self.fresh()).collect();
+                let vars = params
+                    .iter()
+                    .filter_map(|param| match param {
+                        Type::Var(var) => Some(*var),
+                        _ => None,
+                    })
+                    .collect();
+                Scheme {
+                    vars,
+                    ty: Type::Fun(params, Box::new(data)),
+                }
+            };
+            self.bind(tag, scheme);
+        }
+    }
+
+    // The type of a call of the function bound to `name` with parameters of
+    // the types in `args`
+    fn call(&mut self, name: &str, args: Vec<(Type, Span)>, span: Span) -> Type {
+        // builtins come before the functions of the program
+        match name {
+            "put" => return Type::None,
+            "wrap" | "unwrap" => return self.fresh(),
+            _ => {}
+        }
+
+        match self.lookup(name) {
+            Some(fun) => self.apply(fun, args, span, Some(name)),
+            None => self.fresh(),
+        }
+    }
+
+    // The type of a call of `wrap` or `unwrap` given a brand by its name,
+    // without which nothing tells what it gives
+    fn brand_call(&mut self, name: &str, params: &LinkedList<Node>) -> Type {
+        let args: Vec<_> = params
+            .iter()
+            .map(|param| (self.scoped(param), param.span))
+            .collect();
+
+        let brand = match params.front().map(Node::unwrap_expr) {
+            Some(Node {
+                ast: AstNode::Idt(brand),
+                ..
+            }) if args.len() == 2 => brand,
+            _ => return self.fresh(),
+        };
+        let base = match self.brands.get(brand) {
+            Some(base) => base.clone(),
+            None => return self.fresh(),
+        };
+
+        let (val, span) = &args[1];
+        if name == "wrap" {
+            self.expect(val, &base, *span);
+            Type::Brand(brand.clone())
+        } else {
+            self.expect(val, &Type::Brand(brand.clone()), *span);
+            base
+        }
+    }
+
+    fn apply(
+        &mut self,
+        fun: Type,
+        args: Vec<(Type, Span)>,
+        span: Span,
+        name: Option<&str>,
+    ) -> Type {
+        match self.resolve(&fun) {
+            Type::Fun(params, ret) => {
+                if args.len() > params.len() {
+                    let msg = format!(
+                        "Error: {} takes {} argument{}, got {}",
+                        match name {
+                            Some(name) => format!("function {}", name),
+                            None => "anonymous function".to_string(),
+                        },
+                        params.len(),
+                        if params.len() == 1 { "" } else { "s" },
+                        args.len()
+                    );
+                    self.err(msg, span);
+                    return self.fresh();
+                }
+
+                for ((arg, span), param) in args.iter().zip(&params) {
+                    self.expect(arg, param, *span);
+                }
+
+                // calls with fewer parameters give a function of the others
+                match &params[args.len()..] {
+                    [] => *ret,
+                    rest => Type::Fun(rest.to_vec(), ret),
+                }
+            }
+            Type::Var(_) => {
+                let ret = self.fresh();
+                let params = args.into_iter().map(|(arg, _)| arg).collect();
+                self.expect(&fun, &Type::Fun(params, Box::new(ret.clone())), span);
+                ret
+            }
+            ty => {
+                let msg = format!("Error: {} is not a function", self.show(&ty));
+                self.err(msg, span);
+                self.fresh()
+            }
+        }
+    }
+
+    // The type of the output of a pipeline taking a value of type `ty`
+    fn stages(&mut self, ty: Type, span: Span, stages: &LinkedList<Node>) -> Type {
+        let mut ty = ty;
+        let mut span = span;
+
+        for stage in stages {
+            ty = match &stage.ast {
+                AstNode::Idt(idt) => self.call(idt, vec![(ty, span)], stage.span),
+                AstNode::Funcall(idt, params) => {
+                    let mut args = vec![(ty, span)];
+                    for param in params {
+                        args.push((self.scoped(param), param.span));
+                    }
+                    self.call(idt, args, stage.span)
+                }
+                AstNode::Lambda(args, body) => {
+                    let fun = self.fun(args, body);
+                    self.apply(fun, vec![(ty, span)], stage.span, None)
+                }
+                AstNode::Each(stages) => {
+                    self.expect(&ty, &Type::Map, span);
+                    let item = self.fresh();
+                    self.stages(item, stage.span, stages);
+                    Type::Map
+                }
+                AstNode::Fork(arms) => {
+                    for stages in arms.values() {
+                        self.stages(ty.clone(), span, stages);
+                    }
+                    Type::Map
+                }
+                _ => self.fresh(),
+            };
+            span = stage.span;
+        }
+
+        ty
+    }
+
+    // The type of a match. Each branch narrows the type of the value matched,
+    // and of the name holding it: the branch for `none` only sees `none` and
+    // the branches after it, like those of other patterns, never do
+    fn matches(&mut self, expr: &Node, cases: &LinkedList<Node>) -> Type {
+        let subject = match &expr.unwrap_expr().ast {
+            AstNode::Idt(idt) => Some(idt.clone()),
+            _ => None,
+        };
+        let mut rest = self.scoped(expr);
+        let mut out: Option<Type> = None;
+
+        // a value matched with `none` may be `none`, whichever branch does
+        let none = cases.iter().any(|case| match &case.ast {
+            AstNode::Branch(pat, _) => match &pat.ast {
+                AstNode::Guard(pat, _) => matches!(pat.ast, AstNode::None),
+                pat => matches!(pat, AstNode::None),
+            },
+            _ => false,
+        });
+        if let (true, Type::Var(_)) = (none, self.resolve(&rest)) {
+            let opt = Type::Opt(Box::new(self.fresh()));
+            self.unify(&rest, &opt);
+        }
+
+        for case in cases {
+            let (pat, res) = match &case.ast {
+                AstNode::Branch(pat, res) => (pat, res),
+                _ => continue,
+            };
+            let (pat, guard) = match &pat.ast {
+                AstNode::Guard(pat, guard) => (&**pat, Some(guard)),
+                _ => (&**pat, None),
+            };
+
+            // the names the pattern binds are seen by the guard and the branch
+            self.push();
+            let ty = self.pattern(pat, &rest);
+            if let Some(subject) = &subject {
+                if !binds(pat, subject) {
+                    self.bind(subject, Scheme::mono(ty));
+                }
+            }
+            if let Some(guard) = guard {
+                let cond = self.expr(guard);
+                self.expect(&cond, &Type::Bool, guard.span);
+            }
+            let res_ty = self.expr(res);
+            self.pop();
+
+            if guard.is_none() && matches!(pat.ast, AstNode::None) {
+                rest = self.some(&rest);
+            }
+
+            out = match out {
+                Some(out) => match self.join(&out, &res_ty) {
+                    Some(ty) => Some(ty),
+                    None => {
+                        let msg = format!(
+                            "Error: this branch gives {}, the branches before give {}",
+                            self.show(&res_ty),
+                            self.show(&out)
+                        );
+                        self.err(msg, res.span);
+                        Some(out)
+                    }
+                },
+                None => Some(res_ty),
+            };
+        }
+
+        out.unwrap_or(Type::None)
+    }
+
+    // Binds the names of `pat` matching values of type `ty`, giving the type
+    // of the values it matches
+    fn pattern(&mut self, pat: &Node, ty: &Type) -> Type {
+        let found = match &pat.ast {
+            AstNode::Default => return ty.clone(),
+            AstNode::Bind(idt) => {
+                self.bind(idt, Scheme::mono(ty.clone()));
+                return ty.clone();
+            }
+            AstNode::None => Type::None,
+            AstNode::Map(pats) => {
+                for pat in pats.values() {
+                    let ty = self.fresh();
+                    self.pattern(pat, &ty);
+                }
+                Type::Map
+            }
+            AstNode::Variant(tag, pats) => {
+                for pat in pats {
+                    let ty = self.fresh();
+                    self.pattern(pat, &ty);
+                }
+                match self.tags.get(tag) {
+                    Some((name, _)) => Type::Data(name.clone()),
+                    None => return self.some(ty),
+                }
+            }
+            _ => self.scoped(pat),
+        };
+
+        // only `none` matches `none`
+        let matched = match found {
+            Type::None => ty.clone(),
+            _ => self.some(ty),
+        };
+
+        if !self.fits(&found, &matched) {
+            let msg = format!(
+                "Error: this pattern matches {}, the value matched is {}",
+                self.show(&found),
+                self.show(ty)
+            );
+            self.err(msg, pat.span);
+        }
+
+        found
+    }
+
+    // How types are written in messages, the variables standing for any type
+    // are named `a`, `b` and so on
+    fn show(&self, ty: &Type) -> String {
+        let ty = self.zonk(ty);
+        let mut free = Vec::new();
+        vars(&ty, &mut free);
+        show(&ty, &free)
+    }
+}
+
+// The name of the function `node` defines, if any
+fn defines_fun(node: &Node) -> Option<&str> {
+    match &node.ast {
+        AstNode::Expr(expr) => defines_fun(expr),
+        AstNode::Fun(name, _, _) => Some(name),
+        AstNode::Let(name, expr) if is_lambda(expr) => Some(name),
+        _ => None,
+    }
+}
+
+// Whether `pat` binds `name`
+fn binds(pat: &Node, name: &str) -> bool {
+    match &pat.ast {
+        AstNode::Bind(idt) => idt == name,
+        AstNode::Map(pats) => pats.values().any(|pat| binds(pat, name)),
+        AstNode::Variant(_, pats) => pats.iter().any(|pat| binds(pat, name)),
+        _ => false,
+    }
+}
+
+fn is_lambda(node: &Node) -> bool {
+    matches!(node.unwrap_expr().ast, AstNode::Lambda(_, _))
+}
+
+// The variables of `ty` in the order they appear in
+fn vars(ty: &Type, out: &mut Vec<usize>) {
+    match ty {
+        Type::Var(var) if !out.contains(var) => out.push(*var),
+        Type::Fun(params, ret) => {
+            params.iter().for_each(|param| vars(param, out));
+            vars(ret, out);
+        }
+        Type::Opt(ty) => vars(ty, out),
+        _ => {}
+    }
+}
+
+fn show(ty: &Type, free: &[usize]) -> String {
+    match ty {
+        Type::Num => "Num".to_string(),
+        Type::Str => "Str".to_string(),
+        Type::Bool => "Bool".to_string(),
+        Type::None => "None".to_string(),
+        Type::Map => "Map".to_string(),
+        Type::Data(name) | Type::Brand(name) => name.clone(),
+        Type::Fun(params, ret) => {
+            let params: Vec<_> = params.iter().map(|param| show(param, free)).collect();
+            format!("fun({}) -> {}", params.join(", "), show(ret, free))
+        }
+        Type::Opt(ty) => match **ty {
+            Type::Fun(_, _) => format!("({})?", show(ty, free)),
+            _ => format!("{}?", show(ty, free)),
+        },
+        Type::Var(var) => {
+            let idx = free.iter().position(|other| other == var).unwrap_or(0);
+            let letter = (b'a' + (idx % 26) as u8) as char;
+            match idx / 26 {
+                0 => letter.to_string(),
+                n => format!("{}{}", letter, n),
+            }
+        }
+    }
+}
@@ -2,6 +2,15 @@ extern crate pest;
 #[macro_use]
 extern crate pest_derive;
 
+pub mod check;
+pub mod codebase;
+pub mod diagram;
 pub mod eval;
+pub mod fmt;
+pub mod infer;
+pub mod num;
 pub mod parse;
+pub mod serial;
+pub mod span;
+pub mod syntax;
 mod test;
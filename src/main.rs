@@ -1,15 +1,84 @@
-use bang::eval::{eval_prgm, RunErr, Value};
-use bang::parse::BangParser;
+use bang::check::{check, Warning};
+use bang::codebase::Codebase;
+use bang::diagram::{write_diagrams, GRAMMAR};
+use bang::eval::{eval_prgm, eval_prgm_with, show, RunErr, Value};
+use bang::fmt::format;
+use bang::infer::{infer, TypeErr};
+use bang::parse::{BangParseError, BangParser, Node};
+use bang::serial::{to_binary, to_json};
+use bang::syntax::{syntax, syntaxes};
+use std::io::Write;
+
+// Directory of the codebase, programs run from a directory holding one can
+// use the definitions stored in it
+const CODEBASE: &str = ".bang";
+
+const USAGE: &str = "Usage:
+    bang <file>                    run a program
+    bang fmt [--check] <file>...   format programs in place, or only check them
+    bang check [--deny-warnings] <file>...
+                                   report type errors, and warn about matches missing cases
+                                   or with unreachable branches, which only fail the check
+                                   with --deny-warnings
+    bang ast [--format json|binary] <file>
+                                   print the syntax tree of a program, json by default
+    bang add <file>...             store the definitions of programs in the codebase
+    bang rename <old> <new>        rename a definition of the codebase
+    bang view <syntax> <file>      print a program in another syntax: keyword, sexpr or brace
+    bang grammar-diagrams [--grammar <file>] [<dir>]
+                                   write railroad diagrams of the grammar to dir, diagrams by default";
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    let filename = args[1].clone();
 
-    let txt = std::fs::read_to_string(filename).unwrap();
+    match args.get(1).map(String::as_str) {
+        Some("fmt") => fmt(&args[2..]),
+        Some("check") => check_files(&args[2..]),
+        Some("ast") => ast(&args[2..]),
+        Some("add") => add(&args[2..]),
+        Some("rename") => rename(&args[2..]),
+        Some("view") => view(&args[2..]),
+        Some("grammar-diagrams") => grammar_diagrams(&args[2..]),
+        Some(filename) => run(filename),
+        None => {
+            println!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+// Reads and parses `filename`, printing every syntax error found in it
+fn parse_file(filename: &str) -> Option<(String, Node)> {
+    let txt = match std::fs::read_to_string(filename) {
+        Ok(txt) => txt,
+        Err(e) => {
+            println!("Error: cannot read {}: {}", filename, e);
+            return None;
+        }
+    };
+
+    let (node, errs) = BangParser::parse_recover(txt.as_str());
+
+    if !errs.is_empty() {
+        for BangParseError { msg, span, .. } in errs {
+            println!("{}\n", span.snippet(&txt, filename, &msg));
+        }
+        return None;
+    }
+
+    Some((txt, node))
+}
+
+fn run(filename: &str) {
+    let (txt, node) = match parse_file(filename) {
+        Some(parsed) => parsed,
+        None => std::process::exit(1),
+    };
 
-    let val = match BangParser::parse(txt.as_str()) {
-        Ok(node) => eval_prgm(node),
-        Err(e) => panic!("Error Parsing: {:?}", e),
+    let val = if std::path::Path::new(CODEBASE).is_dir() {
+        eval_prgm_with(node, &open_codebase())
+    } else {
+        eval_prgm(node)
     };
 
     match val {
@@ -22,8 +91,12 @@ fn main() {
         Ok(Value::Num(val)) => {
             println!("{}", val);
         }
-        Ok(Value::Fun(_, _, _)) => {
-            println!("{:?}", val);
+        Ok(Value::Fun(args, _, _)) => {
+            let args: Vec<_> = args
+                .iter()
+                .map(|arg| format(arg).trim_end().to_string())
+                .collect();
+            println!("fun({})", args.join(", "));
         }
         Ok(Value::Bool(_)) => {
             println!("{:?}", val);
@@ -31,8 +104,247 @@ fn main() {
         Ok(Value::None) => {
             println!("{:?}", val);
         }
-        Err(RunErr(msg)) => {
-            println!("{}", msg);
+        Ok(val @ Value::Data(_, _))
+        | Ok(val @ Value::Brand(_, _))
+        | Ok(val @ Value::Branded(_, _)) => {
+            println!("{}", show(&val));
+        }
+        Err(RunErr { kind, msg, span }) => {
+            let msg = format!("{} ({})", msg, kind);
+            println!("{}", span.snippet(&txt, filename, &msg));
+        }
+    }
+}
+
+fn fmt(args: &[String]) {
+    let check = args.iter().any(|arg| arg == "--check");
+    let files: Vec<_> = args.iter().filter(|arg| *arg != "--check").collect();
+
+    if files.is_empty() {
+        println!("{}", USAGE);
+        std::process::exit(2);
+    }
+
+    let mut ok = true;
+
+    for filename in files {
+        let (txt, node) = match parse_file(filename) {
+            Some(parsed) => parsed,
+            None => {
+                ok = false;
+                continue;
+            }
+        };
+
+        let formatted = format(&node);
+
+        if formatted == txt {
+            continue;
+        }
+
+        if check {
+            println!("{} is not formatted", filename);
+            ok = false;
+        } else if let Err(e) = std::fs::write(filename, formatted) {
+            println!("Error: cannot write {}: {}", filename, e);
+            ok = false;
+        }
+    }
+
+    if !ok {
+        std::process::exit(1);
+    }
+}
+
+fn check_files(args: &[String]) {
+    let deny = args.iter().any(|arg| arg == "--deny-warnings");
+    let files: Vec<_> = args
+        .iter()
+        .filter(|arg| *arg != "--deny-warnings")
+        .collect();
+
+    if files.is_empty() {
+        println!("{}", USAGE);
+        std::process::exit(2);
+    }
+
+    let mut ok = true;
+
+    for filename in files {
+        let (txt, node) = match parse_file(filename) {
+            Some(parsed) => parsed,
+            None => {
+                ok = false;
+                continue;
+            }
+        };
+
+        // type errors come first, the warnings about matches may follow from them
+        for TypeErr { msg, span } in infer(&node).1 {
+            println!("{}\n", span.snippet(&txt, filename, &msg));
+            ok = false;
+        }
+
+        // warnings only fail the check when asked to
+        for Warning { msg, span } in check(&node) {
+            println!("{}\n", span.snippet(&txt, filename, &msg));
+            ok &= !deny;
+        }
+    }
+
+    if !ok {
+        std::process::exit(1);
+    }
+}
+
+fn ast(args: &[String]) {
+    let (format, filename) = match args {
+        [filename] => ("json", filename),
+        [flag, format, filename] if flag == "--format" => (format.as_str(), filename),
+        _ => {
+            println!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let node = match parse_file(filename) {
+        Some((_, node)) => node,
+        None => std::process::exit(1),
+    };
+
+    match format {
+        "json" => print!("{}", to_json(&node)),
+        "binary" => {
+            if let Err(e) = std::io::stdout().write_all(&to_binary(&node)) {
+                println!("Error: cannot write the syntax tree: {}", e);
+                std::process::exit(1);
+            }
+        }
+        _ => {
+            println!("Error: unknown format {}, expected json or binary", format);
+            std::process::exit(2);
+        }
+    }
+}
+
+fn open_codebase() -> Codebase {
+    match Codebase::open(CODEBASE) {
+        Ok(codebase) => codebase,
+        Err(e) => {
+            println!("Error: cannot open the codebase: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn add(args: &[String]) {
+    if args.is_empty() {
+        println!("{}", USAGE);
+        std::process::exit(2);
+    }
+
+    let mut codebase = open_codebase();
+
+    for filename in args {
+        let node = match parse_file(filename) {
+            Some((_, node)) => node,
+            None => std::process::exit(1),
+        };
+
+        match codebase.add(&node) {
+            Ok(defs) => {
+                for def in defs {
+                    println!("#{} {}", &def.hash[..10], def.name);
+                }
+            }
+            Err(e) => {
+                println!("Error: cannot add {}: {}", filename, e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn rename(args: &[String]) {
+    let (old, new) = match args {
+        [old, new] => (old, new),
+        _ => {
+            println!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    match open_codebase().rename(old, new) {
+        Ok(true) => {}
+        Ok(false) => {
+            println!("Error: {} is not defined in the codebase", old);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            println!("Error: cannot rename {}: {}", old, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn view(args: &[String]) {
+    let (name, filename) = match args {
+        [name, filename] => (name, filename),
+        _ => {
+            println!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let syntax = match syntax(name) {
+        Some(syntax) => syntax,
+        None => {
+            let names: Vec<_> = syntaxes().iter().map(|syntax| syntax.name()).collect();
+            println!(
+                "Error: unknown syntax {}, expected one of {}",
+                name,
+                names.join(", ")
+            );
+            std::process::exit(2);
+        }
+    };
+
+    match parse_file(filename) {
+        Some((_, node)) => print!("{}", syntax.render(&node)),
+        None => std::process::exit(1),
+    }
+}
+
+fn grammar_diagrams(args: &[String]) {
+    let (grammar, dir) = match args {
+        [] => (None, "diagrams"),
+        [dir] => (None, dir.as_str()),
+        [flag, file] if flag == "--grammar" => (Some(file), "diagrams"),
+        [flag, file, dir] if flag == "--grammar" => (Some(file), dir.as_str()),
+        _ => {
+            println!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    // The grammar the parser is built from is embedded, a file is only read
+    // when asked for
+    let grammar = match grammar {
+        Some(file) => match std::fs::read_to_string(file) {
+            Ok(txt) => txt,
+            Err(e) => {
+                println!("Error: cannot read {}: {}", file, e);
+                std::process::exit(1);
+            }
+        },
+        None => GRAMMAR.to_string(),
+    };
+
+    match write_diagrams(&grammar, std::path::Path::new(dir)) {
+        Ok(rules) => println!("{} diagrams written to {}", rules.len(), dir),
+        Err(e) => {
+            println!("Error: cannot write the diagrams: {}", e);
+            std::process::exit(1);
         }
     }
 }
@@ -0,0 +1,175 @@
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{One, Pow, Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::fmt;
+
+// Largest result of `**`, in bits of its numerator and denominator, beyond
+// which a power is refused rather than computed for ever
+pub const MAX_POW_BITS: u64 = 1 << 20;
+
+// A number of a program: an integer of any size, promoted to an exact
+// fraction when a division does not come out even. A fraction whose
+// denominator reduces to 1 goes back to being an integer, so that every
+// number has a single form
+#[derive(Clone, PartialEq, Eq)]
+pub enum Num {
+    Int(BigInt),
+    Ratio(BigRational),
+}
+
+// Why an operation on numbers has no result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumErr {
+    DivByZero,
+    // a power whose result is over `MAX_POW_BITS`
+    TooLarge,
+    // a power that is not an integer, its result would not be exact
+    FracPower,
+}
+
+impl Num {
+    // Parses the digits of a number literal
+    pub fn parse(txt: &str) -> Option<Num> {
+        txt.parse::<BigInt>().ok().map(Num::Int)
+    }
+
+    // The canonical form of `ratio`
+    fn from_ratio(ratio: BigRational) -> Num {
+        if ratio.is_integer() {
+            Num::Int(ratio.to_integer())
+        } else {
+            Num::Ratio(ratio)
+        }
+    }
+
+    fn to_ratio(&self) -> BigRational {
+        match self {
+            Num::Int(int) => BigRational::from_integer(int.clone()),
+            Num::Ratio(ratio) => ratio.clone(),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        matches!(self, Num::Int(int) if int.is_zero())
+    }
+
+    pub fn add(&self, other: &Num) -> Num {
+        match (self, other) {
+            (Num::Int(a), Num::Int(b)) => Num::Int(a + b),
+            _ => Num::from_ratio(self.to_ratio() + other.to_ratio()),
+        }
+    }
+
+    pub fn sub(&self, other: &Num) -> Num {
+        match (self, other) {
+            (Num::Int(a), Num::Int(b)) => Num::Int(a - b),
+            _ => Num::from_ratio(self.to_ratio() - other.to_ratio()),
+        }
+    }
+
+    pub fn mul(&self, other: &Num) -> Num {
+        match (self, other) {
+            (Num::Int(a), Num::Int(b)) => Num::Int(a * b),
+            _ => Num::from_ratio(self.to_ratio() * other.to_ratio()),
+        }
+    }
+
+    pub fn div(&self, other: &Num) -> Result<Num, NumErr> {
+        if other.is_zero() {
+            return Err(NumErr::DivByZero);
+        }
+
+        Ok(Num::from_ratio(self.to_ratio() / other.to_ratio()))
+    }
+
+    // Raises the number to an integer power, a negative power `-n` being
+    // the inverse of the power `n`
+    pub fn pow(&self, exp: &Num) -> Result<Num, NumErr> {
+        let exp = match exp {
+            Num::Int(exp) => exp,
+            Num::Ratio(_) => return Err(NumErr::FracPower),
+        };
+
+        let base = self.to_ratio();
+
+        // powers of 0, 1 and -1 stay small however large the exponent
+        let res = if base.is_zero() || base.abs().is_one() {
+            if exp.is_zero() {
+                BigRational::one()
+            } else if base.is_zero() {
+                base
+            } else if base.is_one() || (exp % 2u8).is_zero() {
+                BigRational::one()
+            } else {
+                -BigRational::one()
+            }
+        } else {
+            let bits = base.numer().bits().max(base.denom().bits());
+            let n = match exp.abs().to_u64() {
+                Some(n) if n.saturating_mul(bits) <= MAX_POW_BITS => n,
+                _ => return Err(NumErr::TooLarge),
+            };
+
+            Pow::pow(base, n)
+        };
+
+        if exp.is_negative() {
+            if res.is_zero() {
+                return Err(NumErr::DivByZero);
+            }
+            Ok(Num::from_ratio(res.recip()))
+        } else {
+            Ok(Num::from_ratio(res))
+        }
+    }
+}
+
+impl From<i64> for Num {
+    fn from(int: i64) -> Num {
+        Num::Int(BigInt::from(int))
+    }
+}
+
+impl From<i32> for Num {
+    fn from(int: i32) -> Num {
+        Num::Int(BigInt::from(int))
+    }
+}
+
+impl Ord for Num {
+    fn cmp(&self, other: &Num) -> Ordering {
+        match (self, other) {
+            (Num::Int(a), Num::Int(b)) => a.cmp(b),
+            _ => self.to_ratio().cmp(&other.to_ratio()),
+        }
+    }
+}
+
+impl PartialOrd for Num {
+    fn partial_cmp(&self, other: &Num) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq<i32> for Num {
+    fn eq(&self, other: &i32) -> bool {
+        matches!(self, Num::Int(int) if *int == BigInt::from(*other))
+    }
+}
+
+// Integers print as their digits and fractions in lowest terms, as `-1/3`
+impl fmt::Display for Num {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Num::Int(int) => write!(f, "{}", int),
+            Num::Ratio(ratio) => write!(f, "{}/{}", ratio.numer(), ratio.denom()),
+        }
+    }
+}
+
+impl fmt::Debug for Num {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
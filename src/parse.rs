@@ -1,6 +1,12 @@
+use crate::span::Span;
+use pest::error::{ErrorVariant, InputLocation, LineColLocation};
 use pest::Parser;
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::collections::LinkedList;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 #[derive(Parser)]
 #[grammar = "./src/grammar.pest"]
 struct LangParser;
@@ -9,214 +15,612 @@ pub struct BangParser;
 
 #[derive(Debug, Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub enum AstNode {
-    Program(LinkedList<AstNode>),
-    Expr(Box<AstNode>),
+    Program(LinkedList<Node>),
+    Expr(Box<Node>),
+    Paren(Box<Node>),
     Idt(String),
     Num(String),
     Str(String),
-    Map(BTreeMap<AstNode, AstNode>),
+    Bool(bool),
+    Map(BTreeMap<Node, Node>),
     Add,
     Sub,
     Mul,
     Div,
     Pow,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
     Default,
-    Let(String, Box<AstNode>),
-    Fun(String, LinkedList<AstNode>, LinkedList<AstNode>),
-    Funcall(String, LinkedList<AstNode>),
-    BinAdd(Box<AstNode>, Box<AstNode>, Box<AstNode>),
-    BinMul(Box<AstNode>, Box<AstNode>, Box<AstNode>),
-    BinPow(Box<AstNode>, Box<AstNode>, Box<AstNode>),
-    Branch(Box<AstNode>, Box<AstNode>),
-    Match(Box<AstNode>, LinkedList<AstNode>),
+    Let(String, Box<Node>),
+    Fun(String, LinkedList<Node>, LinkedList<Node>),
+    Funcall(String, LinkedList<Node>),
+    // An anonymous function, with its args and body
+    Lambda(LinkedList<Node>, LinkedList<Node>),
+    // A call of whatever function an expression evaluates to, like `f(1)(2)`
+    Call(Box<Node>, LinkedList<Node>),
+    Pipe(Box<Node>, LinkedList<Node>),
+    Fork(BTreeMap<Node, LinkedList<Node>>),
+    Each(LinkedList<Node>),
+    BinAdd(Box<Node>, Box<Node>, Box<Node>),
+    BinMul(Box<Node>, Box<Node>, Box<Node>),
+    BinPow(Box<Node>, Box<Node>, Box<Node>),
+    BinCmp(Box<Node>, Box<Node>, Box<Node>),
+    BinAnd(Box<Node>, Box<Node>, Box<Node>),
+    BinOr(Box<Node>, Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Branch(Box<Node>, Box<Node>),
+    Match(Box<Node>, LinkedList<Node>),
+    // A name bound by a pattern
+    Bind(String),
+    // A pattern along with the `if` condition the branch also needs
+    Guard(Box<Node>, Box<Node>),
+    None,
+    // A type declaration, with its name and variants
+    Type(String, LinkedList<Node>),
+    // A variant of a type with its fields, or a pattern matching one with the
+    // patterns of its fields
+    Variant(String, LinkedList<Node>),
+    // A brand declaration, with the name of the brand and of the type of the
+    // values it wraps
+    Brand(String, String),
+    // A `--` comment, the bool is set when it trails code on the same line
+    Comment(String, bool),
+    Error,
     EOI,
 }
 
-fn astify(pair: pest::iterators::Pair<Rule>) -> Option<AstNode> {
-    let node = match pair.as_rule() {
+// An AstNode along with the span of source it was parsed from. Nodes compare
+// and hash by their AstNode alone, so that the same code parsed from two
+// places is still considered equal
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub ast: AstNode,
+    pub span: Span,
+}
+
+impl Node {
+    pub fn new(ast: AstNode, span: Span) -> Node {
+        Node { ast, span }
+    }
+
+    // The expression the node wraps in parentheses, if any
+    pub fn unwrap_expr(&self) -> &Node {
+        match &self.ast {
+            AstNode::Expr(expr) | AstNode::Paren(expr) => expr.unwrap_expr(),
+            _ => self,
+        }
+    }
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Node) -> bool {
+        self.ast == other.ast
+    }
+}
+
+impl Eq for Node {}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Node) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Node {
+    fn cmp(&self, other: &Node) -> Ordering {
+        self.ast.cmp(&other.ast)
+    }
+}
+
+impl Hash for Node {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.ast.hash(state);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub enum TokenKind {
+    Whitespace,
+    Comment,
+    // keywords, punctuation and the text of atomic rules such as numbers
+    Text,
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct CstToken {
+    pub kind: TokenKind,
+    pub text: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub enum CstElem {
+    Node(CstNode),
+    Token(CstToken),
+}
+
+// A lossless syntax tree, one node per grammar rule matched. Unlike the AST it
+// keeps every byte of the source in its tokens, whitespace and comments
+// included, so printing it gives back the exact text it was parsed from
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct CstNode {
+    pub rule: Rule,
+    pub span: Span,
+    pub children: Vec<CstElem>,
+}
+
+impl CstNode {
+    // Child nodes, skipping over tokens
+    pub fn nodes(&self) -> impl Iterator<Item = &CstNode> {
+        self.children.iter().filter_map(|elem| match elem {
+            CstElem::Node(node) => Some(node),
+            CstElem::Token(_) => None,
+        })
+    }
+
+    // Every token of the node in source order
+    pub fn tokens(&self) -> Vec<&CstToken> {
+        let mut tokens = Vec::new();
+        self.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn collect_tokens<'a>(&'a self, tokens: &mut Vec<&'a CstToken>) {
+        for elem in &self.children {
+            match elem {
+                CstElem::Node(node) => node.collect_tokens(tokens),
+                CstElem::Token(token) => tokens.push(token),
+            }
+        }
+    }
+
+    // The source text the node was parsed from
+    pub fn text(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for CstNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for token in self.tokens() {
+            f.write_str(&token.text)?;
+        }
+        Ok(())
+    }
+}
+
+// Start offsets of the lines of a text, to turn byte offsets into spans
+// without rescanning the text from the start for every one of them. Offsets
+// are relative to `offset`, where the part of the text being parsed starts
+pub(crate) struct Lines<'a> {
+    txt: &'a str,
+    starts: Cow<'a, [usize]>,
+    offset: usize,
+}
+
+impl<'a> Lines<'a> {
+    pub(crate) fn new(txt: &'a str) -> Lines<'a> {
+        let mut starts = vec![0];
+        starts.extend(txt.match_indices('\n').map(|(i, _)| i + 1));
+        Lines {
+            txt,
+            starts: Cow::Owned(starts),
+            offset: 0,
+        }
+    }
+
+    // The lines of the same text, for offsets relative to `offset`
+    fn at(&self, offset: usize) -> Lines<'_> {
+        Lines {
+            txt: self.txt,
+            starts: Cow::Borrowed(&self.starts),
+            offset,
+        }
+    }
+
+    pub(crate) fn span(&self, start: usize, end: usize) -> Span {
+        let (start, end) = (start + self.offset, end + self.offset);
+        let line = self.starts.partition_point(|&s| s <= start);
+        let col = self.txt[self.starts[line - 1]..start].chars().count() + 1;
+        Span::new(start, end, line, col)
+    }
+}
+
+// Rules matched as a whole, their text is kept as a single token
+fn is_atomic(rule: Rule) -> bool {
+    matches!(
+        rule,
+        Rule::idt
+            | Rule::tag
+            | Rule::num
+            | Rule::str
+            | Rule::r#true
+            | Rule::r#false
+            | Rule::none
+            | Rule::default
+            | Rule::add
+            | Rule::sub
+            | Rule::mul
+            | Rule::div
+            | Rule::pow
+            | Rule::eq
+            | Rule::ne
+            | Rule::le
+            | Rule::ge
+            | Rule::lt
+            | Rule::gt
+            | Rule::and
+            | Rule::or
+            | Rule::not
+            | Rule::EOI
+    )
+}
+
+// Splits the `start..end` text between two rules into tokens. It can only hold
+// whitespace, comments, keywords and punctuation, comments follow the COMMENT
+// rule of the grammar
+fn lex_gap(lines: &Lines, start: usize, end: usize, out: &mut Vec<CstElem>) {
+    let txt = &lines.txt[lines.offset..lines.offset + end];
+    let mut i = start;
+
+    while i < end {
+        let rest = &txt[i..];
+        let c = rest.chars().next().unwrap();
+
+        let (kind, len) = if matches!(c, ' ' | '\n' | '\t') {
+            let len = rest
+                .find(|c: char| !matches!(c, ' ' | '\n' | '\t'))
+                .unwrap_or(rest.len());
+            (TokenKind::Whitespace, len)
+        } else if let Some(body) = rest.strip_prefix("--") {
+            let len = match (body.find("--"), body.find('\n')) {
+                (Some(close), Some(nl)) if close < nl => close + 2,
+                (Some(close), None) => close + 2,
+                (_, Some(nl)) => nl,
+                (None, None) => body.len(),
+            };
+            (TokenKind::Comment, len + 2)
+        } else if c.is_ascii_alphabetic() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(rest.len());
+            (TokenKind::Text, len)
+        } else if rest.starts_with("=>") || rest.starts_with("|>") {
+            (TokenKind::Text, 2)
+        } else {
+            (TokenKind::Text, c.len_utf8())
+        };
+
+        out.push(CstElem::Token(CstToken {
+            kind,
+            text: txt[i..i + len].to_string(),
+            span: lines.span(i, i + len),
+        }));
+
+        i += len;
+    }
+}
+
+fn build_cst(pair: pest::iterators::Pair<Rule>, lines: &Lines) -> CstNode {
+    let rule = pair.as_rule();
+    let (start, end) = (pair.as_span().start(), pair.as_span().end());
+    let mut children = Vec::new();
+
+    if is_atomic(rule) {
+        if start < end {
+            children.push(CstElem::Token(CstToken {
+                kind: TokenKind::Text,
+                text: pair.as_str().to_string(),
+                span: lines.span(start, end),
+            }));
+        }
+    } else {
+        let mut pos = start;
+        for inner in pair.into_inner() {
+            let inner_start = inner.as_span().start();
+            let inner_end = inner.as_span().end();
+
+            lex_gap(lines, pos, inner_start, &mut children);
+            children.push(CstElem::Node(build_cst(inner, lines)));
+            pos = inner_end;
+        }
+        lex_gap(lines, pos, end, &mut children);
+    }
+
+    CstNode {
+        rule,
+        span: lines.span(start, end),
+        children,
+    }
+}
+
+// Folds `lhs (op rhs)*` into a left associative chain of binary nodes
+fn astify_infix(
+    cst: &CstNode,
+    bin: fn(Box<Node>, Box<Node>, Box<Node>) -> AstNode,
+) -> Option<Node> {
+    let mut inner = cst.nodes();
+    let mut lhs = astify(inner.next().unwrap())?;
+
+    while let Some(op) = inner.next() {
+        let op = astify(op)?;
+        let rhs = astify(inner.next().unwrap())?;
+        let span = lhs.span.to(&rhs.span);
+
+        lhs = Node::new(bin(Box::new(lhs), Box::new(op), Box::new(rhs)), span);
+    }
+
+    Some(lhs)
+}
+
+fn astify(cst: &CstNode) -> Option<Node> {
+    let span = cst.span;
+
+    let node = match cst.rule {
         Rule::program => {
             let mut nodes = LinkedList::new();
-            for pair in pair.into_inner() {
-                nodes.push_back(astify(pair)?);
+            for cst in cst.nodes() {
+                nodes.push_back(astify(cst)?);
             }
             AstNode::Program(nodes)
         }
-        Rule::expr => AstNode::Expr(Box::new(astify(pair.into_inner().next().unwrap())?)),
-        Rule::num => AstNode::Num(pair.as_str().to_string()),
-        Rule::idt => AstNode::Idt(pair.as_str().to_string()),
+        Rule::expr => AstNode::Expr(Box::new(astify(cst.nodes().next().unwrap())?)),
+        Rule::paren => AstNode::Paren(Box::new(astify(cst.nodes().next().unwrap())?)),
+        Rule::num => AstNode::Num(cst.text()),
+        Rule::idt => AstNode::Idt(cst.text()),
         Rule::map => {
             let mut map = BTreeMap::new();
 
-            let mut inner = pair.into_inner();
-            loop {
-                match inner.next() {
-                    Some(idt) => map.insert(astify(idt)?, astify(inner.next().unwrap())?),
-                    None => break,
-                };
+            let mut inner = cst.nodes();
+            while let Some(idt) = inner.next() {
+                map.insert(astify(idt)?, astify(inner.next().unwrap())?);
+            }
+
+            AstNode::Map(map)
+        }
+        Rule::bool => AstNode::Bool(cst.text() == "true"),
+        Rule::none => AstNode::None,
+        Rule::bind => AstNode::Bind(cst.text()),
+        Rule::typedef => {
+            let mut inner = cst.nodes();
+            let name = inner.next().unwrap().text();
+
+            let mut variants = LinkedList::new();
+            for cst in inner {
+                variants.push_back(astify(cst)?);
+            }
+
+            AstNode::Type(name, variants)
+        }
+        Rule::brand => {
+            let mut inner = cst.nodes();
+            let name = inner.next().unwrap().text();
+            let base = inner.next().unwrap().text();
+
+            AstNode::Brand(name, base)
+        }
+        Rule::variant | Rule::tagpat => {
+            let mut inner = cst.nodes();
+            let tag = inner.next().unwrap().text();
+
+            // the fields of a variant are its args, those of a pattern
+            // follow the tag
+            let mut fields = LinkedList::new();
+            for cst in inner {
+                match cst.rule {
+                    Rule::args => {
+                        for cst in cst.nodes() {
+                            fields.push_back(astify(cst)?);
+                        }
+                    }
+                    _ => fields.push_back(astify(cst)?),
+                }
             }
 
-            // dbg!(&pair.as_str().to_string());
+            AstNode::Variant(tag, fields)
+        }
+        Rule::listpat => {
+            let mut map = BTreeMap::new();
+
+            for (i, pat) in cst.nodes().enumerate() {
+                let pat = astify(pat)?;
+                map.insert(Node::new(AstNode::Num(i.to_string()), pat.span), pat);
+            }
 
             AstNode::Map(map)
         }
-        Rule::str => {
-            AstNode::Str(pair.as_str().to_string())} ,
-        Rule::list => {
+        Rule::mappat => {
             let mut map = BTreeMap::new();
 
-            let mut inner = pair.into_inner();
-            let mut i = 0;
-            loop {
-                match inner.next() {
-                    Some(val) => {
-                        map.insert(AstNode::Num(i.to_string()), astify(val)?);
-                        i += 1
-                    }
-                    None => break,
-                };
+            let mut inner = cst.nodes();
+            while let Some(idt) = inner.next() {
+                map.insert(astify(idt)?, astify(inner.next().unwrap())?);
             }
 
-            // dbg!(&pair.as_str().to_string());
+            AstNode::Map(map)
+        }
+        Rule::str => AstNode::Str(cst.text()),
+        Rule::list => {
+            let mut map = BTreeMap::new();
+
+            for (i, val) in cst.nodes().enumerate() {
+                let val = astify(val)?;
+                map.insert(Node::new(AstNode::Num(i.to_string()), val.span), val);
+            }
 
             AstNode::Map(map)
         }
         Rule::ltd => {
-            let mut inner = pair.into_inner();
-            let idt = inner.next().unwrap().as_str().to_string();
+            let mut inner = cst.nodes();
+            let idt = inner.next().unwrap().text();
             let val = Box::new(astify(inner.next().unwrap())?);
             AstNode::Let(idt, val)
         }
         Rule::fun => {
-            let mut inner = pair.into_inner();
-            let idt = inner.next().unwrap().as_str().to_string();
+            let mut inner = cst.nodes().peekable();
+            let idt = inner.next().unwrap().text();
 
             let mut args = LinkedList::new();
 
-            match inner.peek().unwrap().as_rule() {
-                Rule::args => {
-                    args = inner.next().unwrap().into_inner().fold(
-                        LinkedList::new(),
-                        |mut acc, pair| {
-                            acc.push_back(astify(pair).unwrap());
-                            acc
-                        },
-                    );
+            if inner.peek().unwrap().rule == Rule::args {
+                for cst in inner.next().unwrap().nodes() {
+                    args.push_back(astify(cst)?);
                 }
-                _ => {}
-            };
+            }
 
-            let bodies = inner.fold(LinkedList::new(), |mut acc, pair| {
-                acc.push_back(astify(pair).unwrap());
-                acc
-            });
+            let mut bodies = LinkedList::new();
+            for cst in inner {
+                bodies.push_back(astify(cst)?);
+            }
 
             AstNode::Fun(idt, args, bodies)
         }
+        Rule::lambda => {
+            let mut inner = cst.nodes().peekable();
 
+            let mut args = LinkedList::new();
+            if inner.peek().is_some_and(|cst| cst.rule == Rule::args) {
+                for cst in inner.next().unwrap().nodes() {
+                    args.push_back(astify(cst)?);
+                }
+            }
+
+            let mut bodies = LinkedList::new();
+            for cst in inner {
+                let body = astify(cst)?;
+
+                // the body of `fun x: ...` is a bare expression
+                bodies.push_back(match cst.rule {
+                    Rule::expr => body,
+                    _ => Node::new(AstNode::Expr(Box::new(body)), cst.span),
+                });
+            }
+
+            AstNode::Lambda(args, bodies)
+        }
+        Rule::call => {
+            let mut inner = cst.nodes();
+            let mut callee = astify(inner.next().unwrap())?;
+
+            for apply in inner {
+                let mut params = LinkedList::new();
+                for cst in apply.nodes() {
+                    params.push_back(astify(cst)?);
+                }
+
+                let span = callee.span.to(&apply.span);
+                callee = Node::new(AstNode::Call(Box::new(callee), params), span);
+            }
+
+            return Some(callee);
+        }
         Rule::funcall => {
-            let mut inner = pair.into_inner();
-            let idt = inner.next().unwrap().as_str().to_string();
-
-            let params =
-                inner
-                    .next()
-                    .unwrap()
-                    .into_inner()
-                    .fold(LinkedList::new(), |mut acc, pair| {
-                        acc.push_back(astify(pair).unwrap());
-                        acc
-                    });
+            let mut inner = cst.nodes();
+            let idt = inner.next().unwrap().text();
+
+            let mut params = LinkedList::new();
+            if let Some(cst) = inner.next() {
+                for cst in cst.nodes() {
+                    params.push_back(astify(cst)?);
+                }
+            }
 
             AstNode::Funcall(idt, params)
         }
-        Rule::binadd => {
-            let mut inner = pair.into_inner();
-            let lhs = astify(inner.next().unwrap());
+        Rule::pipe => {
+            let mut inner = cst.nodes();
+            let head = astify(inner.next().unwrap())?;
 
-            let mid = inner.fold((lhs, None), |acc, pair| match astify(pair) {
-                Some(AstNode::Add) => (acc.0, Some(AstNode::Add)),
-                Some(AstNode::Sub) => (acc.0, Some(AstNode::Sub)),
-                None => (None, None),
-                rhs => (
-                    Some(AstNode::BinAdd(
-                        Box::new(acc.0.unwrap()),
-                        Box::new(acc.1.unwrap()),
-                        Box::new(rhs.unwrap()),
-                    )),
-                    None,
-                ),
-            });
-
-            mid.0?
-        }
-
-        Rule::binmul => {
-            let mut inner = pair.into_inner();
-            let lhs = astify(inner.next().unwrap());
+            let mut stages = LinkedList::new();
+            for cst in inner {
+                stages.push_back(astify(cst)?);
+            }
 
-            let mid = inner.fold((lhs, None), |acc, pair| match astify(pair) {
-                Some(AstNode::Mul) => (acc.0, Some(AstNode::Mul)),
-                Some(AstNode::Div) => (acc.0, Some(AstNode::Div)),
-                None => (None, None),
-                rhs => (
-                    Some(AstNode::BinMul(
-                        Box::new(acc.0.unwrap()),
-                        Box::new(acc.1.unwrap()),
-                        Box::new(rhs.unwrap()),
-                    )),
-                    None,
-                ),
-            });
-
-            mid.0?
-        }
-        Rule::binpow => {
-            let mut inner = pair.into_inner();
-            let lhs = astify(inner.next().unwrap());
+            AstNode::Pipe(Box::new(head), stages)
+        }
+        Rule::each => {
+            let mut stages = LinkedList::new();
+            for cst in cst.nodes() {
+                stages.push_back(astify(cst)?);
+            }
+
+            AstNode::Each(stages)
+        }
+        Rule::fork => {
+            let mut arms = BTreeMap::new();
+
+            for arm in cst.nodes() {
+                let mut inner = arm.nodes();
+                let idt = astify(inner.next().unwrap())?;
+
+                let mut stages = LinkedList::new();
+                for cst in inner {
+                    stages.push_back(astify(cst)?);
+                }
+
+                arms.insert(idt, stages);
+            }
+
+            AstNode::Fork(arms)
+        }
+        Rule::binor => return astify_infix(cst, AstNode::BinOr),
+        Rule::binand => return astify_infix(cst, AstNode::BinAnd),
+        Rule::binnot => {
+            let mut inner = cst.nodes();
+            let first = inner.next().unwrap();
 
-            let mid = inner.fold((lhs, None), |acc, pair| match astify(pair) {
-                Some(AstNode::Pow) => (acc.0, Some(AstNode::Pow)),
-                None => (None, None),
-                rhs => (
-                    Some(AstNode::BinPow(
-                        Box::new(acc.0.unwrap()),
-                        Box::new(acc.1.unwrap()),
-                        Box::new(rhs.unwrap()),
-                    )),
-                    None,
-                ),
-            });
-
-            mid.0?
+            match first.rule {
+                Rule::not => AstNode::Not(Box::new(astify(inner.next().unwrap())?)),
+                _ => return astify(first),
+            }
         }
+        Rule::bincmp => return astify_infix(cst, AstNode::BinCmp),
+        Rule::binadd => return astify_infix(cst, AstNode::BinAdd),
+
+        Rule::binmul => return astify_infix(cst, AstNode::BinMul),
+        Rule::binpow => return astify_infix(cst, AstNode::BinPow),
         Rule::add => AstNode::Add,
         Rule::sub => AstNode::Sub,
         Rule::mul => AstNode::Mul,
         Rule::div => AstNode::Div,
         Rule::pow => AstNode::Pow,
+        Rule::eq => AstNode::Eq,
+        Rule::ne => AstNode::Ne,
+        Rule::lt => AstNode::Lt,
+        Rule::le => AstNode::Le,
+        Rule::gt => AstNode::Gt,
+        Rule::ge => AstNode::Ge,
+        Rule::and => AstNode::And,
+        Rule::or => AstNode::Or,
         Rule::default => AstNode::Default,
-        Rule::defbranch => {
-            let mut inner = pair.into_inner();
-            let lhs = astify(inner.next().unwrap());
-            let rhs = astify(inner.next().unwrap());
+        Rule::defbranch | Rule::branch => {
+            let mut inner = cst.nodes();
+            let mut lhs = astify(inner.next().unwrap())?;
+            let mut rhs = inner.next().unwrap();
 
-            AstNode::Branch(Box::new(lhs?), Box::new(rhs?))
-        }
-        Rule::branch => {
-            let mut inner = pair.into_inner();
-            let lhs = astify(inner.next().unwrap());
-            let rhs = astify(inner.next().unwrap());
+            if rhs.rule == Rule::guard {
+                let cond = astify(rhs.nodes().next().unwrap())?;
+                let span = lhs.span.to(&rhs.span);
+                lhs = Node::new(AstNode::Guard(Box::new(lhs), Box::new(cond)), span);
+                rhs = inner.next().unwrap();
+            }
 
-            AstNode::Branch(Box::new(lhs?), Box::new(rhs?))
+            AstNode::Branch(Box::new(lhs), Box::new(astify(rhs)?))
         }
         Rule::cond => {
-            let mut inner = pair.into_inner();
+            let mut inner = cst.nodes();
             let lhs = astify(inner.next().unwrap());
 
-            let rhss = inner.fold(LinkedList::new(), |mut acc, pair| {
-                acc.push_back(astify(pair).unwrap());
-                acc
-            });
+            let mut rhss = LinkedList::new();
+            for cst in inner {
+                rhss.push_back(astify(cst)?);
+            }
 
             AstNode::Match(Box::new(lhs?), rhss)
         }
@@ -226,15 +630,409 @@ fn astify(pair: pest::iterators::Pair<Rule>) -> Option<AstNode> {
         }
     };
 
-    Some(node)
+    Some(Node::new(node, span))
 }
 
-#[derive(Debug, Clone)]
-pub struct BangParseError(String);
+// The comments kept in the tokens of `cst`, as nodes to be attached to the AST
+fn collect_comments(cst: &CstNode) -> LinkedList<Node> {
+    let mut comments = LinkedList::new();
+    let mut line_has_code = false;
+
+    for token in cst.tokens() {
+        match token.kind {
+            TokenKind::Whitespace if token.text.contains('\n') => line_has_code = false,
+            TokenKind::Whitespace => {}
+            TokenKind::Comment => {
+                comments.push_back(Node::new(
+                    AstNode::Comment(token.text.trim_end().to_string(), line_has_code),
+                    token.span,
+                ));
+                line_has_code = true;
+            }
+            TokenKind::Text => line_has_code = true,
+        }
+    }
+
+    comments
+}
+
+// Moves the comments that start before `pos` from `comments` into `lst`
+fn take_comments(lst: &mut LinkedList<Node>, comments: &mut LinkedList<Node>, pos: usize) {
+    while comments.front().is_some_and(|node| node.span.start < pos) {
+        lst.push_back(comments.pop_front().unwrap());
+    }
+}
+
+// Places each comment in the innermost list of statements around it, that is
+// a program, a function body or the branches of a match. Comments that sit
+// inside an expression are placed after the statement holding it
+fn attach_list(lst: &mut LinkedList<Node>, comments: &mut LinkedList<Node>, end: usize) {
+    let mut out = LinkedList::new();
+
+    for mut node in std::mem::take(lst) {
+        take_comments(&mut out, comments, node.span.start);
+
+        let end = node.span.end;
+        attach_comments(&mut node, comments);
+        out.push_back(node);
+
+        take_comments(&mut out, comments, end);
+    }
+
+    take_comments(&mut out, comments, end);
+    *lst = out;
+}
+
+pub(crate) fn attach_comments(node: &mut Node, comments: &mut LinkedList<Node>) {
+    if comments
+        .front()
+        .is_none_or(|c| c.span.start >= node.span.end)
+    {
+        return;
+    }
+
+    let end = node.span.end;
+
+    match &mut node.ast {
+        AstNode::Program(lst) => {
+            // comments go before the EOI
+            let eoi = lst.pop_back();
+            attach_list(lst, comments, end);
+            lst.extend(eoi);
+        }
+        AstNode::Fun(_, _, lst) | AstNode::Lambda(_, lst) => attach_list(lst, comments, end),
+        AstNode::Match(expr, lst) => {
+            attach_comments(expr, comments);
+            attach_list(lst, comments, end);
+        }
+        AstNode::Expr(expr) | AstNode::Paren(expr) | AstNode::Not(expr) | AstNode::Let(_, expr) => {
+            attach_comments(expr, comments)
+        }
+        AstNode::Branch(lhs, rhs) | AstNode::Guard(lhs, rhs) => {
+            attach_comments(lhs, comments);
+            attach_comments(rhs, comments);
+        }
+        AstNode::BinAdd(lhs, _, rhs)
+        | AstNode::BinMul(lhs, _, rhs)
+        | AstNode::BinPow(lhs, _, rhs)
+        | AstNode::BinCmp(lhs, _, rhs)
+        | AstNode::BinAnd(lhs, _, rhs)
+        | AstNode::BinOr(lhs, _, rhs) => {
+            attach_comments(lhs, comments);
+            attach_comments(rhs, comments);
+        }
+        AstNode::Funcall(_, lst) | AstNode::Each(lst) => {
+            for node in lst.iter_mut() {
+                attach_comments(node, comments);
+            }
+        }
+        AstNode::Pipe(head, stages) | AstNode::Call(head, stages) => {
+            attach_comments(head, comments);
+            for node in stages.iter_mut() {
+                attach_comments(node, comments);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BangParseError {
+    pub msg: String,
+    pub expected: Vec<String>,
+    pub span: Span,
+}
+
+// Name of a rule as it should read in error messages
+fn rule_name(rule: &Rule) -> &'static str {
+    match rule {
+        Rule::WHITESPACE => "whitespace",
+        Rule::COMMENT => "comment",
+        Rule::program => "expression",
+        Rule::expr
+        | Rule::binor
+        | Rule::binand
+        | Rule::binnot
+        | Rule::bincmp
+        | Rule::binadd
+        | Rule::binmul
+        | Rule::binpow => "expression",
+        Rule::ltd => "let binding",
+        Rule::fun => "function",
+        Rule::lambda => "anonymous function",
+        Rule::funcall => "function call",
+        Rule::typedef => "type declaration",
+        Rule::variant => "variant",
+        Rule::brand => "brand declaration",
+        Rule::tag => "capitalized name",
+        Rule::call => "function call",
+        Rule::apply => "`(`",
+        Rule::args => "arguments",
+        Rule::params => "parameters",
+        Rule::pipe => "pipe",
+        Rule::stage => "pipe stage",
+        Rule::each => "`[` map stage",
+        Rule::fork => "`{` pipe fork",
+        Rule::forkarm => "fork arm",
+        Rule::pipeop => "`|>`",
+        Rule::cond => "match",
+        Rule::branch => "match branch",
+        Rule::defbranch => "default branch",
+        Rule::default => "`_`",
+        Rule::guard => "`if` guard",
+        Rule::pattern | Rule::pat => "pattern",
+        Rule::listpat => "list pattern",
+        Rule::mappat => "map pattern",
+        Rule::tagpat => "variant pattern",
+        Rule::bind => "identifier",
+        Rule::list => "list",
+        Rule::map => "map",
+        Rule::bool => "boolean",
+        Rule::num => "number",
+        Rule::r#true => "`true`",
+        Rule::r#false => "`false`",
+        Rule::none => "`none`",
+        Rule::idt => "identifier",
+        Rule::keyword => "keyword",
+        Rule::str => "string",
+        Rule::paren => "`(`",
+        Rule::add => "`+`",
+        Rule::sub => "`-`",
+        Rule::mul => "`*`",
+        Rule::div => "`/`",
+        Rule::pow => "`**`",
+        Rule::eq => "`==`",
+        Rule::ne => "`!=`",
+        Rule::le => "`<=`",
+        Rule::ge => "`>=`",
+        Rule::lt => "`<`",
+        Rule::gt => "`>`",
+        Rule::and => "`and`",
+        Rule::or => "`or`",
+        Rule::not => "`not`",
+        Rule::EOI => "end of input",
+    }
+}
+
+// Joins names as `a, b or c`
+fn join_names(names: &[String]) -> String {
+    match names.split_last() {
+        None => String::new(),
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+    }
+}
+
+// Describes the token starting at `pos` in `txt` for error messages
+fn found_at(txt: &str, pos: usize) -> String {
+    let rest = txt.get(pos..).unwrap_or("");
+
+    match rest.split_whitespace().next() {
+        None => "end of input".to_string(),
+        Some(word) => {
+            let word: String = word.chars().take(16).collect();
+            format!("`{}`", word)
+        }
+    }
+}
+
+impl BangParseError {
+    fn from_pest(err: pest::error::Error<Rule>, txt: &str) -> BangParseError {
+        let (start, end) = match err.location {
+            InputLocation::Pos(pos) => (pos, pos),
+            InputLocation::Span(span) => span,
+        };
+
+        let (line, col) = match err.line_col {
+            LineColLocation::Pos(pos) => pos,
+            LineColLocation::Span(pos, _) => pos,
+        };
+
+        let span = Span::new(start, end, line, col);
+
+        let names = |rules: &[Rule]| {
+            let mut names: Vec<String> = Vec::new();
+            for rule in rules {
+                let name = rule_name(rule).to_string();
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            names
+        };
+
+        let found = found_at(txt, start);
+
+        match err.variant {
+            ErrorVariant::ParsingError {
+                positives,
+                negatives,
+            } => {
+                let expected = names(&positives);
+                let unexpected = names(&negatives);
+
+                let msg = match (expected.is_empty(), unexpected.is_empty()) {
+                    (false, _) => {
+                        format!("Error: expected {}, found {}", join_names(&expected), found)
+                    }
+                    (true, false) => format!("Error: unexpected {}", join_names(&unexpected)),
+                    (true, true) => format!("Error: unexpected {}", found),
+                };
+
+                BangParseError {
+                    msg,
+                    expected,
+                    span,
+                }
+            }
+            ErrorVariant::CustomError { message } => BangParseError {
+                msg: format!("Error: {}", message),
+                expected: Vec::new(),
+                span,
+            },
+        }
+    }
+}
+
+// Byte offsets at which parsing can resume after a syntax error, these are the
+// starts of top-level `fun`, `let`, `type` and `brand` definitions and the ends
+// of top-level `end`s. The first offset is always 0 and the last the length of `txt`
+fn sync_points(txt: &str) -> Vec<usize> {
+    let mut points = vec![0];
+    let mut offset = 0;
+
+    for line in txt.split_inclusive('\n') {
+        let keyword = |kw: &str| {
+            line.starts_with(kw)
+                && !line[kw.len()..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '-')
+        };
+
+        if keyword("fun") || keyword("let") || keyword("type") || keyword("brand") {
+            points.push(offset);
+        } else if keyword("end") {
+            points.push(offset + "end".len());
+        }
+
+        offset += line.len();
+    }
+
+    points.push(txt.len());
+    points.dedup();
+    points
+}
+
+// Parses the `len` bytes of text starting at the offset of `lines` into its
+// lossless syntax tree, spans being relative to the whole text
+fn parse_cst_at(lines: &Lines, len: usize) -> Result<CstNode, BangParseError> {
+    let src = &lines.txt[lines.offset..lines.offset + len];
+    let mut pairs = LangParser::parse(Rule::program, src).map_err(|err| {
+        let mut err = BangParseError::from_pest(err, src);
+        err.span = lines.span(err.span.start, err.span.end);
+        err
+    })?;
+
+    Ok(build_cst(pairs.next().unwrap(), lines))
+}
+
+fn node_of(cst: &CstNode) -> Result<Node, BangParseError> {
+    let mut node = astify(cst).ok_or(BangParseError {
+        msg: "Error: could not build the syntax tree".to_string(),
+        expected: Vec::new(),
+        span: cst.span,
+    })?;
+
+    attach_comments(&mut node, &mut collect_comments(cst));
+
+    Ok(node)
+}
+
+// Parses the `start..end` range of the text of `lines` alone, spans staying
+// relative to all of the text
+fn parse_range(lines: &Lines, start: usize, end: usize) -> Result<Node, BangParseError> {
+    node_of(&parse_cst_at(&lines.at(start), end - start)?)
+}
 
 impl BangParser {
-    pub fn parse(txt: &str) -> Result<AstNode, BangParseError> {
-        let pre_ast = LangParser::parse(Rule::program, txt);
-        astify(pre_ast.unwrap().next().unwrap()).ok_or(BangParseError("Parse error".to_string()))
+    // Parses `txt` into its lossless syntax tree
+    pub fn parse_cst(txt: &str) -> Result<CstNode, BangParseError> {
+        parse_cst_at(&Lines::new(txt), txt.len())
+    }
+
+    pub fn parse(txt: &str) -> Result<Node, BangParseError> {
+        node_of(&BangParser::parse_cst(txt)?)
+    }
+
+    // Parses `txt` collecting every syntax error rather than stopping at the
+    // first one. Code that fails to parse is skipped up to the next top-level
+    // `fun`, `let` or `end`, and is left in the program as an `AstNode::Error`
+    pub fn parse_recover(txt: &str) -> (Node, Vec<BangParseError>) {
+        let err = match BangParser::parse(txt) {
+            Ok(node) => return (node, Vec::new()),
+            Err(err) => err,
+        };
+
+        let points = sync_points(txt);
+        let lines = Lines::new(txt);
+        let mut nodes = LinkedList::new();
+        let mut errs: Vec<BangParseError> = Vec::new();
+
+        let mut i = 0;
+        while i + 1 < points.len() {
+            let start = points[i];
+
+            // the smallest run of chunks that parses, a chunk can end early
+            // when a definition has its body unindented. Only running out of
+            // input can be fixed by more chunks, an error before the end of
+            // the run stays whatever follows it
+            let mut parsed = None;
+            for j in i + 1..points.len() {
+                match parse_range(&lines, start, points[j]) {
+                    Ok(node) => {
+                        parsed = Some((j, node));
+                        break;
+                    }
+                    Err(err) if err.span.start < start + txt[start..points[j]].trim_end().len() => {
+                        break
+                    }
+                    Err(_) => {}
+                }
+            }
+
+            if let Some((j, node)) = parsed {
+                if let AstNode::Program(lst) = node.ast {
+                    nodes.extend(lst.into_iter().filter(|node| node.ast != AstNode::EOI));
+                }
+
+                i = j;
+                continue;
+            }
+
+            let end = points[i + 1];
+            let chunk = &txt[start..end];
+            let lo = start + (chunk.len() - chunk.trim_start().len());
+            let hi = start + chunk.trim_end().len();
+
+            // running out of input is reported past the blank lines ending
+            // the chunk, it goes right after its code instead
+            let mut err = parse_range(&lines, start, end).unwrap_err();
+            if err.span.start >= hi {
+                err.span = lines.span(hi, hi);
+            }
+
+            nodes.push_back(Node::new(AstNode::Error, lines.span(lo, hi)));
+            errs.push(err);
+
+            i += 1;
+        }
+
+        if errs.is_empty() {
+            errs.push(err);
+        }
+
+        nodes.push_back(Node::new(AstNode::EOI, lines.span(txt.len(), txt.len())));
+
+        let program = Node::new(AstNode::Program(nodes), Span::new(0, txt.len(), 1, 1));
+
+        (program, errs)
     }
 }
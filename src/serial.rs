@@ -0,0 +1,1142 @@
+// Saving and loading parsed programs as data.
+//
+// A program can be written as JSON or as a compact binary encoding. Both keep
+// every node, comment and span, so that decoding an encoded program gives back
+// the exact tree that was encoded, spans included. The format is versioned by
+// `VERSION`, decoders reject any other version.
+//
+// JSON: the document is an object holding the format name, its version and
+// the root `Program` node:
+//
+//   { "format": "bang-ast", "version": 1, "program": <node> }
+//
+// A node is an object with its `kind`, its `span` as `[start, end, line, col]`
+// and the fields of that kind:
+//
+// `Program`                      `body`: nodes
+// `Expr`, `Paren`, `Not`         `expr`: node
+// `Idt`, `Num`, `Str`, `Bind`    `value`: string, as written in the source
+// `Bool`                         `value`: bool
+// `Map`                          `entries`: `[key, value]` node pairs
+// `Let`                          `name`: string, `expr`: node
+// `Fun`                          `name`: string, `args`: nodes, `body`: nodes
+// `Funcall`                      `name`: string, `params`: nodes
+// `Lambda`                       `args`: nodes, `body`: nodes
+// `Type`                         `name`: string, `variants`: nodes
+// `Variant`                      `name`: string, `fields`: nodes
+// `Brand`                        `name`: string, `base`: string
+// `Call`                         `callee`: node, `params`: nodes
+// `Pipe`                         `head`: node, `stages`: nodes
+// `Fork`                         `arms`: `[key, stages]` pairs
+// `Each`                         `stages`: nodes
+// `BinAdd`, `BinMul`, `BinPow`,  `lhs`, `op`, `rhs`: nodes
+// `BinCmp`, `BinAnd`, `BinOr`
+// `Branch`                       `pattern`: node, `expr`: node
+// `Guard`                        `pattern`: node, `guard`: node
+// `Match`                        `expr`: node, `cases`: nodes
+// `Comment`                      `text`: string, `trailing`: bool
+//
+// Operators (`Add`, `Sub`, `Mul`, `Div`, `Pow`, `Eq`, `Ne`, `Lt`, `Le`, `Gt`,
+// `Ge`, `And`, `Or`) and `Default`, `None`, `Error` and `EOI` have no fields.
+//
+// Children are of the kinds the parser gives them: the `args` of `Fun` and
+// `Lambda` and the keys of `Fork` are `Idt` nodes, the `expr` of `Match` is an
+// `Expr` and its `cases` are `Branch` or `Comment` nodes, the `variants` of
+// `Type` are `Variant` nodes and stages are `Idt`, `Funcall`, `Lambda`, `Each`
+// or `Fork` nodes. Decoders reject other trees.
+//
+// Binary: the bytes `BAST` followed by the version as one byte, then the root
+// node. Numbers are unsigned LEB128 varints. A node is its kind as one byte
+// (the index of the kind in `KINDS`), its span as four numbers and then its
+// fields in the order listed above. Strings are a length and UTF-8 bytes,
+// bools a single `0` or `1` byte, and lists of nodes or pairs a count followed
+// by the items.
+//
+// New kinds are only ever added at the end of `KINDS`, any other change to the
+// layout bumps `VERSION`.
+
+use crate::parse::{AstNode, Node};
+use crate::span::Span;
+use std::collections::{BTreeMap, LinkedList};
+
+pub const VERSION: u8 = 1;
+
+const FORMAT: &str = "bang-ast";
+const MAGIC: &[u8] = b"BAST";
+
+// Node kinds, the binary tag of a kind is its index in this list
+pub const KINDS: &[&str] = &[
+    "Program", "Expr", "Paren", "Idt", "Num", "Str", "Bool", "Map", "Add", "Sub", "Mul", "Div",
+    "Pow", "Eq", "Ne", "Lt", "Le", "Gt", "Ge", "And", "Or", "Default", "Let", "Fun", "Funcall",
+    "Pipe", "Fork", "Each", "BinAdd", "BinMul", "BinPow", "BinCmp", "BinAnd", "BinOr", "Not",
+    "Branch", "Match", "Comment", "Error", "EOI", "Lambda", "Call", "Bind", "Guard", "None",
+    "Type", "Variant", "Brand",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeErr {
+    pub msg: String,
+}
+
+impl DecodeErr {
+    fn new(msg: impl Into<String>) -> DecodeErr {
+        DecodeErr { msg: msg.into() }
+    }
+}
+
+pub(crate) fn kind(ast: &AstNode) -> &'static str {
+    match ast {
+        AstNode::Program(_) => "Program",
+        AstNode::Expr(_) => "Expr",
+        AstNode::Paren(_) => "Paren",
+        AstNode::Idt(_) => "Idt",
+        AstNode::Num(_) => "Num",
+        AstNode::Str(_) => "Str",
+        AstNode::Bool(_) => "Bool",
+        AstNode::Map(_) => "Map",
+        AstNode::Add => "Add",
+        AstNode::Sub => "Sub",
+        AstNode::Mul => "Mul",
+        AstNode::Div => "Div",
+        AstNode::Pow => "Pow",
+        AstNode::Eq => "Eq",
+        AstNode::Ne => "Ne",
+        AstNode::Lt => "Lt",
+        AstNode::Le => "Le",
+        AstNode::Gt => "Gt",
+        AstNode::Ge => "Ge",
+        AstNode::And => "And",
+        AstNode::Or => "Or",
+        AstNode::Default => "Default",
+        AstNode::Let(_, _) => "Let",
+        AstNode::Fun(_, _, _) => "Fun",
+        AstNode::Funcall(_, _) => "Funcall",
+        AstNode::Lambda(_, _) => "Lambda",
+        AstNode::Call(_, _) => "Call",
+        AstNode::Pipe(_, _) => "Pipe",
+        AstNode::Fork(_) => "Fork",
+        AstNode::Each(_) => "Each",
+        AstNode::BinAdd(_, _, _) => "BinAdd",
+        AstNode::BinMul(_, _, _) => "BinMul",
+        AstNode::BinPow(_, _, _) => "BinPow",
+        AstNode::BinCmp(_, _, _) => "BinCmp",
+        AstNode::BinAnd(_, _, _) => "BinAnd",
+        AstNode::BinOr(_, _, _) => "BinOr",
+        AstNode::Not(_) => "Not",
+        AstNode::Branch(_, _) => "Branch",
+        AstNode::Bind(_) => "Bind",
+        AstNode::Guard(_, _) => "Guard",
+        AstNode::None => "None",
+        AstNode::Type(_, _) => "Type",
+        AstNode::Variant(_, _) => "Variant",
+        AstNode::Brand(_, _) => "Brand",
+        AstNode::Match(_, _) => "Match",
+        AstNode::Comment(_, _) => "Comment",
+        AstNode::Error => "Error",
+        AstNode::EOI => "EOI",
+    }
+}
+
+// The node of a kind that has no fields
+fn leaf(kind: &str) -> Option<AstNode> {
+    let ast = match kind {
+        "Add" => AstNode::Add,
+        "Sub" => AstNode::Sub,
+        "Mul" => AstNode::Mul,
+        "Div" => AstNode::Div,
+        "Pow" => AstNode::Pow,
+        "Eq" => AstNode::Eq,
+        "Ne" => AstNode::Ne,
+        "Lt" => AstNode::Lt,
+        "Le" => AstNode::Le,
+        "Gt" => AstNode::Gt,
+        "Ge" => AstNode::Ge,
+        "And" => AstNode::And,
+        "Or" => AstNode::Or,
+        "Default" => AstNode::Default,
+        "None" => AstNode::None,
+        "Error" => AstNode::Error,
+        "EOI" => AstNode::EOI,
+        _ => return None,
+    };
+
+    Some(ast)
+}
+
+// Builds a binary operation of the given kind
+fn binop(kind: &str, lhs: Node, op: Node, rhs: Node) -> AstNode {
+    let bin = match kind {
+        "BinAdd" => AstNode::BinAdd,
+        "BinMul" => AstNode::BinMul,
+        "BinPow" => AstNode::BinPow,
+        "BinCmp" => AstNode::BinCmp,
+        "BinAnd" => AstNode::BinAnd,
+        _ => AstNode::BinOr,
+    };
+
+    bin(Box::new(lhs), Box::new(op), Box::new(rhs))
+}
+
+fn check_program(node: Node) -> Result<Node, DecodeErr> {
+    match node.ast {
+        AstNode::Program(_) => Ok(node),
+        _ => Err(DecodeErr::new(format!(
+            "Error: expected a Program at the root, found {}",
+            kind(&node.ast)
+        ))),
+    }
+}
+
+// Checks that the children of a decoded node are of the kinds the parser
+// gives them, which evaluation relies on
+fn check_shape(ast: &AstNode) -> Result<(), DecodeErr> {
+    fn expect<'a>(
+        parent: &AstNode,
+        field: &str,
+        exp: &str,
+        nodes: impl IntoIterator<Item = &'a Node>,
+        ok: fn(&AstNode) -> bool,
+    ) -> Result<(), DecodeErr> {
+        match nodes.into_iter().find(|node| !ok(&node.ast)) {
+            Some(node) => Err(DecodeErr::new(format!(
+                "Error: expected `{}` of {} node to be {}, found {}",
+                field,
+                kind(parent),
+                exp,
+                kind(&node.ast)
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    let is_idt = |ast: &AstNode| matches!(ast, AstNode::Idt(_));
+    let is_stage = |ast: &AstNode| {
+        matches!(
+            ast,
+            AstNode::Idt(_)
+                | AstNode::Funcall(_, _)
+                | AstNode::Lambda(_, _)
+                | AstNode::Each(_)
+                | AstNode::Fork(_)
+        )
+    };
+
+    match ast {
+        AstNode::Fun(_, args, _) | AstNode::Lambda(args, _) => {
+            expect(ast, "args", "Idt nodes", args, is_idt)
+        }
+        AstNode::Match(expr, cases) => {
+            expect(ast, "expr", "an Expr node", [&**expr], |ast| {
+                matches!(ast, AstNode::Expr(_))
+            })?;
+            expect(ast, "cases", "Branch nodes", cases, |ast| {
+                matches!(ast, AstNode::Branch(_, _) | AstNode::Comment(_, _))
+            })
+        }
+        AstNode::Type(_, variants) => expect(ast, "variants", "Variant nodes", variants, |ast| {
+            matches!(ast, AstNode::Variant(_, _))
+        }),
+        AstNode::Pipe(_, stages) | AstNode::Each(stages) => {
+            expect(ast, "stages", "pipe stages", stages, is_stage)
+        }
+        AstNode::Fork(arms) => {
+            expect(ast, "arms", "keyed by Idt nodes", arms.keys(), is_idt)?;
+            expect(
+                ast,
+                "arms",
+                "pipe stages",
+                arms.values().flatten(),
+                is_stage,
+            )
+        }
+        _ => Ok(()),
+    }
+}
+
+// A parsed JSON value, numbers are limited to the unsigned integers that the
+// format uses
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Bool(bool),
+    Num(u64),
+    Str(String),
+    Arr(Vec<Json>),
+    Obj(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn name(&self) -> &'static str {
+        match self {
+            Json::Bool(_) => "a bool",
+            Json::Num(_) => "a number",
+            Json::Str(_) => "a string",
+            Json::Arr(_) => "an array",
+            Json::Obj(_) => "an object",
+        }
+    }
+
+    fn write(&self, depth: usize, out: &mut String) {
+        match self {
+            Json::Bool(b) => out.push_str(&b.to_string()),
+            Json::Num(n) => out.push_str(&n.to_string()),
+            Json::Str(s) => write_str(s, out),
+            Json::Arr(items) if items.iter().all(|item| matches!(item, Json::Num(_))) => {
+                let items: Vec<_> = items
+                    .iter()
+                    .map(|item| match item {
+                        Json::Num(n) => n.to_string(),
+                        _ => unreachable!(),
+                    })
+                    .collect();
+                out.push('[');
+                out.push_str(&items.join(", "));
+                out.push(']');
+            }
+            Json::Arr(items) => {
+                if items.is_empty() {
+                    out.push_str("[]");
+                    return;
+                }
+
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    newline(depth + 1, out);
+                    item.write(depth + 1, out);
+                }
+                newline(depth, out);
+                out.push(']');
+            }
+            Json::Obj(fields) => {
+                out.push('{');
+                for (i, (key, val)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    newline(depth + 1, out);
+                    write_str(key, out);
+                    out.push_str(": ");
+                    val.write(depth + 1, out);
+                }
+                newline(depth, out);
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn newline(depth: usize, out: &mut String) {
+    out.push('\n');
+    out.push_str(&"  ".repeat(depth));
+}
+
+fn write_str(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+struct JsonParser<'a> {
+    txt: &'a str,
+    pos: usize,
+}
+
+impl JsonParser<'_> {
+    fn err<T>(&self, msg: &str) -> Result<T, DecodeErr> {
+        Err(DecodeErr::new(format!(
+            "Error: {} at byte {} of the JSON",
+            msg, self.pos
+        )))
+    }
+
+    fn skip_ws(&mut self) {
+        let rest = &self.txt[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_ws();
+        self.txt[self.pos..].chars().next()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), DecodeErr> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.err(&format!("expected `{}`", c))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, DecodeErr> {
+        match self.peek() {
+            Some('{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                if self.peek() == Some('}') {
+                    self.pos += 1;
+                    return Ok(Json::Obj(fields));
+                }
+                loop {
+                    let key = match self.value()? {
+                        Json::Str(key) => key,
+                        _ => return self.err("expected a string key"),
+                    };
+                    self.expect(':')?;
+                    fields.push((key, self.value()?));
+                    match self.peek() {
+                        Some(',') => self.pos += 1,
+                        Some('}') => {
+                            self.pos += 1;
+                            return Ok(Json::Obj(fields));
+                        }
+                        _ => return self.err("expected `,` or `}`"),
+                    }
+                }
+            }
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if self.peek() == Some(']') {
+                    self.pos += 1;
+                    return Ok(Json::Arr(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    match self.peek() {
+                        Some(',') => self.pos += 1,
+                        Some(']') => {
+                            self.pos += 1;
+                            return Ok(Json::Arr(items));
+                        }
+                        _ => return self.err("expected `,` or `]`"),
+                    }
+                }
+            }
+            Some('"') => self.string(),
+            Some('0'..='9') => {
+                let rest = &self.txt[self.pos..];
+                let len = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                match rest[..len].parse() {
+                    Ok(n) => {
+                        self.pos += len;
+                        Ok(Json::Num(n))
+                    }
+                    Err(_) => self.err("number out of range"),
+                }
+            }
+            Some('t') if self.txt[self.pos..].starts_with("true") => {
+                self.pos += 4;
+                Ok(Json::Bool(true))
+            }
+            Some('f') if self.txt[self.pos..].starts_with("false") => {
+                self.pos += 5;
+                Ok(Json::Bool(false))
+            }
+            Some(_) => self.err("unexpected character"),
+            None => self.err("unexpected end of input"),
+        }
+    }
+
+    fn string(&mut self) -> Result<Json, DecodeErr> {
+        self.pos += 1;
+        let mut out = String::new();
+
+        loop {
+            let c = match self.txt[self.pos..].chars().next() {
+                Some(c) => c,
+                None => return self.err("unterminated string"),
+            };
+            self.pos += c.len_utf8();
+
+            match c {
+                '"' => return Ok(Json::Str(out)),
+                '\\' => {
+                    let esc = self.txt[self.pos..].chars().next();
+                    self.pos += 1;
+                    match esc {
+                        Some('"') => out.push('"'),
+                        Some('\\') => out.push('\\'),
+                        Some('/') => out.push('/'),
+                        Some('n') => out.push('\n'),
+                        Some('r') => out.push('\r'),
+                        Some('t') => out.push('\t'),
+                        Some('b') => out.push('\u{8}'),
+                        Some('f') => out.push('\u{c}'),
+                        Some('u') => {
+                            let mut code = self.hex4()?;
+                            // a surrogate pair encodes a char outside the BMP
+                            if (0xd800..0xdc00).contains(&code)
+                                && self.txt[self.pos..].starts_with("\\u")
+                            {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return self.err("invalid unicode escape");
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            match char::from_u32(code) {
+                                Some(c) => out.push(c),
+                                None => return self.err("invalid unicode escape"),
+                            }
+                        }
+                        _ => return self.err("invalid escape"),
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, DecodeErr> {
+        let code = self
+            .txt
+            .get(self.pos..self.pos + 4)
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok());
+
+        match code {
+            Some(code) => {
+                self.pos += 4;
+                Ok(code)
+            }
+            None => self.err("invalid unicode escape"),
+        }
+    }
+}
+
+fn span_json(span: Span) -> Json {
+    Json::Arr(
+        [span.start, span.end, span.line, span.col]
+            .iter()
+            .map(|&n| Json::Num(n as u64))
+            .collect(),
+    )
+}
+
+fn nodes_json<'a>(nodes: impl Iterator<Item = &'a Node>) -> Json {
+    Json::Arr(nodes.map(node_json).collect())
+}
+
+fn node_json(node: &Node) -> Json {
+    let str = |s: &String| Json::Str(s.clone());
+
+    let mut fields = vec![
+        ("kind".to_string(), Json::Str(kind(&node.ast).to_string())),
+        ("span".to_string(), span_json(node.span)),
+    ];
+
+    let extra = match &node.ast {
+        AstNode::Program(body) => vec![("body", nodes_json(body.iter()))],
+        AstNode::Expr(expr) | AstNode::Paren(expr) | AstNode::Not(expr) => {
+            vec![("expr", node_json(expr))]
+        }
+        AstNode::Idt(s) | AstNode::Num(s) | AstNode::Str(s) | AstNode::Bind(s) => {
+            vec![("value", str(s))]
+        }
+        AstNode::Bool(b) => vec![("value", Json::Bool(*b))],
+        AstNode::Map(map) => {
+            let entries = map
+                .iter()
+                .map(|(key, val)| Json::Arr(vec![node_json(key), node_json(val)]))
+                .collect();
+            vec![("entries", Json::Arr(entries))]
+        }
+        AstNode::Let(name, expr) => vec![("name", str(name)), ("expr", node_json(expr))],
+        AstNode::Fun(name, args, body) => vec![
+            ("name", str(name)),
+            ("args", nodes_json(args.iter())),
+            ("body", nodes_json(body.iter())),
+        ],
+        AstNode::Funcall(name, params) => {
+            vec![("name", str(name)), ("params", nodes_json(params.iter()))]
+        }
+        AstNode::Type(name, variants) => {
+            vec![
+                ("name", str(name)),
+                ("variants", nodes_json(variants.iter())),
+            ]
+        }
+        AstNode::Variant(name, fields) => {
+            vec![("name", str(name)), ("fields", nodes_json(fields.iter()))]
+        }
+        AstNode::Brand(name, base) => vec![("name", str(name)), ("base", str(base))],
+        AstNode::Lambda(args, body) => vec![
+            ("args", nodes_json(args.iter())),
+            ("body", nodes_json(body.iter())),
+        ],
+        AstNode::Call(callee, params) => {
+            vec![
+                ("callee", node_json(callee)),
+                ("params", nodes_json(params.iter())),
+            ]
+        }
+        AstNode::Pipe(head, stages) => {
+            vec![
+                ("head", node_json(head)),
+                ("stages", nodes_json(stages.iter())),
+            ]
+        }
+        AstNode::Fork(arms) => {
+            let arms = arms
+                .iter()
+                .map(|(key, stages)| Json::Arr(vec![node_json(key), nodes_json(stages.iter())]))
+                .collect();
+            vec![("arms", Json::Arr(arms))]
+        }
+        AstNode::Each(stages) => vec![("stages", nodes_json(stages.iter()))],
+        AstNode::BinAdd(lhs, op, rhs)
+        | AstNode::BinMul(lhs, op, rhs)
+        | AstNode::BinPow(lhs, op, rhs)
+        | AstNode::BinCmp(lhs, op, rhs)
+        | AstNode::BinAnd(lhs, op, rhs)
+        | AstNode::BinOr(lhs, op, rhs) => vec![
+            ("lhs", node_json(lhs)),
+            ("op", node_json(op)),
+            ("rhs", node_json(rhs)),
+        ],
+        AstNode::Branch(pat, expr) => vec![("pattern", node_json(pat)), ("expr", node_json(expr))],
+        AstNode::Guard(pat, guard) => {
+            vec![("pattern", node_json(pat)), ("guard", node_json(guard))]
+        }
+        AstNode::Match(expr, cases) => {
+            vec![
+                ("expr", node_json(expr)),
+                ("cases", nodes_json(cases.iter())),
+            ]
+        }
+        AstNode::Comment(text, trailing) => {
+            vec![("text", str(text)), ("trailing", Json::Bool(*trailing))]
+        }
+        _ => vec![],
+    };
+
+    fields.extend(extra.into_iter().map(|(key, val)| (key.to_string(), val)));
+    Json::Obj(fields)
+}
+
+// The fields of a JSON object being decoded into a node
+struct Fields<'a> {
+    kind: &'a str,
+    fields: &'a [(String, Json)],
+}
+
+impl<'a> Fields<'a> {
+    fn get(&self, name: &str) -> Result<&'a Json, DecodeErr> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, val)| val)
+            .ok_or_else(|| {
+                DecodeErr::new(format!(
+                    "Error: {} node is missing the `{}` field",
+                    self.kind, name
+                ))
+            })
+    }
+
+    fn mismatch<T>(&self, name: &str, exp: &str, found: &Json) -> Result<T, DecodeErr> {
+        Err(DecodeErr::new(format!(
+            "Error: expected `{}` of {} node to be {}, found {}",
+            name,
+            self.kind,
+            exp,
+            found.name()
+        )))
+    }
+
+    fn str(&self, name: &str) -> Result<String, DecodeErr> {
+        match self.get(name)? {
+            Json::Str(s) => Ok(s.clone()),
+            other => self.mismatch(name, "a string", other),
+        }
+    }
+
+    fn bool(&self, name: &str) -> Result<bool, DecodeErr> {
+        match self.get(name)? {
+            Json::Bool(b) => Ok(*b),
+            other => self.mismatch(name, "a bool", other),
+        }
+    }
+
+    fn node(&self, name: &str) -> Result<Box<Node>, DecodeErr> {
+        Ok(Box::new(json_node(self.get(name)?)?))
+    }
+
+    fn array(&self, name: &str) -> Result<&'a [Json], DecodeErr> {
+        match self.get(name)? {
+            Json::Arr(items) => Ok(items),
+            other => self.mismatch(name, "an array", other),
+        }
+    }
+
+    fn nodes(&self, name: &str) -> Result<LinkedList<Node>, DecodeErr> {
+        self.array(name)?.iter().map(json_node).collect()
+    }
+
+    // An array of `[key, value]` pairs
+    fn pairs(&self, name: &str) -> Result<Vec<(&'a Json, &'a Json)>, DecodeErr> {
+        self.array(name)?
+            .iter()
+            .map(|pair| match pair {
+                Json::Arr(pair) if pair.len() == 2 => Ok((&pair[0], &pair[1])),
+                other => self.mismatch(name, "an array of pairs", other),
+            })
+            .collect()
+    }
+}
+
+fn json_span(json: &Json) -> Result<Span, DecodeErr> {
+    let nums: Vec<usize> = match json {
+        Json::Arr(items) => items
+            .iter()
+            .filter_map(|item| match item {
+                Json::Num(n) => Some(*n as usize),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    match nums[..] {
+        [start, end, line, col] => Ok(Span::new(start, end, line, col)),
+        _ => Err(DecodeErr::new(
+            "Error: expected a span of [start, end, line, col]",
+        )),
+    }
+}
+
+fn json_nodes(json: &Json) -> Result<LinkedList<Node>, DecodeErr> {
+    match json {
+        Json::Arr(items) => items.iter().map(json_node).collect(),
+        other => Err(DecodeErr::new(format!(
+            "Error: expected an array of nodes, found {}",
+            other.name()
+        ))),
+    }
+}
+
+#[allow(clippy::mutable_key_type)]
+fn json_node(json: &Json) -> Result<Node, DecodeErr> {
+    let fields = match json {
+        Json::Obj(fields) => fields,
+        other => {
+            return Err(DecodeErr::new(format!(
+                "Error: expected a node object, found {}",
+                other.name()
+            )))
+        }
+    };
+
+    let kind = match fields.iter().find(|(key, _)| key == "kind") {
+        Some((_, Json::Str(kind))) => kind.as_str(),
+        _ => return Err(DecodeErr::new("Error: node is missing its `kind`")),
+    };
+
+    let f = Fields { kind, fields };
+    let span = json_span(f.get("span")?)?;
+
+    let ast = match kind {
+        "Program" => AstNode::Program(f.nodes("body")?),
+        "Expr" => AstNode::Expr(f.node("expr")?),
+        "Paren" => AstNode::Paren(f.node("expr")?),
+        "Not" => AstNode::Not(f.node("expr")?),
+        "Idt" => AstNode::Idt(f.str("value")?),
+        "Bind" => AstNode::Bind(f.str("value")?),
+        "Num" => AstNode::Num(f.str("value")?),
+        "Str" => AstNode::Str(f.str("value")?),
+        "Bool" => AstNode::Bool(f.bool("value")?),
+        "Map" => {
+            let mut map = BTreeMap::new();
+            for (key, val) in f.pairs("entries")? {
+                map.insert(json_node(key)?, json_node(val)?);
+            }
+            AstNode::Map(map)
+        }
+        "Let" => AstNode::Let(f.str("name")?, f.node("expr")?),
+        "Fun" => AstNode::Fun(f.str("name")?, f.nodes("args")?, f.nodes("body")?),
+        "Funcall" => AstNode::Funcall(f.str("name")?, f.nodes("params")?),
+        "Type" => AstNode::Type(f.str("name")?, f.nodes("variants")?),
+        "Variant" => AstNode::Variant(f.str("name")?, f.nodes("fields")?),
+        "Brand" => AstNode::Brand(f.str("name")?, f.str("base")?),
+        "Lambda" => AstNode::Lambda(f.nodes("args")?, f.nodes("body")?),
+        "Call" => AstNode::Call(f.node("callee")?, f.nodes("params")?),
+        "Pipe" => AstNode::Pipe(f.node("head")?, f.nodes("stages")?),
+        "Fork" => {
+            let mut arms = BTreeMap::new();
+            for (key, stages) in f.pairs("arms")? {
+                arms.insert(json_node(key)?, json_nodes(stages)?);
+            }
+            AstNode::Fork(arms)
+        }
+        "Each" => AstNode::Each(f.nodes("stages")?),
+        "BinAdd" | "BinMul" | "BinPow" | "BinCmp" | "BinAnd" | "BinOr" => {
+            binop(kind, *f.node("lhs")?, *f.node("op")?, *f.node("rhs")?)
+        }
+        "Branch" => AstNode::Branch(f.node("pattern")?, f.node("expr")?),
+        "Guard" => AstNode::Guard(f.node("pattern")?, f.node("guard")?),
+        "Match" => AstNode::Match(f.node("expr")?, f.nodes("cases")?),
+        "Comment" => AstNode::Comment(f.str("text")?, f.bool("trailing")?),
+        _ => match leaf(kind) {
+            Some(ast) => ast,
+            None => {
+                return Err(DecodeErr::new(format!(
+                    "Error: unknown node kind `{}`",
+                    kind
+                )))
+            }
+        },
+    };
+
+    check_shape(&ast)?;
+    Ok(Node::new(ast, span))
+}
+
+// Encodes a program as a JSON document
+pub fn to_json(program: &Node) -> String {
+    let doc = Json::Obj(vec![
+        ("format".to_string(), Json::Str(FORMAT.to_string())),
+        ("version".to_string(), Json::Num(VERSION as u64)),
+        ("program".to_string(), node_json(program)),
+    ]);
+
+    let mut out = String::new();
+    doc.write(0, &mut out);
+    out.push('\n');
+    out
+}
+
+// Decodes a program written by `to_json`
+pub fn from_json(txt: &str) -> Result<Node, DecodeErr> {
+    let mut parser = JsonParser { txt, pos: 0 };
+    let doc = parser.value()?;
+    if parser.peek().is_some() {
+        return parser.err("unexpected text after the document");
+    }
+
+    let fields = match &doc {
+        Json::Obj(fields) => fields,
+        _ => return Err(DecodeErr::new("Error: expected a JSON object")),
+    };
+    let f = Fields {
+        kind: "the document",
+        fields,
+    };
+
+    if f.str("format")? != FORMAT {
+        return Err(DecodeErr::new(format!("Error: not a {} document", FORMAT)));
+    }
+
+    match f.get("version")? {
+        Json::Num(v) if *v == VERSION as u64 => {}
+        Json::Num(v) => {
+            return Err(DecodeErr::new(format!(
+                "Error: unsupported format version {}, expected {}",
+                v, VERSION
+            )))
+        }
+        other => return f.mismatch("version", "a number", other),
+    }
+
+    check_program(json_node(f.get("program")?)?)
+}
+
+fn write_num(mut n: usize, out: &mut Vec<u8>) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_str_bin(s: &str, out: &mut Vec<u8>) {
+    write_num(s.len(), out);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_nodes<'a>(nodes: impl ExactSizeIterator<Item = &'a Node>, out: &mut Vec<u8>) {
+    write_num(nodes.len(), out);
+    for node in nodes {
+        write_node(node, out);
+    }
+}
+
+fn write_node(node: &Node, out: &mut Vec<u8>) {
+    let tag = KINDS.iter().position(|k| *k == kind(&node.ast)).unwrap();
+    out.push(tag as u8);
+
+    for n in [
+        node.span.start,
+        node.span.end,
+        node.span.line,
+        node.span.col,
+    ] {
+        write_num(n, out);
+    }
+
+    match &node.ast {
+        AstNode::Program(body) => write_nodes(body.iter(), out),
+        AstNode::Expr(expr) | AstNode::Paren(expr) | AstNode::Not(expr) => write_node(expr, out),
+        AstNode::Idt(s) | AstNode::Num(s) | AstNode::Str(s) | AstNode::Bind(s) => {
+            write_str_bin(s, out)
+        }
+        AstNode::Bool(b) => out.push(*b as u8),
+        AstNode::Map(map) => {
+            write_num(map.len(), out);
+            for (key, val) in map {
+                write_node(key, out);
+                write_node(val, out);
+            }
+        }
+        AstNode::Let(name, expr) => {
+            write_str_bin(name, out);
+            write_node(expr, out);
+        }
+        AstNode::Fun(name, args, body) => {
+            write_str_bin(name, out);
+            write_nodes(args.iter(), out);
+            write_nodes(body.iter(), out);
+        }
+        AstNode::Funcall(name, params)
+        | AstNode::Type(name, params)
+        | AstNode::Variant(name, params) => {
+            write_str_bin(name, out);
+            write_nodes(params.iter(), out);
+        }
+        AstNode::Lambda(args, body) => {
+            write_nodes(args.iter(), out);
+            write_nodes(body.iter(), out);
+        }
+        AstNode::Call(callee, params) => {
+            write_node(callee, out);
+            write_nodes(params.iter(), out);
+        }
+        AstNode::Pipe(head, stages) => {
+            write_node(head, out);
+            write_nodes(stages.iter(), out);
+        }
+        AstNode::Fork(arms) => {
+            write_num(arms.len(), out);
+            for (key, stages) in arms {
+                write_node(key, out);
+                write_nodes(stages.iter(), out);
+            }
+        }
+        AstNode::Each(stages) => write_nodes(stages.iter(), out),
+        AstNode::BinAdd(lhs, op, rhs)
+        | AstNode::BinMul(lhs, op, rhs)
+        | AstNode::BinPow(lhs, op, rhs)
+        | AstNode::BinCmp(lhs, op, rhs)
+        | AstNode::BinAnd(lhs, op, rhs)
+        | AstNode::BinOr(lhs, op, rhs) => {
+            write_node(lhs, out);
+            write_node(op, out);
+            write_node(rhs, out);
+        }
+        AstNode::Branch(pat, expr) | AstNode::Guard(pat, expr) => {
+            write_node(pat, out);
+            write_node(expr, out);
+        }
+        AstNode::Match(expr, cases) => {
+            write_node(expr, out);
+            write_nodes(cases.iter(), out);
+        }
+        AstNode::Comment(text, trailing) => {
+            write_str_bin(text, out);
+            out.push(*trailing as u8);
+        }
+        AstNode::Brand(name, base) => {
+            write_str_bin(name, out);
+            write_str_bin(base, out);
+        }
+        _ => {}
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn err<T>(&self, msg: &str) -> Result<T, DecodeErr> {
+        Err(DecodeErr::new(format!(
+            "Error: {} at byte {} of the binary",
+            msg, self.pos
+        )))
+    }
+
+    fn byte(&mut self) -> Result<u8, DecodeErr> {
+        match self.bytes.get(self.pos) {
+            Some(&byte) => {
+                self.pos += 1;
+                Ok(byte)
+            }
+            None => self.err("unexpected end of input"),
+        }
+    }
+
+    fn num(&mut self) -> Result<usize, DecodeErr> {
+        let mut n: usize = 0;
+        let mut shift = 0;
+
+        loop {
+            let byte = self.byte()?;
+            if shift >= usize::BITS {
+                return self.err("number out of range");
+            }
+            n |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+            shift += 7;
+        }
+    }
+
+    fn bool(&mut self) -> Result<bool, DecodeErr> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => self.err("invalid bool"),
+        }
+    }
+
+    fn str(&mut self) -> Result<String, DecodeErr> {
+        let len = self.num()?;
+        let bytes = match self.bytes.get(self.pos..self.pos.saturating_add(len)) {
+            Some(bytes) => bytes,
+            None => return self.err("unexpected end of input"),
+        };
+
+        match String::from_utf8(bytes.to_vec()) {
+            Ok(s) => {
+                self.pos += len;
+                Ok(s)
+            }
+            Err(_) => self.err("invalid UTF-8 in string"),
+        }
+    }
+
+    fn node(&mut self) -> Result<Box<Node>, DecodeErr> {
+        Ok(Box::new(self.read_node()?))
+    }
+
+    fn nodes(&mut self) -> Result<LinkedList<Node>, DecodeErr> {
+        let len = self.num()?;
+        (0..len).map(|_| self.read_node()).collect()
+    }
+
+    #[allow(clippy::mutable_key_type)]
+    fn read_node(&mut self) -> Result<Node, DecodeErr> {
+        let tag = self.byte()?;
+        let kind = match KINDS.get(tag as usize) {
+            Some(kind) => *kind,
+            None => return self.err(&format!("unknown node kind {}", tag)),
+        };
+
+        let span = Span::new(self.num()?, self.num()?, self.num()?, self.num()?);
+
+        let ast = match kind {
+            "Program" => AstNode::Program(self.nodes()?),
+            "Expr" => AstNode::Expr(self.node()?),
+            "Paren" => AstNode::Paren(self.node()?),
+            "Not" => AstNode::Not(self.node()?),
+            "Idt" => AstNode::Idt(self.str()?),
+            "Bind" => AstNode::Bind(self.str()?),
+            "Num" => AstNode::Num(self.str()?),
+            "Str" => AstNode::Str(self.str()?),
+            "Bool" => AstNode::Bool(self.bool()?),
+            "Map" => {
+                let mut map = BTreeMap::new();
+                for _ in 0..self.num()? {
+                    map.insert(self.read_node()?, self.read_node()?);
+                }
+                AstNode::Map(map)
+            }
+            "Let" => AstNode::Let(self.str()?, self.node()?),
+            "Fun" => AstNode::Fun(self.str()?, self.nodes()?, self.nodes()?),
+            "Funcall" => AstNode::Funcall(self.str()?, self.nodes()?),
+            "Type" => AstNode::Type(self.str()?, self.nodes()?),
+            "Variant" => AstNode::Variant(self.str()?, self.nodes()?),
+            "Brand" => AstNode::Brand(self.str()?, self.str()?),
+            "Lambda" => AstNode::Lambda(self.nodes()?, self.nodes()?),
+            "Call" => AstNode::Call(self.node()?, self.nodes()?),
+            "Pipe" => AstNode::Pipe(self.node()?, self.nodes()?),
+            "Fork" => {
+                let mut arms = BTreeMap::new();
+                for _ in 0..self.num()? {
+                    arms.insert(self.read_node()?, self.nodes()?);
+                }
+                AstNode::Fork(arms)
+            }
+            "Each" => AstNode::Each(self.nodes()?),
+            "BinAdd" | "BinMul" | "BinPow" | "BinCmp" | "BinAnd" | "BinOr" => binop(
+                kind,
+                self.read_node()?,
+                self.read_node()?,
+                self.read_node()?,
+            ),
+            "Branch" => AstNode::Branch(self.node()?, self.node()?),
+            "Guard" => AstNode::Guard(self.node()?, self.node()?),
+            "Match" => AstNode::Match(self.node()?, self.nodes()?),
+            "Comment" => AstNode::Comment(self.str()?, self.bool()?),
+            _ => leaf(kind).unwrap(),
+        };
+
+        check_shape(&ast)?;
+        Ok(Node::new(ast, span))
+    }
+}
+
+// Encodes a program in the compact binary format
+pub fn to_binary(program: &Node) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    write_node(program, &mut out);
+    out
+}
+
+// Decodes a program written by `to_binary`
+pub fn from_binary(bytes: &[u8]) -> Result<Node, DecodeErr> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(DecodeErr::new("Error: not a bang-ast binary"));
+    }
+
+    let mut reader = Reader {
+        bytes,
+        pos: MAGIC.len(),
+    };
+
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(DecodeErr::new(format!(
+            "Error: unsupported format version {}, expected {}",
+            version, VERSION
+        )));
+    }
+
+    let node = reader.read_node()?;
+    if reader.pos != bytes.len() {
+        return reader.err("unexpected bytes after the program");
+    }
+
+    check_program(node)
+}
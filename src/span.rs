@@ -0,0 +1,73 @@
+// Location of a piece of source text, `start` and `end` are byte offsets
+// while `line` and `col` are the 1-based position of `start`
+#[derive(Debug, Clone, Copy, Default, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, col: usize) -> Span {
+        Span {
+            start,
+            end,
+            line,
+            col,
+        }
+    }
+
+    // Span covering both `self` and `other`, `other` being the later one
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+            line: self.line,
+            col: self.col,
+        }
+    }
+
+    // Renders `msg` followed by the first line of the span in `src`, with the
+    // spanned text underlined by carets:
+    //
+    //   Error: identifier x is not defined
+    //    --> sample.bang:2:5
+    //     |
+    //   2 | a + x
+    //     |     ^
+    pub fn snippet(&self, src: &str, filename: &str, msg: &str) -> String {
+        let line = src.lines().nth(self.line.saturating_sub(1)).unwrap_or("");
+        let lineno = self.line.to_string();
+        let pad = " ".repeat(lineno.len());
+
+        let col = self.col.saturating_sub(1).min(line.chars().count());
+        let width = src
+            .get(self.start..self.end)
+            .map(|txt| txt.lines().next().unwrap_or("").chars().count())
+            .unwrap_or(0)
+            .clamp(1, (line.chars().count() - col).max(1));
+
+        format!(
+            "{}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+            msg,
+            pad,
+            filename,
+            self.line,
+            self.col,
+            pad,
+            lineno,
+            line,
+            pad,
+            " ".repeat(col),
+            "^".repeat(width),
+        )
+    }
+}
+
+impl From<pest::Span<'_>> for Span {
+    fn from(span: pest::Span<'_>) -> Span {
+        let (line, col) = span.start_pos().line_col();
+        Span::new(span.start(), span.end(), line, col)
+    }
+}
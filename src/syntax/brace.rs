@@ -0,0 +1,940 @@
+// Brace, C-like syntax. Blocks are wrapped in braces, statements end with `;`
+// and match branches with `,`. `and`, `or` and `not` are `&&`, `||` and `!`
+//
+//   fn fact(n) {
+//     match n {
+//       0 => 1,
+//       _ => n * fact(n - 1),
+//     }
+//   }
+//
+// Anonymous functions are `fn(x) { x + 1; }`, and calls can follow any
+// parenthesized expression or call, as in `adder(1)(2)`. Types are declared
+// as in the keyword syntax, `type Shape = Circle(radius) | Empty;`, and so
+// are brands, `brand UserId = Num;`.
+//
+// Lists are written `[1, 2]` so that a `[` stage and a `{` fork only follow a
+// `|>`. Comments are `// ...` to the end of the line, or `/* ... */` for the
+// closed `-- ... --` form
+
+use super::{finish, parse_err, render_block, render_program, trails_code, Syntax};
+use crate::fmt::fmt_op;
+use crate::parse::{AstNode, BangParseError, Lines, Node};
+use crate::span::Span;
+use std::collections::{BTreeMap, LinkedList};
+
+pub struct Brace;
+
+impl Syntax for Brace {
+    fn name(&self) -> &'static str {
+        "brace"
+    }
+
+    fn render(&self, program: &Node) -> String {
+        render_program(program, &stmt, &comment)
+    }
+
+    fn parse(&self, txt: &str) -> Result<Node, BangParseError> {
+        let lines = Lines::new(txt);
+        let (tokens, comments) = lex(txt, &lines)?;
+
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            lines,
+            len: txt.len(),
+        };
+
+        let mut stmts = LinkedList::new();
+        while parser.peek().is_some() {
+            stmts.push_back(parser.stmt()?);
+        }
+
+        Ok(finish(txt, stmts, comments))
+    }
+}
+
+fn comment(text: &str) -> String {
+    let body = &text[2..];
+
+    match body.strip_suffix("--") {
+        Some(inner) if text.len() >= 4 => format!("/*{}*/", inner),
+        _ => format!("//{}", body),
+    }
+}
+
+fn op(node: &Node) -> &'static str {
+    match node.ast {
+        AstNode::And => "&&",
+        AstNode::Or => "||",
+        _ => fmt_op(node),
+    }
+}
+
+// A statement of a block, blocks end with their closing brace while other
+// statements end with a `;`
+fn stmt(node: &Node, depth: usize) -> String {
+    let inner = match &node.ast {
+        AstNode::Expr(expr) => expr,
+        _ => node,
+    };
+
+    match &inner.ast {
+        AstNode::Fun(_, _, _) | AstNode::Lambda(_, _) | AstNode::Match(_, _) => render(node, depth),
+        AstNode::Branch(_, _) => format!("{},", render(node, depth)),
+        _ => format!("{};", render(node, depth)),
+    }
+}
+
+fn block(header: String, lst: &LinkedList<Node>, depth: usize) -> String {
+    let mut out = header;
+    out.push_str(" {");
+    out.push_str(&render_block(lst.iter(), depth + 1, &stmt, &comment));
+    out.push('\n');
+    out.push_str(&super::indent(depth));
+    out.push('}');
+    out
+}
+
+fn render_list<'a>(nodes: impl Iterator<Item = &'a Node>, depth: usize) -> Vec<String> {
+    nodes.map(|node| render(node, depth)).collect()
+}
+
+fn render(node: &Node, depth: usize) -> String {
+    match &node.ast {
+        AstNode::Expr(expr) => render(expr, depth),
+        AstNode::Paren(expr) => format!("({})", render(expr, depth)),
+        AstNode::Not(expr) => format!("!{}", render(expr, depth)),
+        AstNode::Idt(idt) => idt.clone(),
+        AstNode::Num(num) => num.clone(),
+        AstNode::Str(str) => str.clone(),
+        AstNode::Bool(b) => b.to_string(),
+        AstNode::Default => "_".to_string(),
+        AstNode::None => "none".to_string(),
+        AstNode::Bind(idt) => idt.clone(),
+        AstNode::Guard(pat, guard) => format!("{} if {}", render(pat, depth), render(guard, depth)),
+        AstNode::Type(name, variants) => {
+            let variants = render_list(variants.iter(), depth);
+            format!("type {} = {}", name, variants.join(" | "))
+        }
+        AstNode::Brand(name, base) => format!("brand {} = {}", name, base),
+        AstNode::Variant(tag, fields) if fields.is_empty() => tag.clone(),
+        AstNode::Variant(tag, fields) => {
+            format!("{}({})", tag, render_list(fields.iter(), depth).join(", "))
+        }
+        AstNode::Comment(text, _) => comment(text),
+        AstNode::Map(map) => {
+            if map.keys().all(|key| matches!(key.ast, AstNode::Num(_))) {
+                let mut items: Vec<_> = map.iter().collect();
+                items.sort_by_key(|(key, _)| match &key.ast {
+                    AstNode::Num(num) => num.parse::<usize>().unwrap_or(0),
+                    _ => 0,
+                });
+                let items = render_list(items.into_iter().map(|(_, val)| val), depth);
+                format!("[{}]", items.join(", "))
+            } else {
+                let entries: Vec<_> = map
+                    .iter()
+                    .map(|(key, val)| format!("{}: {}", render(key, depth), render(val, depth)))
+                    .collect();
+                format!("{{ {} }}", entries.join(", "))
+            }
+        }
+        AstNode::Let(idt, expr) => format!("let {} = {}", idt, render(expr, depth)),
+        AstNode::Fun(idt, args, body) => {
+            let args = render_list(args.iter(), depth);
+            block(format!("fn {}({})", idt, args.join(", ")), body, depth)
+        }
+        AstNode::Lambda(args, body) => {
+            let args = render_list(args.iter(), depth);
+            block(format!("fn({})", args.join(", ")), body, depth)
+        }
+        AstNode::Funcall(idt, params) => {
+            format!("{}({})", idt, render_list(params.iter(), depth).join(", "))
+        }
+        AstNode::Call(callee, params) => {
+            let params = render_list(params.iter(), depth).join(", ");
+            match callee.ast {
+                AstNode::Paren(_) | AstNode::Funcall(_, _) | AstNode::Call(_, _) => {
+                    format!("{}({})", render(callee, depth), params)
+                }
+                _ => format!("({})({})", render(callee, depth), params),
+            }
+        }
+        AstNode::Pipe(head, stages) => {
+            let mut parts = vec![render(head, depth)];
+            parts.extend(render_list(stages.iter(), depth));
+            parts.join(" |> ")
+        }
+        AstNode::Each(stages) => format!("[{}]", render_list(stages.iter(), depth).join(" |> ")),
+        AstNode::Fork(arms) => {
+            let arms: Vec<_> = arms
+                .iter()
+                .map(|(key, stages)| {
+                    let stages = render_list(stages.iter(), depth).join(" |> ");
+                    format!("{}: {}", render(key, depth), stages)
+                })
+                .collect();
+            format!("{{ {} }}", arms.join(", "))
+        }
+        AstNode::BinAdd(lhs, o, rhs)
+        | AstNode::BinMul(lhs, o, rhs)
+        | AstNode::BinPow(lhs, o, rhs)
+        | AstNode::BinCmp(lhs, o, rhs)
+        | AstNode::BinAnd(lhs, o, rhs)
+        | AstNode::BinOr(lhs, o, rhs) => {
+            format!("{} {} {}", render(lhs, depth), op(o), render(rhs, depth))
+        }
+        AstNode::Match(expr, cases) => {
+            block(format!("match {}", render(expr, depth)), cases, depth)
+        }
+        AstNode::Branch(pat, expr) => format!("{} => {}", render(pat, depth), render(expr, depth)),
+        AstNode::Error => "error!()".to_string(),
+        _ => {
+            unreachable!()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Idt(String),
+    Num(String),
+    Str(String),
+    Punct(&'static str),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    span: Span,
+}
+
+// Longest first, so that `**` is not read as two `*`
+const PUNCTS: &[&str] = &[
+    "=>", "|>", "==", "!=", "<=", ">=", "**", "&&", "||", "(", ")", "{", "}", "[", "]", ",", ";",
+    ":", "=", "<", ">", "+", "-", "*", "/", "!", "_", "|",
+];
+
+fn lex(txt: &str, lines: &Lines) -> Result<(Vec<Token>, LinkedList<Node>), BangParseError> {
+    let mut tokens = Vec::new();
+    let mut comments = LinkedList::new();
+    let mut i = 0;
+
+    let letters = |s: &str| {
+        s.find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(s.len())
+    };
+
+    while i < txt.len() {
+        let rest = &txt[i..];
+        let c = rest.chars().next().unwrap();
+
+        if c.is_whitespace() {
+            i += c.len_utf8();
+            continue;
+        }
+
+        if let Some(body) = rest.strip_prefix("//") {
+            let len = body.find('\n').unwrap_or(body.len()) + 2;
+            let text = format!("--{}", &rest[2..len]);
+            comments.push_back(Node::new(
+                AstNode::Comment(text.trim_end().to_string(), trails_code(txt, i)),
+                lines.span(i, i + len),
+            ));
+            i += len;
+            continue;
+        }
+
+        if let Some(body) = rest.strip_prefix("/*") {
+            let len = match body.find("*/") {
+                Some(end) => end + 4,
+                None => {
+                    return Err(parse_err(
+                        "`*/`",
+                        "end of input",
+                        lines.span(txt.len(), txt.len()),
+                    ))
+                }
+            };
+            let text = format!("--{}--", &rest[2..len - 2]);
+            comments.push_back(Node::new(
+                AstNode::Comment(text, trails_code(txt, i)),
+                lines.span(i, i + len),
+            ));
+            i += len;
+            continue;
+        }
+
+        let (tok, len) = if c.is_ascii_alphabetic() {
+            // identifiers can hold dashes between letters, as in the grammar
+            let mut len = letters(rest);
+            while rest[len..].starts_with('-') && letters(&rest[len + 1..]) > 0 {
+                len += 1 + letters(&rest[len + 1..]);
+            }
+            (Tok::Idt(rest[..len].to_string()), len)
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            (Tok::Num(rest[..len].to_string()), len)
+        } else if c == '"' {
+            match rest[1..].find('"') {
+                Some(end) => (Tok::Str(rest[..end + 2].to_string()), end + 2),
+                None => {
+                    return Err(parse_err(
+                        "`\"`",
+                        "end of input",
+                        lines.span(txt.len(), txt.len()),
+                    ))
+                }
+            }
+        } else {
+            match PUNCTS.iter().find(|p| rest.starts_with(**p)) {
+                Some(p) => (Tok::Punct(p), p.len()),
+                None => {
+                    let span = lines.span(i, i + c.len_utf8());
+                    return Err(parse_err("token", &format!("`{}`", c), span));
+                }
+            }
+        };
+
+        tokens.push(Token {
+            tok,
+            span: lines.span(i, i + len),
+        });
+        i += len;
+    }
+
+    Ok((tokens, comments))
+}
+
+// Whether an identifier names a variant, which patterns tell from a binding by
+// its capital first letter
+fn is_tag(idt: &str) -> bool {
+    idt.starts_with(|c: char| c.is_ascii_uppercase())
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    lines: Lines<'a>,
+    len: usize,
+}
+
+type Bin = fn(Box<Node>, Box<Node>, Box<Node>) -> AstNode;
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Tok> {
+        self.tokens.get(self.pos).map(|token| &token.tok)
+    }
+
+    fn peek_at(&self, n: usize) -> Option<&Tok> {
+        self.tokens.get(self.pos + n).map(|token| &token.tok)
+    }
+
+    fn is(&self, punct: &str) -> bool {
+        matches!(self.peek(), Some(Tok::Punct(p)) if *p == punct)
+    }
+
+    fn is_kw(&self, kw: &str) -> bool {
+        matches!(self.peek(), Some(Tok::Idt(idt)) if idt == kw)
+    }
+
+    // Span of the next token, or the end of input
+    fn here(&self) -> Span {
+        match self.tokens.get(self.pos) {
+            Some(token) => token.span,
+            None => self.lines.span(self.len, self.len),
+        }
+    }
+
+    // Span from the token at `start` to the last token consumed
+    fn span_from(&self, start: usize) -> Span {
+        let first = self.tokens[start].span;
+        let last = self.tokens[self.pos - 1].span;
+        first.to(&last)
+    }
+
+    fn found(&self) -> String {
+        match self.peek() {
+            None => "end of input".to_string(),
+            Some(Tok::Idt(s)) | Some(Tok::Num(s)) | Some(Tok::Str(s)) => format!("`{}`", s),
+            Some(Tok::Punct(p)) => format!("`{}`", p),
+        }
+    }
+
+    fn err<T>(&self, expected: &str) -> Result<T, BangParseError> {
+        Err(parse_err(expected, &self.found(), self.here()))
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<Span, BangParseError> {
+        if self.is(punct) {
+            self.pos += 1;
+            Ok(self.tokens[self.pos - 1].span)
+        } else {
+            self.err(&format!("`{}`", punct))
+        }
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        let is = self.is(punct);
+        if is {
+            self.pos += 1;
+        }
+        is
+    }
+
+    fn idt(&mut self) -> Result<Node, BangParseError> {
+        match self.peek() {
+            Some(Tok::Idt(idt)) => {
+                let node = Node::new(AstNode::Idt(idt.clone()), self.here());
+                self.pos += 1;
+                Ok(node)
+            }
+            _ => self.err("identifier"),
+        }
+    }
+
+    fn wrap(node: Node) -> Node {
+        let span = node.span;
+        Node::new(AstNode::Expr(Box::new(node)), span)
+    }
+
+    fn stmt(&mut self) -> Result<Node, BangParseError> {
+        let node = Parser::wrap(self.expr()?);
+        self.eat(";");
+        Ok(node)
+    }
+
+    fn block(&mut self) -> Result<LinkedList<Node>, BangParseError> {
+        self.expect("{")?;
+        let mut stmts = LinkedList::new();
+        while !self.is("}") {
+            if self.peek().is_none() {
+                return self.err("`}`");
+            }
+            stmts.push_back(self.stmt()?);
+        }
+        self.pos += 1;
+        Ok(stmts)
+    }
+
+    fn expr(&mut self) -> Result<Node, BangParseError> {
+        let start = self.pos;
+
+        if self.is_kw("let") && matches!(self.peek_at(1), Some(Tok::Idt(_))) {
+            self.pos += 1;
+            let idt = match self.idt()?.ast {
+                AstNode::Idt(idt) => idt,
+                _ => unreachable!(),
+            };
+            self.expect("=")?;
+            let val = Parser::wrap(self.expr()?);
+            return Ok(Node::new(
+                AstNode::Let(idt, Box::new(val)),
+                self.span_from(start),
+            ));
+        }
+
+        if self.is_kw("type") && matches!(self.peek_at(1), Some(Tok::Idt(_))) {
+            return self.typedef();
+        }
+
+        if self.is_kw("brand") && matches!(self.peek_at(1), Some(Tok::Idt(_))) {
+            self.pos += 1;
+            let name = self.tag()?;
+            self.expect("=")?;
+            let base = self.tag()?;
+            return Ok(Node::new(AstNode::Brand(name, base), self.span_from(start)));
+        }
+
+        let head = if self.is("{") {
+            self.map()?
+        } else if self.is("[") {
+            self.list()?
+        } else {
+            self.binor()?
+        };
+
+        if !self.is("|>") {
+            return Ok(head);
+        }
+
+        let mut stages = LinkedList::new();
+        while self.eat("|>") {
+            stages.push_back(self.stage()?);
+        }
+
+        Ok(Node::new(
+            AstNode::Pipe(Box::new(head), stages),
+            self.span_from(start),
+        ))
+    }
+
+    fn stages(&mut self) -> Result<LinkedList<Node>, BangParseError> {
+        let mut stages = LinkedList::from([self.stage()?]);
+        while self.eat("|>") {
+            stages.push_back(self.stage()?);
+        }
+        Ok(stages)
+    }
+
+    #[allow(clippy::mutable_key_type)]
+    fn stage(&mut self) -> Result<Node, BangParseError> {
+        let start = self.pos;
+
+        if self.eat("[") {
+            let stages = self.stages()?;
+            self.expect("]")?;
+            return Ok(Node::new(AstNode::Each(stages), self.span_from(start)));
+        }
+
+        if self.eat("{") {
+            let mut arms = BTreeMap::new();
+            while !self.is("}") {
+                let key = self.idt()?;
+                self.expect(":")?;
+                arms.insert(key, self.stages()?);
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            self.expect("}")?;
+            return Ok(Node::new(AstNode::Fork(arms), self.span_from(start)));
+        }
+
+        match self.peek_at(1) {
+            Some(Tok::Punct("(")) if self.is_kw("fn") => self.lambda(),
+            Some(Tok::Punct("(")) => self.funcall(),
+            _ => self.idt(),
+        }
+    }
+
+    #[allow(clippy::mutable_key_type)]
+    fn map(&mut self) -> Result<Node, BangParseError> {
+        let start = self.pos;
+        self.expect("{")?;
+
+        let mut map = BTreeMap::new();
+        while !self.is("}") {
+            let key = self.idt()?;
+            self.expect(":")?;
+            map.insert(key, self.binor()?);
+            if !self.eat(",") {
+                break;
+            }
+        }
+        self.expect("}")?;
+
+        Ok(Node::new(AstNode::Map(map), self.span_from(start)))
+    }
+
+    #[allow(clippy::mutable_key_type)]
+    fn list(&mut self) -> Result<Node, BangParseError> {
+        let start = self.pos;
+        self.expect("[")?;
+
+        let mut map = BTreeMap::new();
+        while !self.is("]") {
+            let val = self.binor()?;
+            map.insert(
+                Node::new(AstNode::Num(map.len().to_string()), val.span),
+                val,
+            );
+            if !self.eat(",") {
+                break;
+            }
+        }
+        self.expect("]")?;
+
+        Ok(Node::new(AstNode::Map(map), self.span_from(start)))
+    }
+
+    // Folds `next (op next)*` left to right, `ops` pairing each operator
+    // token with the node it stands for
+    fn infix(
+        &mut self,
+        next: fn(&mut Self) -> Result<Node, BangParseError>,
+        ops: &[(&str, AstNode)],
+        bin: Bin,
+        once: bool,
+    ) -> Result<Node, BangParseError> {
+        let mut lhs = next(self)?;
+
+        while let Some((_, ast)) = ops.iter().find(|(punct, _)| self.is(punct)) {
+            let op = Node::new(ast.clone(), self.here());
+            self.pos += 1;
+            let rhs = next(self)?;
+            let span = lhs.span.to(&rhs.span);
+            lhs = Node::new(bin(Box::new(lhs), Box::new(op), Box::new(rhs)), span);
+
+            if once {
+                break;
+            }
+        }
+
+        Ok(lhs)
+    }
+
+    fn binor(&mut self) -> Result<Node, BangParseError> {
+        self.infix(
+            Parser::binand,
+            &[("||", AstNode::Or)],
+            AstNode::BinOr,
+            false,
+        )
+    }
+
+    fn binand(&mut self) -> Result<Node, BangParseError> {
+        self.infix(
+            Parser::binnot,
+            &[("&&", AstNode::And)],
+            AstNode::BinAnd,
+            false,
+        )
+    }
+
+    fn binnot(&mut self) -> Result<Node, BangParseError> {
+        let start = self.pos;
+
+        if self.eat("!") {
+            let expr = self.binnot()?;
+            return Ok(Node::new(
+                AstNode::Not(Box::new(expr)),
+                self.span_from(start),
+            ));
+        }
+
+        self.bincmp()
+    }
+
+    fn bincmp(&mut self) -> Result<Node, BangParseError> {
+        let ops = [
+            ("==", AstNode::Eq),
+            ("!=", AstNode::Ne),
+            ("<=", AstNode::Le),
+            (">=", AstNode::Ge),
+            ("<", AstNode::Lt),
+            (">", AstNode::Gt),
+        ];
+        self.infix(Parser::binadd, &ops, AstNode::BinCmp, true)
+    }
+
+    fn binadd(&mut self) -> Result<Node, BangParseError> {
+        let ops = [("+", AstNode::Add), ("-", AstNode::Sub)];
+        self.infix(Parser::binmul, &ops, AstNode::BinAdd, false)
+    }
+
+    fn binmul(&mut self) -> Result<Node, BangParseError> {
+        let ops = [("*", AstNode::Mul), ("/", AstNode::Div)];
+        self.infix(Parser::binpow, &ops, AstNode::BinMul, false)
+    }
+
+    fn binpow(&mut self) -> Result<Node, BangParseError> {
+        self.infix(
+            Parser::primary,
+            &[("**", AstNode::Pow)],
+            AstNode::BinPow,
+            false,
+        )
+    }
+
+    // The pattern of a match branch and its `if` guard. Only what the keyword
+    // syntax reads as a pattern is one, anything else is an expression the
+    // value is compared with
+    fn pattern(&mut self) -> Result<Node, BangParseError> {
+        let start = self.pos;
+
+        let pat = match self.pat() {
+            Some(pat) if self.is("=>") || self.is_kw("if") => pat,
+            _ => {
+                self.pos = start;
+                Parser::wrap(self.expr()?)
+            }
+        };
+
+        if !self.is_kw("if") {
+            return Ok(pat);
+        }
+
+        self.pos += 1;
+        let guard = Parser::wrap(self.expr()?);
+        Ok(Node::new(
+            AstNode::Guard(Box::new(pat), Box::new(guard)),
+            self.span_from(start),
+        ))
+    }
+
+    #[allow(clippy::mutable_key_type)]
+    fn pat(&mut self) -> Option<Node> {
+        let start = self.pos;
+        let tok = self.peek()?.clone();
+        self.pos += 1;
+
+        let ast = match tok {
+            Tok::Punct("_") => AstNode::Default,
+            Tok::Num(num) => AstNode::Num(num),
+            Tok::Str(str) => AstNode::Str(str),
+            Tok::Idt(idt) if idt == "true" || idt == "false" => AstNode::Bool(idt == "true"),
+            Tok::Idt(idt) if idt == "none" => AstNode::None,
+            Tok::Idt(tag) if is_tag(&tag) => {
+                let mut pats = LinkedList::new();
+                if self.eat("(") {
+                    while !self.is(")") {
+                        pats.push_back(self.pat()?);
+                        if !self.eat(",") {
+                            break;
+                        }
+                    }
+                    if !self.eat(")") {
+                        return None;
+                    }
+                }
+                AstNode::Variant(tag, pats)
+            }
+            Tok::Idt(idt) if idt != "if" => AstNode::Bind(idt),
+            Tok::Punct("[") => {
+                let mut map = BTreeMap::new();
+                while !self.is("]") {
+                    let pat = self.pat()?;
+                    map.insert(
+                        Node::new(AstNode::Num(map.len().to_string()), pat.span),
+                        pat,
+                    );
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                if !self.eat("]") {
+                    return None;
+                }
+                AstNode::Map(map)
+            }
+            Tok::Punct("{") => {
+                let mut map = BTreeMap::new();
+                while !self.is("}") {
+                    let key = self.idt().ok()?;
+                    if !self.eat(":") {
+                        return None;
+                    }
+                    map.insert(key, self.pat()?);
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                if map.is_empty() || !self.eat("}") {
+                    return None;
+                }
+                AstNode::Map(map)
+            }
+            _ => return None,
+        };
+
+        Some(Node::new(ast, self.span_from(start)))
+    }
+
+    fn params(&mut self) -> Result<LinkedList<Node>, BangParseError> {
+        self.expect("(")?;
+        let mut params = LinkedList::new();
+        while !self.is(")") {
+            params.push_back(Parser::wrap(self.expr()?));
+            if !self.eat(",") {
+                break;
+            }
+        }
+        self.expect(")")?;
+        Ok(params)
+    }
+
+    fn args(&mut self) -> Result<LinkedList<Node>, BangParseError> {
+        self.expect("(")?;
+        let mut args = LinkedList::new();
+        while !self.is(")") {
+            args.push_back(self.idt()?);
+            if !self.eat(",") {
+                break;
+            }
+        }
+        self.expect(")")?;
+        Ok(args)
+    }
+
+    fn typedef(&mut self) -> Result<Node, BangParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        let name = self.tag()?;
+        self.expect("=")?;
+        self.eat("|");
+
+        let mut variants = LinkedList::new();
+        loop {
+            let start = self.pos;
+            let tag = self.tag()?;
+            let fields = if self.is("(") {
+                self.args()?
+            } else {
+                LinkedList::new()
+            };
+            variants.push_back(Node::new(
+                AstNode::Variant(tag, fields),
+                self.span_from(start),
+            ));
+
+            if !self.eat("|") {
+                break;
+            }
+        }
+
+        Ok(Node::new(
+            AstNode::Type(name, variants),
+            self.span_from(start),
+        ))
+    }
+
+    fn tag(&mut self) -> Result<String, BangParseError> {
+        match self.peek() {
+            Some(Tok::Idt(tag)) if is_tag(tag) => {
+                let tag = tag.clone();
+                self.pos += 1;
+                Ok(tag)
+            }
+            _ => self.err("capitalized name"),
+        }
+    }
+
+    fn lambda(&mut self) -> Result<Node, BangParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        let args = self.args()?;
+        let body = self.block()?;
+        Ok(Node::new(
+            AstNode::Lambda(args, body),
+            self.span_from(start),
+        ))
+    }
+
+    // Calls of the function a parenthesized expression or a call gives
+    fn calls(&mut self, mut callee: Node) -> Result<Node, BangParseError> {
+        while self.is("(")
+            && matches!(
+                callee.ast,
+                AstNode::Paren(_) | AstNode::Funcall(_, _) | AstNode::Call(_, _)
+            )
+        {
+            let params = self.params()?;
+            let span = callee.span.to(&self.tokens[self.pos - 1].span);
+            callee = Node::new(AstNode::Call(Box::new(callee), params), span);
+        }
+        Ok(callee)
+    }
+
+    fn funcall(&mut self) -> Result<Node, BangParseError> {
+        let start = self.pos;
+        let idt = match self.idt()?.ast {
+            AstNode::Idt(idt) => idt,
+            _ => unreachable!(),
+        };
+
+        let params = self.params()?;
+
+        Ok(Node::new(
+            AstNode::Funcall(idt, params),
+            self.span_from(start),
+        ))
+    }
+
+    fn primary(&mut self) -> Result<Node, BangParseError> {
+        let node = self.operand()?;
+        self.calls(node)
+    }
+
+    fn operand(&mut self) -> Result<Node, BangParseError> {
+        let start = self.pos;
+
+        let tok = match self.peek() {
+            Some(tok) => tok.clone(),
+            None => return self.err("expression"),
+        };
+
+        let ast = match tok {
+            Tok::Punct("(") => {
+                self.pos += 1;
+                let expr = Parser::wrap(self.expr()?);
+                self.expect(")")?;
+                AstNode::Paren(Box::new(expr))
+            }
+            Tok::Punct("{") => return self.map(),
+            Tok::Punct("[") => return self.list(),
+            Tok::Num(num) => {
+                self.pos += 1;
+                AstNode::Num(num)
+            }
+            Tok::Str(str) => {
+                self.pos += 1;
+                AstNode::Str(str)
+            }
+            Tok::Idt(idt) if idt == "true" || idt == "false" => {
+                self.pos += 1;
+                AstNode::Bool(idt == "true")
+            }
+            Tok::Idt(idt) if idt == "none" => {
+                self.pos += 1;
+                AstNode::None
+            }
+            Tok::Idt(idt) if idt == "fn" && matches!(self.peek_at(1), Some(Tok::Idt(_))) => {
+                self.pos += 1;
+                let name = match self.idt()?.ast {
+                    AstNode::Idt(idt) => idt,
+                    _ => unreachable!(),
+                };
+
+                let args = self.args()?;
+                AstNode::Fun(name, args, self.block()?)
+            }
+            Tok::Idt(idt) if idt == "fn" && matches!(self.peek_at(1), Some(Tok::Punct("("))) => {
+                return self.lambda()
+            }
+            Tok::Idt(idt)
+                if idt == "match" && !matches!(self.peek_at(1), Some(Tok::Punct("("))) =>
+            {
+                self.pos += 1;
+                let expr = Parser::wrap(self.expr()?);
+
+                self.expect("{")?;
+                let mut cases = LinkedList::new();
+                while !self.is("}") {
+                    let case = self.pos;
+                    let pat = self.pattern()?;
+                    self.expect("=>")?;
+                    let res = Parser::wrap(self.expr()?);
+                    let branch = AstNode::Branch(Box::new(pat), Box::new(res));
+                    cases.push_back(Node::new(branch, self.span_from(case)));
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                self.expect("}")?;
+
+                AstNode::Match(Box::new(expr), cases)
+            }
+            Tok::Idt(idt) if idt == "error" && matches!(self.peek_at(1), Some(Tok::Punct("!"))) => {
+                self.pos += 1;
+                self.expect("!")?;
+                self.expect("(")?;
+                self.expect(")")?;
+                AstNode::Error
+            }
+            Tok::Idt(_) if matches!(self.peek_at(1), Some(Tok::Punct("("))) => {
+                return self.funcall()
+            }
+            Tok::Idt(idt) => {
+                self.pos += 1;
+                AstNode::Idt(idt)
+            }
+            _ => return self.err("expression"),
+        };
+
+        Ok(Node::new(ast, self.span_from(start)))
+    }
+}
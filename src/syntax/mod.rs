@@ -0,0 +1,153 @@
+// Surface syntaxes a program can be viewed and edited in. Each one renders
+// the AST into text and parses that text back into the same AST, spans aside
+//
+//   keyword   fun add(a, b) a + b end       the syntax of the grammar
+//   sexpr     (fun add (a b) (+ a b))
+//   brace     fn add(a, b) { a + b; }
+
+mod brace;
+mod sexpr;
+
+use crate::fmt::format;
+use crate::parse::{attach_comments, AstNode, BangParseError, BangParser, Node};
+use crate::span::Span;
+use std::collections::LinkedList;
+
+pub use brace::Brace;
+pub use sexpr::Sexpr;
+
+pub trait Syntax {
+    fn name(&self) -> &'static str;
+    fn render(&self, program: &Node) -> String;
+    fn parse(&self, txt: &str) -> Result<Node, BangParseError>;
+}
+
+pub struct Keyword;
+
+impl Syntax for Keyword {
+    fn name(&self) -> &'static str {
+        "keyword"
+    }
+
+    fn render(&self, program: &Node) -> String {
+        format(program)
+    }
+
+    fn parse(&self, txt: &str) -> Result<Node, BangParseError> {
+        BangParser::parse(txt)
+    }
+}
+
+pub fn syntaxes() -> Vec<Box<dyn Syntax>> {
+    vec![Box::new(Keyword), Box::new(Sexpr), Box::new(Brace)]
+}
+
+pub fn syntax(name: &str) -> Option<Box<dyn Syntax>> {
+    syntaxes().into_iter().find(|syntax| syntax.name() == name)
+}
+
+fn indent(depth: usize) -> String {
+    "  ".repeat(depth)
+}
+
+fn parse_err(expected: &str, found: &str, span: Span) -> BangParseError {
+    BangParseError {
+        msg: format!("Error: expected {}, found {}", expected, found),
+        expected: vec![expected.to_string()],
+        span,
+    }
+}
+
+// Writes the statements of a block each on its own line at `depth`, every
+// one of them starting with a newline. A trailing comment goes at the end of
+// the line before it. At the top level statements are separated by a blank
+// line, unless it follows a comment on its own line, the way `fmt` does it.
+// The first statement of a nested block comes right after its header, so a
+// trailing comment there goes at the end of the header
+fn render_block<'a>(
+    items: impl Iterator<Item = &'a Node>,
+    depth: usize,
+    item: &dyn Fn(&Node, usize) -> String,
+    comment: &dyn Fn(&str) -> String,
+) -> String {
+    let mut out = String::new();
+    let mut prev: Option<&Node> = None;
+
+    for node in items {
+        match &node.ast {
+            AstNode::EOI => continue,
+            AstNode::Comment(text, true) => {
+                out.push(' ');
+                out.push_str(&comment(text));
+            }
+            AstNode::Comment(text, _) => {
+                if depth == 0 && prev.is_some() {
+                    out.push('\n');
+                }
+                out.push('\n');
+                out.push_str(&indent(depth));
+                out.push_str(&comment(text));
+            }
+            _ => {
+                let after_comment =
+                    matches!(prev.map(|node| &node.ast), Some(AstNode::Comment(_, false)));
+                if depth == 0 && prev.is_some() && !after_comment {
+                    out.push('\n');
+                }
+                out.push('\n');
+                out.push_str(&indent(depth));
+                out.push_str(&item(node, depth));
+            }
+        }
+
+        prev = Some(node);
+    }
+
+    out
+}
+
+// Renders a whole program with `render_block`
+fn render_program(
+    program: &Node,
+    item: &dyn Fn(&Node, usize) -> String,
+    comment: &dyn Fn(&str) -> String,
+) -> String {
+    let lst = match &program.ast {
+        AstNode::Program(lst) => lst,
+        _ => return format!("{}\n", item(program, 0)),
+    };
+
+    let mut out = render_block(lst.iter(), 0, item, comment)
+        .trim_start_matches('\n')
+        .to_string();
+    out.push('\n');
+    out
+}
+
+// Whether the last statement of a block is a comment, in which case the
+// block has to be closed on a new line
+fn ends_in_comment(lst: &LinkedList<Node>) -> bool {
+    matches!(
+        lst.iter().last().map(|node| &node.ast),
+        Some(AstNode::Comment(_, _))
+    )
+}
+
+// Whether the line of `txt` up to `pos` holds anything but whitespace, which
+// makes a comment at `pos` a trailing one
+fn trails_code(txt: &str, pos: usize) -> bool {
+    let line_start = txt[..pos].rfind('\n').map_or(0, |n| n + 1);
+    !txt[line_start..pos].trim().is_empty()
+}
+
+// Builds the program node out of its statements and places the comments in
+// it, the way the keyword parser does
+fn finish(txt: &str, stmts: LinkedList<Node>, mut comments: LinkedList<Node>) -> Node {
+    let lines = crate::parse::Lines::new(txt);
+    let mut stmts = stmts;
+    stmts.push_back(Node::new(AstNode::EOI, lines.span(txt.len(), txt.len())));
+
+    let mut program = Node::new(AstNode::Program(stmts), lines.span(0, txt.len()));
+    attach_comments(&mut program, &mut comments);
+    program
+}
@@ -0,0 +1,662 @@
+// S-expression syntax. Every construct is a list headed by its name, any other
+// list is a function call. Calls to functions named after a construct are
+// written `(call name ...)`, calls of the function an expression gives are
+// lists headed by that expression, like `((adder 1) 2)`
+//
+//   (fun fact (n)
+//     (match n
+//       (0 1)
+//       (_ (* n (fact (- n 1))))))
+//
+//   (type Shape (Circle radius) Empty)
+//   (brand UserId Num)
+//
+// Comments are `; ...` to the end of the line, or `#| ... |#` for the closed
+// `-- ... --` form
+
+use super::Syntax;
+use super::{
+    ends_in_comment, finish, indent, parse_err, render_block, render_program, trails_code,
+};
+use crate::fmt::fmt_op;
+use crate::parse::{AstNode, BangParseError, Lines, Node};
+use crate::span::Span;
+use std::collections::{BTreeMap, LinkedList};
+
+pub struct Sexpr;
+
+const FORMS: &[&str] = &[
+    "fun", "lambda", "let", "match", "not", "and", "or", "+", "-", "*", "/", "**", "==", "!=", "<",
+    "<=", ">", ">=", "paren", "map", "list", "|>", "each", "fork", "call", "error", "if", "type",
+    "brand",
+];
+
+impl Syntax for Sexpr {
+    fn name(&self) -> &'static str {
+        "sexpr"
+    }
+
+    fn render(&self, program: &Node) -> String {
+        render_program(program, &render, &comment)
+    }
+
+    fn parse(&self, txt: &str) -> Result<Node, BangParseError> {
+        let lines = Lines::new(txt);
+        let (tokens, comments) = lex(txt, &lines)?;
+
+        let mut pos = 0;
+        let mut stmts = LinkedList::new();
+        while pos < tokens.len() {
+            let sx = read(&tokens, &mut pos, &lines, txt.len())?;
+            stmts.push_back(expr(&sx)?);
+        }
+
+        Ok(finish(txt, stmts, comments))
+    }
+}
+
+fn comment(text: &str) -> String {
+    let body = &text[2..];
+
+    match body.strip_suffix("--") {
+        Some(inner) if text.len() >= 4 => format!("#|{}|#", inner),
+        _ => format!(";{}", body),
+    }
+}
+
+fn render_list<'a>(nodes: impl Iterator<Item = &'a Node>, depth: usize) -> Vec<String> {
+    nodes.map(|node| render(node, depth)).collect()
+}
+
+fn form(head: &str, args: Vec<String>) -> String {
+    if args.is_empty() {
+        format!("({})", head)
+    } else {
+        format!("({} {})", head, args.join(" "))
+    }
+}
+
+fn render(node: &Node, depth: usize) -> String {
+    match &node.ast {
+        AstNode::Expr(expr) => render(expr, depth),
+        AstNode::Paren(expr) => form("paren", vec![render(expr, depth)]),
+        AstNode::Not(expr) => form("not", vec![render(expr, depth)]),
+        AstNode::Idt(idt) => idt.clone(),
+        AstNode::Num(num) => num.clone(),
+        AstNode::Str(str) => str.clone(),
+        AstNode::Bool(b) => b.to_string(),
+        AstNode::Default => "_".to_string(),
+        AstNode::None => "none".to_string(),
+        AstNode::Bind(idt) => idt.clone(),
+        AstNode::Guard(pat, guard) => form("if", vec![render(pat, depth), render(guard, depth)]),
+        AstNode::Type(name, variants) => {
+            let mut args = vec![name.clone()];
+            args.extend(render_list(variants.iter(), depth));
+            form("type", args)
+        }
+        AstNode::Brand(name, base) => form("brand", vec![name.clone(), base.clone()]),
+        AstNode::Variant(tag, fields) if fields.is_empty() => tag.clone(),
+        AstNode::Variant(tag, fields) => form(tag, render_list(fields.iter(), depth)),
+        AstNode::Error => "(error)".to_string(),
+        AstNode::Comment(text, _) => comment(text),
+        AstNode::Map(map) => {
+            if map.keys().all(|key| matches!(key.ast, AstNode::Num(_))) {
+                let mut items: Vec<_> = map.iter().collect();
+                items.sort_by_key(|(key, _)| match &key.ast {
+                    AstNode::Num(num) => num.parse::<usize>().unwrap_or(0),
+                    _ => 0,
+                });
+                form(
+                    "list",
+                    render_list(items.into_iter().map(|(_, val)| val), depth),
+                )
+            } else {
+                let entries = map
+                    .iter()
+                    .map(|(key, val)| format!("({} {})", render(key, depth), render(val, depth)))
+                    .collect();
+                form("map", entries)
+            }
+        }
+        AstNode::Let(idt, expr) => form("let", vec![idt.clone(), render(expr, depth)]),
+        AstNode::Fun(idt, args, body) => {
+            let args = render_list(args.iter(), depth);
+            let mut out = format!("(fun {} ({})", idt, args.join(" "));
+            out.push_str(&render_block(body.iter(), depth + 1, &render, &comment));
+            close(&mut out, body, depth);
+            out
+        }
+        AstNode::Lambda(args, body) => {
+            let args = render_list(args.iter(), depth);
+            let mut out = format!("(lambda ({})", args.join(" "));
+            out.push_str(&render_block(body.iter(), depth + 1, &render, &comment));
+            close(&mut out, body, depth);
+            out
+        }
+        AstNode::Call(callee, params) => {
+            let mut args = vec![render(callee, depth)];
+            args.extend(render_list(params.iter(), depth));
+            format!("({})", args.join(" "))
+        }
+        AstNode::Funcall(idt, params) => {
+            let params = render_list(params.iter(), depth);
+            if FORMS.contains(&idt.as_str()) {
+                let mut args = vec![idt.clone()];
+                args.extend(params);
+                form("call", args)
+            } else {
+                form(idt, params)
+            }
+        }
+        AstNode::Pipe(head, stages) => {
+            let mut args = vec![render(head, depth)];
+            args.extend(render_list(stages.iter(), depth));
+            form("|>", args)
+        }
+        AstNode::Each(stages) => form("each", render_list(stages.iter(), depth)),
+        AstNode::Fork(arms) => {
+            let arms = arms
+                .iter()
+                .map(|(key, stages)| {
+                    let mut args = render_list(stages.iter(), depth);
+                    args.insert(0, render(key, depth));
+                    format!("({})", args.join(" "))
+                })
+                .collect();
+            form("fork", arms)
+        }
+        AstNode::BinAdd(lhs, op, rhs)
+        | AstNode::BinMul(lhs, op, rhs)
+        | AstNode::BinPow(lhs, op, rhs)
+        | AstNode::BinCmp(lhs, op, rhs)
+        | AstNode::BinAnd(lhs, op, rhs)
+        | AstNode::BinOr(lhs, op, rhs) => {
+            form(fmt_op(op), vec![render(lhs, depth), render(rhs, depth)])
+        }
+        AstNode::Match(expr, cases) => {
+            let mut out = format!("(match {}", render(expr, depth));
+            out.push_str(&render_block(cases.iter(), depth + 1, &render, &comment));
+            close(&mut out, cases, depth);
+            out
+        }
+        AstNode::Branch(pat, expr) => format!("({} {})", render(pat, depth), render(expr, depth)),
+        _ => {
+            unreachable!()
+        }
+    }
+}
+
+// Closes a block, on a line of its own when a `;` comment would swallow it
+fn close(out: &mut String, lst: &LinkedList<Node>, depth: usize) {
+    if ends_in_comment(lst) {
+        out.push('\n');
+        out.push_str(&indent(depth));
+    }
+    out.push(')');
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Open,
+    Close,
+    Atom(String),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    span: Span,
+}
+
+// Splits `txt` into parens and atoms, comments are returned apart as nodes
+fn lex(txt: &str, lines: &Lines) -> Result<(Vec<Token>, LinkedList<Node>), BangParseError> {
+    let mut tokens = Vec::new();
+    let mut comments = LinkedList::new();
+    let mut i = 0;
+
+    while i < txt.len() {
+        let rest = &txt[i..];
+        let c = rest.chars().next().unwrap();
+
+        if c.is_whitespace() {
+            i += c.len_utf8();
+            continue;
+        }
+
+        let (tok, len) = match c {
+            '(' => (Some(Tok::Open), 1),
+            ')' => (Some(Tok::Close), 1),
+            ';' => {
+                let len = rest.find('\n').unwrap_or(rest.len());
+                let text = format!("--{}", &rest[1..len]);
+                comments.push_back(Node::new(
+                    AstNode::Comment(text.trim_end().to_string(), trails_code(txt, i)),
+                    lines.span(i, i + len),
+                ));
+                (None, len)
+            }
+            '#' if rest.starts_with("#|") => {
+                let len = match rest.find("|#") {
+                    Some(end) => end + 2,
+                    None => {
+                        return Err(parse_err(
+                            "`|#`",
+                            "end of input",
+                            lines.span(txt.len(), txt.len()),
+                        ))
+                    }
+                };
+                let text = format!("--{}--", &rest[2..len - 2]);
+                comments.push_back(Node::new(
+                    AstNode::Comment(text, trails_code(txt, i)),
+                    lines.span(i, i + len),
+                ));
+                (None, len)
+            }
+            '"' => match rest[1..].find('"') {
+                Some(end) => (Some(Tok::Atom(rest[..end + 2].to_string())), end + 2),
+                None => {
+                    return Err(parse_err(
+                        "`\"`",
+                        "end of input",
+                        lines.span(txt.len(), txt.len()),
+                    ))
+                }
+            },
+            _ => {
+                let len = rest
+                    .find(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | '"' | ';'))
+                    .unwrap_or(rest.len());
+                (Some(Tok::Atom(rest[..len].to_string())), len)
+            }
+        };
+
+        if let Some(tok) = tok {
+            tokens.push(Token {
+                tok,
+                span: lines.span(i, i + len),
+            });
+        }
+        i += len;
+    }
+
+    Ok((tokens, comments))
+}
+
+#[derive(Debug, Clone)]
+enum Sx {
+    Atom(String, Span),
+    List(Vec<Sx>, Span),
+}
+
+impl Sx {
+    fn span(&self) -> Span {
+        match self {
+            Sx::Atom(_, span) | Sx::List(_, span) => *span,
+        }
+    }
+}
+
+fn read(
+    tokens: &[Token],
+    pos: &mut usize,
+    lines: &Lines,
+    len: usize,
+) -> Result<Sx, BangParseError> {
+    let token = match tokens.get(*pos) {
+        Some(token) => token,
+        None => return Err(parse_err("`)`", "end of input", lines.span(len, len))),
+    };
+    *pos += 1;
+
+    match &token.tok {
+        Tok::Atom(atom) => Ok(Sx::Atom(atom.clone(), token.span)),
+        Tok::Close => Err(parse_err("expression", "`)`", token.span)),
+        Tok::Open => {
+            let mut items = Vec::new();
+            loop {
+                match tokens.get(*pos) {
+                    Some(Token {
+                        tok: Tok::Close,
+                        span,
+                    }) => {
+                        *pos += 1;
+                        let span = lines.span(token.span.start, span.end);
+                        return Ok(Sx::List(items, span));
+                    }
+                    _ => items.push(read(tokens, pos, lines, len)?),
+                }
+            }
+        }
+    }
+}
+
+fn expr(sx: &Sx) -> Result<Node, BangParseError> {
+    let node = term(sx)?;
+    Ok(Node::new(AstNode::Expr(Box::new(node)), sx.span()))
+}
+
+fn exprs(sxs: &[Sx]) -> Result<LinkedList<Node>, BangParseError> {
+    sxs.iter().map(expr).collect()
+}
+
+fn terms(sxs: &[Sx]) -> Result<LinkedList<Node>, BangParseError> {
+    sxs.iter().map(term).collect()
+}
+
+fn atom(atom: &str) -> AstNode {
+    if atom.starts_with('"') {
+        AstNode::Str(atom.to_string())
+    } else if !atom.is_empty() && atom.chars().all(|c| c.is_ascii_digit()) {
+        AstNode::Num(atom.to_string())
+    } else if atom == "true" || atom == "false" {
+        AstNode::Bool(atom == "true")
+    } else if atom == "none" {
+        AstNode::None
+    } else {
+        AstNode::Idt(atom.to_string())
+    }
+}
+
+// How an error refers to `sx` when it is not what was expected
+fn found(sx: &Sx) -> String {
+    match sx {
+        Sx::Atom(atom, _) => format!("`{}`", atom),
+        Sx::List(_, _) => "a list".to_string(),
+    }
+}
+
+// An identifier, as the keyword syntax requires for the arguments of a
+// function and the keys of a fork
+fn ident(sx: &Sx) -> Result<Node, BangParseError> {
+    let node = term(sx)?;
+    match node.ast {
+        AstNode::Idt(_) => Ok(node),
+        _ => Err(parse_err("identifier", &found(sx), sx.span())),
+    }
+}
+
+fn idents(sxs: &[Sx]) -> Result<LinkedList<Node>, BangParseError> {
+    sxs.iter().map(ident).collect()
+}
+
+// The stages of a pipeline, which are the forms the keyword syntax allows
+// after `|>`
+fn stages(sxs: &[Sx]) -> Result<LinkedList<Node>, BangParseError> {
+    let stage = |sx: &Sx| {
+        let node = term(sx)?;
+        match node.ast {
+            AstNode::Each(_)
+            | AstNode::Fork(_)
+            | AstNode::Lambda(_, _)
+            | AstNode::Funcall(_, _)
+            | AstNode::Idt(_) => Ok(node),
+            _ => Err(parse_err("pipe stage", &found(sx), sx.span())),
+        }
+    };
+
+    sxs.iter().map(stage).collect()
+}
+
+fn name(sx: &Sx) -> Result<String, BangParseError> {
+    match sx {
+        Sx::Atom(atom, _) => Ok(atom.clone()),
+        Sx::List(_, span) => Err(parse_err("identifier", "a list", *span)),
+    }
+}
+
+fn pair(sx: &Sx, what: &str) -> Result<(Sx, Sx), BangParseError> {
+    match sx {
+        Sx::List(items, _) if items.len() == 2 => Ok((items[0].clone(), items[1].clone())),
+        _ => Err(parse_err(what, "something else", sx.span())),
+    }
+}
+
+// The pattern of a match branch, `(if pattern guard)` when guarded. Only what
+// the keyword syntax reads as a pattern is one, anything else is an expression
+// the value is compared with
+fn pattern(sx: &Sx) -> Result<Node, BangParseError> {
+    if let Sx::List(items, span) = sx {
+        if let [Sx::Atom(head, _), pat, guard] = &items[..] {
+            if head == "if" {
+                let guard = Box::new(expr(guard)?);
+                return Ok(Node::new(
+                    AstNode::Guard(Box::new(pattern(pat)?), guard),
+                    *span,
+                ));
+            }
+        }
+    }
+
+    match pat(sx) {
+        Some(node) => Ok(node),
+        None => expr(sx),
+    }
+}
+
+#[allow(clippy::mutable_key_type)]
+fn pat(sx: &Sx) -> Option<Node> {
+    let (items, span) = match sx {
+        Sx::Atom(a, span) => {
+            let ast = match (a.as_str(), atom(a)) {
+                ("_", _) => AstNode::Default,
+                (_, AstNode::Idt(tag)) if is_tag(&tag) => AstNode::Variant(tag, LinkedList::new()),
+                (_, AstNode::Idt(idt)) => AstNode::Bind(idt),
+                (_, ast) => ast,
+            };
+            return Some(Node::new(ast, *span));
+        }
+        Sx::List(items, span) => (items, *span),
+    };
+
+    let mut map = BTreeMap::new();
+
+    match items.split_first() {
+        Some((Sx::Atom(head, _), args)) if head == "list" => {
+            for (i, arg) in args.iter().enumerate() {
+                let pat = pat(arg)?;
+                map.insert(Node::new(AstNode::Num(i.to_string()), pat.span), pat);
+            }
+        }
+        Some((Sx::Atom(head, _), args)) if is_tag(head) => {
+            let pats = args.iter().map(pat).collect::<Option<_>>()?;
+            return Some(Node::new(AstNode::Variant(head.clone(), pats), span));
+        }
+        Some((Sx::Atom(head, _), args)) if head == "map" && !args.is_empty() => {
+            for entry in args {
+                let (key, val) = pair(entry, "map entry").ok()?;
+                let key = match &key {
+                    Sx::Atom(a, span) => match atom(a) {
+                        AstNode::Idt(idt) => Node::new(AstNode::Idt(idt), *span),
+                        _ => return None,
+                    },
+                    _ => return None,
+                };
+                map.insert(key, pat(&val)?);
+            }
+        }
+        _ => return None,
+    }
+
+    Some(Node::new(AstNode::Map(map), span))
+}
+
+// Whether an atom names a variant, which patterns tell from a binding by its
+// capital first letter
+fn is_tag(atom: &str) -> bool {
+    atom.starts_with(|c: char| c.is_ascii_uppercase())
+}
+
+// A variant of a type declaration, `Tag` or `(Tag field ...)`
+fn variant(sx: &Sx) -> Result<Node, BangParseError> {
+    let (tag, fields) = match sx {
+        Sx::Atom(tag, _) => (tag.clone(), LinkedList::new()),
+        Sx::List(items, span) => match items.split_first() {
+            Some((head, fields)) => (name(head)?, terms(fields)?),
+            None => return Err(parse_err("variant", "`()`", *span)),
+        },
+    };
+
+    if !is_tag(&tag) {
+        return Err(parse_err(
+            "capitalized name",
+            &format!("`{}`", tag),
+            sx.span(),
+        ));
+    }
+
+    Ok(Node::new(AstNode::Variant(tag, fields), sx.span()))
+}
+
+#[allow(clippy::mutable_key_type)]
+fn term(sx: &Sx) -> Result<Node, BangParseError> {
+    let (items, span) = match sx {
+        Sx::Atom(a, span) => return Ok(Node::new(atom(a), *span)),
+        Sx::List(items, span) => (items, *span),
+    };
+
+    let (head, args) = match items.split_first() {
+        Some((Sx::Atom(head, head_span), args)) => ((head.as_str(), *head_span), args),
+        Some((callee @ Sx::List(_, _), args)) => {
+            let call = AstNode::Call(Box::new(term(callee)?), exprs(args)?);
+            return Ok(Node::new(call, span));
+        }
+        None => return Err(parse_err("form name", "`)`", span)),
+    };
+
+    let arity = |n: usize| -> Result<(), BangParseError> {
+        if args.len() == n {
+            Ok(())
+        } else {
+            Err(parse_err(
+                &format!("{} arguments to `{}`", n, head.0),
+                &args.len().to_string(),
+                span,
+            ))
+        }
+    };
+
+    let binop = |bin: fn(Box<Node>, Box<Node>, Box<Node>) -> AstNode, op: AstNode| {
+        arity(2)?;
+        let op = Node::new(op, head.1);
+        Ok::<_, BangParseError>(bin(
+            Box::new(term(&args[0])?),
+            Box::new(op),
+            Box::new(term(&args[1])?),
+        ))
+    };
+
+    let ast = match head.0 {
+        "fun" => {
+            if args.len() < 2 {
+                return Err(parse_err("function name and arguments", "`)`", span));
+            }
+            let params = match &args[1] {
+                Sx::List(params, _) => idents(params)?,
+                sx => return Err(parse_err("argument list", "an atom", sx.span())),
+            };
+            AstNode::Fun(name(&args[0])?, params, exprs(&args[2..])?)
+        }
+        "lambda" => {
+            let params = match args.first() {
+                Some(Sx::List(params, _)) => idents(params)?,
+                Some(sx) => return Err(parse_err("argument list", "an atom", sx.span())),
+                None => return Err(parse_err("argument list", "`)`", span)),
+            };
+            AstNode::Lambda(params, exprs(&args[1..])?)
+        }
+        "type" => {
+            if args.len() < 2 {
+                return Err(parse_err("type name and variants", "`)`", span));
+            }
+            let variants = args[1..].iter().map(variant).collect::<Result<_, _>>()?;
+            AstNode::Type(name(&args[0])?, variants)
+        }
+        "brand" => {
+            arity(2)?;
+            AstNode::Brand(name(&args[0])?, name(&args[1])?)
+        }
+        "let" => {
+            arity(2)?;
+            AstNode::Let(name(&args[0])?, Box::new(expr(&args[1])?))
+        }
+        "match" => {
+            if args.is_empty() {
+                return Err(parse_err("match subject", "`)`", span));
+            }
+            let mut cases = LinkedList::new();
+            for case in &args[1..] {
+                let (pat, res) = pair(case, "match branch")?;
+                let branch = AstNode::Branch(Box::new(pattern(&pat)?), Box::new(expr(&res)?));
+                cases.push_back(Node::new(branch, case.span()));
+            }
+            AstNode::Match(Box::new(expr(&args[0])?), cases)
+        }
+        "not" => {
+            arity(1)?;
+            AstNode::Not(Box::new(term(&args[0])?))
+        }
+        "paren" => {
+            arity(1)?;
+            AstNode::Paren(Box::new(expr(&args[0])?))
+        }
+        "and" => binop(AstNode::BinAnd, AstNode::And)?,
+        "or" => binop(AstNode::BinOr, AstNode::Or)?,
+        "+" => binop(AstNode::BinAdd, AstNode::Add)?,
+        "-" => binop(AstNode::BinAdd, AstNode::Sub)?,
+        "*" => binop(AstNode::BinMul, AstNode::Mul)?,
+        "/" => binop(AstNode::BinMul, AstNode::Div)?,
+        "**" => binop(AstNode::BinPow, AstNode::Pow)?,
+        "==" => binop(AstNode::BinCmp, AstNode::Eq)?,
+        "!=" => binop(AstNode::BinCmp, AstNode::Ne)?,
+        "<" => binop(AstNode::BinCmp, AstNode::Lt)?,
+        "<=" => binop(AstNode::BinCmp, AstNode::Le)?,
+        ">" => binop(AstNode::BinCmp, AstNode::Gt)?,
+        ">=" => binop(AstNode::BinCmp, AstNode::Ge)?,
+        "map" => {
+            let mut map = BTreeMap::new();
+            for entry in args {
+                let (key, val) = pair(entry, "map entry")?;
+                map.insert(term(&key)?, term(&val)?);
+            }
+            AstNode::Map(map)
+        }
+        "list" => {
+            let mut map = BTreeMap::new();
+            for (i, val) in args.iter().enumerate() {
+                let val = term(val)?;
+                map.insert(Node::new(AstNode::Num(i.to_string()), val.span), val);
+            }
+            AstNode::Map(map)
+        }
+        "|>" => {
+            if args.is_empty() {
+                return Err(parse_err("pipe head", "`)`", span));
+            }
+            AstNode::Pipe(Box::new(term(&args[0])?), stages(&args[1..])?)
+        }
+        "each" => AstNode::Each(stages(args)?),
+        "fork" => {
+            let mut arms = BTreeMap::new();
+            for arm in args {
+                match arm {
+                    Sx::List(items, _) if !items.is_empty() => {
+                        arms.insert(ident(&items[0])?, stages(&items[1..])?);
+                    }
+                    _ => return Err(parse_err("fork arm", "something else", arm.span())),
+                }
+            }
+            AstNode::Fork(arms)
+        }
+        "call" => {
+            if args.is_empty() {
+                return Err(parse_err("function name", "`)`", span));
+            }
+            AstNode::Funcall(name(&args[0])?, exprs(&args[1..])?)
+        }
+        "error" => AstNode::Error,
+        idt => AstNode::Funcall(idt.to_string(), exprs(args)?),
+    };
+
+    Ok(Node::new(ast, span))
+}
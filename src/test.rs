@@ -2,10 +2,14 @@
 // run with `cargo test`
 // cargo test -- --nocapture
 #[cfg(test)]
-
 mod tests {
-    use crate::eval::{eval_prgm, RunErr, Value};
-    use crate::parse::BangParser;
+    use crate::eval::{eval_prgm, ErrKind, RunErr, Value};
+    use crate::num::Num;
+    use crate::parse::{BangParseError, BangParser};
+
+    fn run(txt: &str) -> Result<Value, RunErr> {
+        eval_prgm(BangParser::parse(txt).unwrap())
+    }
 
     #[test]
     fn test_parser_bin_mul() {
@@ -24,7 +28,7 @@ mod tests {
             Ok(_) => {
                 println!("unimplemented val");
             }
-            Err(RunErr(msg)) => {
+            Err(RunErr { msg, .. }) => {
                 panic!("{}", msg);
             }
         }
@@ -47,7 +51,7 @@ mod tests {
             Ok(_) => {
                 println!("unimplemented val");
             }
-            Err(RunErr(msg)) => {
+            Err(RunErr { msg, .. }) => {
                 panic!("{}", msg);
             }
         }
@@ -73,7 +77,7 @@ mod tests {
             Ok(_) => {
                 println!("unimplemented val");
             }
-            Err(RunErr(msg)) => {
+            Err(RunErr { msg, .. }) => {
                 panic!("{}", msg);
             }
         }
@@ -100,7 +104,7 @@ mod tests {
             Ok(_) => {
                 println!("unimplemented val");
             }
-            Err(RunErr(msg)) => {
+            Err(RunErr { msg, .. }) => {
                 panic!("{}", msg);
             }
         }
@@ -137,7 +141,7 @@ mod tests {
             Ok(_) => {
                 println!("unimplemented val");
             }
-            Err(RunErr(msg)) => {
+            Err(RunErr { msg, .. }) => {
                 panic!("{}", msg);
             }
         }
@@ -167,7 +171,7 @@ mod tests {
             Ok(_) => {
                 println!("unimplemented val");
             }
-            Err(RunErr(msg)) => {
+            Err(RunErr { msg, .. }) => {
                 panic!("{}", msg);
             }
         }
@@ -180,7 +184,403 @@ mod tests {
             1
             end
             ";
-        let exp = 0;
+
+        let val = match BangParser::parse(txt) {
+            Ok(node) => eval_prgm(node),
+            Err(e) => panic!("Error: {:?}", e),
+        };
+
+        // a definition evaluates to the function it defines
+        match val {
+            Ok(Value::Fun(args, _, _)) => {
+                assert!(args.is_empty());
+            }
+            Ok(val) => {
+                panic!("expected a function, got {:?}", val);
+            }
+            Err(RunErr { msg, .. }) => {
+                panic!("{}", msg);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parser_paren() {
+        let txt = "(1 + 2) * 3 - (4 - 2) ** 3";
+        let exp = (1 + 2) * 3 - (4 - 2_i32).pow(3);
+
+        let val = match BangParser::parse(txt) {
+            Ok(node) => eval_prgm(node),
+            Err(e) => panic!("Error: {:?}", e),
+        };
+
+        match val {
+            Ok(Value::Num(val)) => {
+                assert_eq!(val, exp);
+            }
+            Ok(_) => {
+                println!("unimplemented val");
+            }
+            Err(RunErr { msg, .. }) => {
+                panic!("{}", msg);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parser_paren_nested() {
+        let txt = "let a = 3
+                   ((a + 1) * (2 + (10 - a) * 2)) / ((2))";
+        let exp = ((3 + 1) * (2 + (10 - 3) * 2)) / 2;
+
+        let val = match BangParser::parse(txt) {
+            Ok(node) => eval_prgm(node),
+            Err(e) => panic!("Error: {:?}", e),
+        };
+
+        match val {
+            Ok(Value::Num(val)) => {
+                assert_eq!(val, exp);
+            }
+            Ok(_) => {
+                println!("unimplemented val");
+            }
+            Err(RunErr { msg, .. }) => {
+                panic!("{}", msg);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parser_paren_keeps_grouping() {
+        use crate::parse::AstNode;
+
+        let grouped = BangParser::parse("(1 + 2) * 3").unwrap();
+        let flat = BangParser::parse("1 + 2 * 3").unwrap();
+
+        assert_ne!(grouped, flat);
+
+        let expr = match grouped.ast {
+            AstNode::Program(lst) => lst.front().unwrap().clone(),
+            _ => panic!("expected a program"),
+        };
+
+        match expr.ast {
+            AstNode::Expr(expr) => match expr.ast {
+                AstNode::BinMul(lhs, _, _) => assert!(matches!(lhs.ast, AstNode::Paren(_))),
+                node => panic!("expected a multiplication, got {:?}", node),
+            },
+            node => panic!("expected an expression, got {:?}", node),
+        }
+    }
+
+    #[test]
+    fn test_parser_pipe() {
+        let txt = "fun double(a)
+                     a * 2
+                   end
+
+                   fun inc(a)
+                     a + 1
+                   end
+
+                   3 + 4 |> double |> inc";
+        let exp = (3 + 4) * 2 + 1;
+
+        let val = match BangParser::parse(txt) {
+            Ok(node) => eval_prgm(node),
+            Err(e) => panic!("Error: {:?}", e),
+        };
+
+        match val {
+            Ok(Value::Num(val)) => {
+                assert_eq!(val, exp);
+            }
+            Ok(_) => {
+                println!("unimplemented val");
+            }
+            Err(RunErr { msg, .. }) => {
+                panic!("{}", msg);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parser_pipe_args() {
+        let txt = "fun sub(a, b)
+                     a - b
+                   end
+
+                   fun scale(a, b, c)
+                     a * b + c
+                   end
+
+                   10 |> sub(3) |> scale(2, 1)";
+        let exp = (10 - 3) * 2 + 1;
+
+        let val = match BangParser::parse(txt) {
+            Ok(node) => eval_prgm(node),
+            Err(e) => panic!("Error: {:?}", e),
+        };
+
+        match val {
+            Ok(Value::Num(val)) => {
+                assert_eq!(val, exp);
+            }
+            Ok(_) => {
+                println!("unimplemented val");
+            }
+            Err(RunErr { msg, .. }) => {
+                panic!("{}", msg);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parser_pipe_fork() {
+        let txt = "fun double(a)
+                     a * 2
+                   end
+
+                   fun add(a, b)
+                     a + b
+                   end
+
+                   5 |> add(1) |> { twice: double, more: add(10) |> double, same: add(0) }";
+
+        let val = match BangParser::parse(txt) {
+            Ok(node) => eval_prgm(node),
+            Err(e) => panic!("Error: {:?}", e),
+        };
+
+        match val {
+            Ok(Value::Map(map)) => {
+                assert_eq!(map.len(), 3);
+                assert_eq!(
+                    map[&Value::String("twice".to_string())],
+                    Value::Num(Num::from(12))
+                );
+                assert_eq!(
+                    map[&Value::String("more".to_string())],
+                    Value::Num(Num::from(32))
+                );
+                assert_eq!(
+                    map[&Value::String("same".to_string())],
+                    Value::Num(Num::from(6))
+                );
+            }
+            Ok(val) => {
+                panic!("expected a map, got {:?}", val);
+            }
+            Err(RunErr { msg, .. }) => {
+                panic!("{}", msg);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parser_pipe_each() {
+        let txt = "fun double(a)
+                     a * 2
+                   end
+
+                   fun add(a, b)
+                     a + b
+                   end
+
+                   { 11, 22, 33, } |> [double |> add(1)]";
+
+        let val = match BangParser::parse(txt) {
+            Ok(node) => eval_prgm(node),
+            Err(e) => panic!("Error: {:?}", e),
+        };
+
+        match val {
+            Ok(Value::Map(map)) => {
+                let vals: Vec<_> = map.into_iter().collect();
+                assert_eq!(
+                    vals,
+                    vec![
+                        (Value::Num(Num::from(0)), Value::Num(Num::from(23))),
+                        (Value::Num(Num::from(1)), Value::Num(Num::from(45))),
+                        (Value::Num(Num::from(2)), Value::Num(Num::from(67))),
+                    ]
+                );
+            }
+            Ok(val) => {
+                panic!("expected a map, got {:?}", val);
+            }
+            Err(RunErr { msg, .. }) => {
+                panic!("{}", msg);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parser_pipe_each_nested() {
+        let txt = "fun dec(c)
+                     c - 1
+                   end
+
+                   let a = { 1, 2, }
+                   let b = { x: 10, y: 20, }
+                   { a, b, } |> [[dec]]";
+
+        let val = match BangParser::parse(txt) {
+            Ok(node) => eval_prgm(node),
+            Err(e) => panic!("Error: {:?}", e),
+        };
+
+        let a = Value::Map(
+            [
+                (Value::Num(Num::from(0)), Value::Num(Num::from(0))),
+                (Value::Num(Num::from(1)), Value::Num(Num::from(1))),
+            ]
+            .into(),
+        );
+        let b = Value::Map(
+            [
+                (Value::String("x".to_string()), Value::Num(Num::from(9))),
+                (Value::String("y".to_string()), Value::Num(Num::from(19))),
+            ]
+            .into(),
+        );
+
+        match val {
+            Ok(Value::Map(map)) => {
+                let vals: Vec<_> = map.into_iter().collect();
+                assert_eq!(
+                    vals,
+                    vec![(Value::Num(Num::from(0)), a), (Value::Num(Num::from(1)), b)]
+                );
+            }
+            Ok(val) => {
+                panic!("expected a map, got {:?}", val);
+            }
+            Err(RunErr { msg, .. }) => {
+                panic!("{}", msg);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parser_pipe_each_not_collection() {
+        let txt = "fun dec(c)
+                     c - 1
+                   end
+
+                   { 1, 2, } |> [[dec]]";
+
+        let val = match BangParser::parse(txt) {
+            Ok(node) => eval_prgm(node),
+            Err(e) => panic!("Error: {:?}", e),
+        };
+
+        match val {
+            Err(RunErr { msg, .. }) => {
+                assert!(msg.contains("cannot map over"), "{}", msg);
+            }
+            Ok(val) => {
+                panic!("expected an error, got {:?}", val);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parser_bool_precedence() {
+        let txt = "let a = 4
+                   1 + 2 * 3 == 7 and not a > 10 or false and a < 0";
+        let exp = true;
+
+        let val = match BangParser::parse(txt) {
+            Ok(node) => eval_prgm(node),
+            Err(e) => panic!("Error: {:?}", e),
+        };
+
+        match val {
+            Ok(Value::Bool(val)) => {
+                assert_eq!(val, exp);
+            }
+            Ok(_) => {
+                println!("unimplemented val");
+            }
+            Err(RunErr { msg, .. }) => {
+                panic!("{}", msg);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parser_cmp_ops() {
+        let cases = [
+            ("1 < 2", true),
+            ("2 <= 2", true),
+            ("3 > 4", false),
+            ("4 >= 5", false),
+            ("5 == 5", true),
+            ("5 != 5", false),
+            ("\"bob\" == \"bob\"", true),
+            ("\"a\" < \"b\"", true),
+            ("let name = \"bob\"\nname != \"bob\"", false),
+            ("match \"a\"\n  \"a\" => true\n  _ => false\nend", true),
+        ];
+
+        for (txt, exp) in cases {
+            let val = match BangParser::parse(txt) {
+                Ok(node) => eval_prgm(node),
+                Err(e) => panic!("Error: {:?}", e),
+            };
+
+            match val {
+                Ok(Value::Bool(val)) => {
+                    assert_eq!(val, exp, "{}", txt);
+                }
+                Ok(val) => {
+                    panic!("expected a bool, got {:?}", val);
+                }
+                Err(RunErr { msg, .. }) => {
+                    panic!("{}", msg);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parser_bool_short_circuit() {
+        let txt = "let a = false and missing
+                   let b = true or missing
+                   a == false and b";
+        let exp = true;
+
+        let val = match BangParser::parse(txt) {
+            Ok(node) => eval_prgm(node),
+            Err(e) => panic!("Error: {:?}", e),
+        };
+
+        match val {
+            Ok(Value::Bool(val)) => {
+                assert_eq!(val, exp);
+            }
+            Ok(_) => {
+                println!("unimplemented val");
+            }
+            Err(RunErr { msg, .. }) => {
+                panic!("{}", msg);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parser_bool_predicate() {
+        let txt = "fun between(n, lo, hi)
+                     n >= lo and n <= hi
+                   end
+
+                   match between(5, 1, 10) and not between(50, 1, 10)
+                     true => 1
+                     false => 2
+                   end";
+        let exp = 1;
 
         let val = match BangParser::parse(txt) {
             Ok(node) => eval_prgm(node),
@@ -194,9 +594,2009 @@ mod tests {
             Ok(_) => {
                 println!("unimplemented val");
             }
-            Err(RunErr(msg)) => {
+            Err(RunErr { msg, .. }) => {
                 panic!("{}", msg);
             }
         }
     }
+
+    #[test]
+    fn test_span_nodes() {
+        use crate::parse::AstNode;
+
+        let txt = "let a = 1
+                   a + (a * 2)";
+
+        let node = BangParser::parse(txt).unwrap();
+
+        let exprs = match node.ast {
+            AstNode::Program(lst) => lst,
+            _ => panic!("expected a program"),
+        };
+
+        let expr = exprs.into_iter().nth(1).unwrap();
+        assert_eq!(&txt[expr.span.start..expr.span.end], "a + (a * 2)");
+        assert_eq!((expr.span.line, expr.span.col), (2, 20));
+
+        match expr.ast {
+            AstNode::Expr(expr) => match expr.ast {
+                AstNode::BinAdd(lhs, op, rhs) => {
+                    assert_eq!((lhs.span.line, lhs.span.col), (2, 20));
+                    assert_eq!((op.span.line, op.span.col), (2, 22));
+                    assert_eq!(&txt[rhs.span.start..rhs.span.end], "(a * 2)");
+                }
+                node => panic!("expected an addition, got {:?}", node),
+            },
+            node => panic!("expected an expression, got {:?}", node),
+        }
+    }
+
+    #[test]
+    fn test_span_run_err() {
+        let txt = "fun f(x)
+  x + missing
+end
+
+f(1)";
+
+        let val = match BangParser::parse(txt) {
+            Ok(node) => eval_prgm(node),
+            Err(e) => panic!("Error: {:?}", e),
+        };
+
+        match val {
+            Err(RunErr { msg, span, .. }) => {
+                assert_eq!(msg, "Error: identifier missing is not defined");
+                assert_eq!((span.line, span.col), (2, 7));
+                assert_eq!(
+                    span.snippet(txt, "f.bang", &msg),
+                    "Error: identifier missing is not defined
+ --> f.bang:2:7
+  |
+2 |   x + missing
+  |       ^^^^^^^"
+                );
+            }
+            Ok(val) => {
+                panic!("expected an error, got {:?}", val);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_err() {
+        let txt = "let a = 1
+                   let = 3";
+
+        match BangParser::parse(txt) {
+            Err(BangParseError {
+                msg,
+                expected,
+                span,
+            }) => {
+                assert_eq!((span.line, span.col), (2, 24));
+                assert!(
+                    expected.contains(&"expression".to_string()),
+                    "{:?}",
+                    expected
+                );
+                assert!(msg.starts_with("Error: expected "), "{}", msg);
+                assert!(msg.ends_with("found `=`"), "{}", msg);
+            }
+            Ok(node) => panic!("expected an error, got {:?}", node),
+        }
+    }
+
+    #[test]
+    fn test_parse_err_eoi() {
+        match BangParser::parse("1 + (2 *") {
+            Err(BangParseError { msg, span, .. }) => {
+                assert_eq!(span.start, 8);
+                assert!(msg.ends_with("found end of input"), "{}", msg);
+            }
+            Ok(node) => panic!("expected an error, got {:?}", node),
+        }
+    }
+
+    #[test]
+    fn test_parse_err_no_panic() {
+        let cases = [
+            "",
+            "1 +",
+            "(",
+            ")",
+            "fun",
+            "fun f(a b) a end",
+            "match 1 end",
+            "match 1 2 => end",
+            "let",
+            "{ a: 1 }",
+            "1 |> [",
+            "1 |> { a: }",
+            "f(1,)",
+            "\"unterminated",
+            "-- only a comment",
+            "not",
+            "1 < < 2",
+            "\u{1F4A5} + 1",
+        ];
+
+        for txt in cases {
+            let _ = BangParser::parse(txt);
+        }
+    }
+
+    #[test]
+    fn test_parse_recover() {
+        use crate::parse::AstNode;
+
+        let txt = "let x = 10
+
+fun add(a, b)
+  a + * b
+end
+
+fun ok(a)
+let b = a
+b
+end
+
+let y = (1 +
+
+fun sub(a, b)
+  a - b
+end
+
+ok(x)
+";
+
+        let (node, errs) = BangParser::parse_recover(txt);
+
+        let lines: Vec<_> = errs.iter().map(|err| err.span.line).collect();
+        assert_eq!(lines, vec![4, 12]);
+
+        let lst = match node.ast {
+            AstNode::Program(lst) => lst,
+            node => panic!("expected a program, got {:?}", node),
+        };
+
+        let kinds: Vec<_> = lst
+            .iter()
+            .map(|node| match &node.ast {
+                AstNode::Expr(expr) => match &expr.ast {
+                    AstNode::Let(idt, _) => format!("let {}", idt),
+                    AstNode::Fun(idt, _, _) => format!("fun {}", idt),
+                    AstNode::Funcall(idt, _) => format!("call {}", idt),
+                    node => format!("{:?}", node),
+                },
+                AstNode::Error => format!("error at {}", node.span.line),
+                node => format!("{:?}", node),
+            })
+            .collect();
+
+        assert_eq!(
+            kinds,
+            vec![
+                "let x",
+                "error at 3",
+                "fun ok",
+                "error at 12",
+                "fun sub",
+                "call ok",
+                "EOI"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_recover_valid() {
+        let txt = "fun ok(a)
+let b = a
+b
+end
+
+ok(1)";
+
+        let (node, errs) = BangParser::parse_recover(txt);
+
+        assert!(errs.is_empty(), "{:?}", errs);
+        assert_eq!(node, BangParser::parse(txt).unwrap());
+    }
+
+    #[test]
+    fn test_fmt() {
+        use crate::fmt::format;
+
+        let txt = "-- header
+let x=10 -- ten
+fun add(a,b) -- adds
+    -- body
+    a+b
+end
+match x>3 and not false
+ true=>(1+2)*3
+ _ => add(1, 2) |> add(3) |> {p: add(1), q: [add(2)]}
+end";
+        let exp = "-- header
+let x = 10 -- ten
+
+fun add(a, b) -- adds
+  -- body
+  a + b
+end
+
+match x > 3 and not false
+  true => (1 + 2) * 3
+  _ => add(1, 2) |> add(3) |> { p: add(1), q: [add(2)] }
+end
+";
+
+        let node = BangParser::parse(txt).unwrap();
+        let formatted = format(&node);
+        assert_eq!(formatted, exp);
+
+        let reparsed = BangParser::parse(&formatted).unwrap();
+        assert_eq!(reparsed, node);
+        assert_eq!(format(&reparsed), formatted);
+    }
+
+    #[test]
+    fn test_fmt_long_map() {
+        use crate::fmt::format;
+
+        let txt = "fun f(a)
+  let m = { name: \"a rather long string value\", other: \"another long string\", n: 1, }
+  m
+end";
+        let exp = "fun f(a)
+  let m = {
+    n: 1,
+    name: \"a rather long string value\",
+    other: \"another long string\",
+  }
+  m
+end
+";
+
+        let node = BangParser::parse(txt).unwrap();
+        let formatted = format(&node);
+        assert_eq!(formatted, exp);
+        assert_eq!(BangParser::parse(&formatted).unwrap(), node);
+    }
+
+    #[test]
+    fn test_cst_lossless() {
+        use crate::parse::{CstElem, TokenKind};
+
+        let txt = "  -- header --\n\nfun  add( a,b ) -- trailing\n\ta+b   -- inline -- * 1\nend\n\nlet   x={ a : \"--\" ,}\nmatch x\n  { a: 1, } => x |> [ id ]\n  _=>x\nend-- last";
+
+        let cst = BangParser::parse_cst(txt).unwrap();
+        assert_eq!(cst.to_string(), txt);
+
+        let comments: Vec<_> = cst
+            .tokens()
+            .into_iter()
+            .filter(|token| token.kind == TokenKind::Comment)
+            .map(|token| token.text.as_str())
+            .collect();
+        assert_eq!(
+            comments,
+            vec!["-- header --", "-- trailing", "-- inline --", "-- last"]
+        );
+
+        // every child sits inside its parent, one after the other
+        fn check(node: &crate::parse::CstNode, txt: &str) {
+            let mut pos = node.span.start;
+            for elem in &node.children {
+                let span = match elem {
+                    CstElem::Node(node) => {
+                        check(node, txt);
+                        node.span
+                    }
+                    CstElem::Token(token) => {
+                        assert_eq!(&txt[token.span.start..token.span.end], token.text);
+                        token.span
+                    }
+                };
+                assert_eq!(span.start, pos);
+                pos = span.end;
+            }
+            assert_eq!(pos, node.span.end);
+        }
+        check(&cst, txt);
+
+        for path in ["examples/sample.bang", "examples/loop.bang"] {
+            let txt = std::fs::read_to_string(path).unwrap();
+            assert_eq!(BangParser::parse_cst(&txt).unwrap().to_string(), txt);
+        }
+    }
+
+    #[test]
+    fn test_cst_to_ast() {
+        use crate::parse::AstNode;
+
+        let txt = "fun add(a, b) -- sum\n  a + b\nend\nadd(1, 2)";
+
+        let cst = BangParser::parse_cst(txt).unwrap();
+        let node = BangParser::parse(txt).unwrap();
+
+        assert_eq!(node.span, cst.span);
+
+        match node.ast {
+            AstNode::Program(lst) => match &lst.front().unwrap().ast {
+                AstNode::Expr(expr) => match &expr.ast {
+                    AstNode::Fun(_, _, body) => {
+                        assert_eq!(
+                            body.front().unwrap().ast,
+                            AstNode::Comment("-- sum".to_string(), true)
+                        );
+                    }
+                    _ => panic!("expected a function"),
+                },
+                _ => panic!("expected an expression"),
+            },
+            _ => panic!("expected a program"),
+        }
+    }
+
+    #[test]
+    fn test_serial_round_trip() {
+        use crate::serial::{from_binary, from_json, to_binary, to_json};
+
+        let txt = "-- every kind of node
+fun f(a, b) -- trailing
+  let m = { k: \"multi\nline \\ \u{e9}\u{1f600}\", n: true, }
+  match not a or b and (a != 1)
+    { 1, } => 2 ** 3 * 4 / 5 - 6 >= 7
+    { k: v, } if v > 1 => none
+    Some(Pair(_, b)) => b
+    Nothing => 0
+    _ => { 1, 2, } |> [id |> g(1)] |> { x: h, y: [id], }
+  end
+end
+type Opt = Some(value) | Nothing
+brand Token = Str
+f(1, 2)
+(fun(x) x end)(f)(fun y: y, { k: fun z: z, })";
+
+        let mut srcs = vec![txt.to_string()];
+        for path in ["examples/sample.bang", "examples/loop.bang"] {
+            srcs.push(std::fs::read_to_string(path).unwrap());
+        }
+
+        for src in srcs {
+            let node = BangParser::parse(&src).unwrap();
+
+            // spans are not part of node equality, the debug output has them
+            let json = from_json(&to_json(&node)).unwrap();
+            assert_eq!(format!("{:?}", json), format!("{:?}", node));
+
+            let bin = from_binary(&to_binary(&node)).unwrap();
+            assert_eq!(format!("{:?}", bin), format!("{:?}", node));
+        }
+
+        let (node, errs) = BangParser::parse_recover("fun f(a\nend\n1");
+        assert_eq!(errs.len(), 1);
+        let bin = from_binary(&to_binary(&node)).unwrap();
+        assert_eq!(format!("{:?}", bin), format!("{:?}", node));
+    }
+
+    #[test]
+    fn test_serial_errors() {
+        use crate::parse::{AstNode, Node};
+        use crate::serial::{from_binary, from_json, to_binary, to_json, DecodeErr};
+        use crate::span::Span;
+        use std::collections::{BTreeMap, LinkedList};
+
+        let node = BangParser::parse("fun f(a) a + 1 end f(1)").unwrap();
+
+        let json = to_json(&node).replacen("\"version\": 1", "\"version\": 2", 1);
+        match from_json(&json) {
+            Err(DecodeErr { msg }) => {
+                assert_eq!(msg, "Error: unsupported format version 2, expected 1")
+            }
+            Ok(_) => panic!("expected a version error"),
+        }
+
+        let json = to_json(&node).replacen("\"kind\": \"Fun\"", "\"kind\": \"Fn\"", 1);
+        match from_json(&json) {
+            Err(DecodeErr { msg }) => assert_eq!(msg, "Error: unknown node kind `Fn`"),
+            Ok(_) => panic!("expected a kind error"),
+        }
+
+        // every cut short document is an error rather than a panic
+        let json = to_json(&node);
+        for i in 0..json.trim_end().len() {
+            assert!(from_json(&json[..i]).is_err());
+        }
+
+        let bin = to_binary(&node);
+        for i in 0..bin.len() {
+            assert!(from_binary(&bin[..i]).is_err());
+        }
+
+        let mut bin = to_binary(&node);
+        bin[4] = 9;
+        match from_binary(&bin) {
+            Err(DecodeErr { msg }) => {
+                assert_eq!(msg, "Error: unsupported format version 9, expected 1")
+            }
+            Ok(_) => panic!("expected a version error"),
+        }
+
+        // children of kinds the parser never gives are rejected
+        let json = to_json(&node).replacen("\"kind\": \"Idt\"", "\"kind\": \"Num\"", 1);
+        match from_json(&json) {
+            Err(DecodeErr { msg }) => assert_eq!(
+                msg,
+                "Error: expected `args` of Fun node to be Idt nodes, found Num"
+            ),
+            Ok(_) => panic!("expected a shape error"),
+        }
+
+        let node = |ast| Node::new(ast, Span::default());
+        let num = || node(AstNode::Num("1".to_string()));
+        let program = |ast| node(AstNode::Program(LinkedList::from([node(ast)])));
+        for (ast, err) in [
+            (
+                AstNode::Match(Box::new(num()), LinkedList::new()),
+                "Error: expected `expr` of Match node to be an Expr node, found Num",
+            ),
+            (
+                AstNode::Pipe(
+                    Box::new(num()),
+                    LinkedList::from([node(AstNode::Fork(BTreeMap::from([(
+                        num(),
+                        LinkedList::new(),
+                    )])))]),
+                ),
+                "Error: expected `arms` of Fork node to be keyed by Idt nodes, found Num",
+            ),
+            (
+                AstNode::Pipe(Box::new(num()), LinkedList::from([num()])),
+                "Error: expected `stages` of Pipe node to be pipe stages, found Num",
+            ),
+        ] {
+            match from_binary(&to_binary(&program(ast))) {
+                Err(DecodeErr { msg }) => assert_eq!(msg, err),
+                Ok(_) => panic!("expected a shape error"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_sha256() {
+        use crate::codebase::sha256;
+
+        let hex =
+            |data: &[u8]| -> String { sha256(data).iter().map(|b| format!("{:02x}", b)).collect() };
+
+        assert_eq!(
+            hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn test_codebase_hash() {
+        use crate::codebase::hash_defs;
+        use std::collections::BTreeMap;
+
+        let hashes = |txt: &str| -> Vec<(String, String)> {
+            let node = BangParser::parse(txt).unwrap();
+            hash_defs(&node, &BTreeMap::new())
+                .into_iter()
+                .map(|def| (def.name, def.hash))
+                .collect()
+        };
+
+        let a = hashes(
+            "fun add(a, b) a + b end
+fun fact(n) -- recursive
+  match n
+    0 => 1
+    _ => n * fact(n - 1)
+  end
+end
+fun twice(x) let y = x add(y, x) end",
+        );
+        let b = hashes(
+            "fun plus(p, q)
+  p + q
+end
+
+fun factorial(k)
+  match k
+    0 => 1
+    _ => k * factorial(k - 1)
+  end
+end
+
+fun double(z)
+  let w = z
+  plus(w, z)
+end",
+        );
+
+        // renaming a function, its arguments and its callers keeps every hash
+        let hashes_of = |defs: &[(String, String)]| -> Vec<String> {
+            defs.iter().map(|(_, hash)| hash.clone()).collect()
+        };
+        assert_eq!(hashes_of(&a), hashes_of(&b));
+
+        // the order of the arguments matters, as does what is being called
+        let c = hashes("fun add(a, b) b + a end fun twice(x) let y = x add(y, x) end");
+        assert_ne!(c[0].1, a[0].1);
+        assert_ne!(c[1].1, a[2].1);
+
+        // a free name is kept as is
+        let d = hashes("fun f(a) g(a) end");
+        let e = hashes("fun f(a) h(a) end");
+        assert_ne!(d[0].1, e[0].1);
+    }
+
+    #[test]
+    fn test_codebase_eval() {
+        use crate::codebase::{hash, Codebase};
+        use crate::eval::eval_prgm_with;
+        use crate::parse::{AstNode, Node};
+        use crate::serial::to_binary;
+        use crate::span::Span;
+        use std::collections::LinkedList;
+
+        let dir = std::env::temp_dir().join(format!("bang-codebase-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut codebase = Codebase::open(&dir).unwrap();
+        let lib = BangParser::parse(
+            "fun add(a, b) a + b end
+fun twice(x) add(x, x) end
+let ten = twice(5)",
+        )
+        .unwrap();
+        let defs = codebase.add(&lib).unwrap();
+        assert_eq!(defs.len(), 3);
+
+        let run = |codebase: &Codebase, txt: &str| {
+            eval_prgm_with(BangParser::parse(txt).unwrap(), codebase)
+        };
+
+        match run(&codebase, "twice(3) + ten") {
+            Ok(Value::Num(num)) if num == 16 => {}
+            val => panic!("expected 16, got {:?}", val),
+        }
+
+        // callers refer to `add` by hash, so renaming it does not break them
+        assert!(codebase.rename("add", "plus").unwrap());
+        assert_eq!(codebase.hash_of("plus"), Some(defs[0].hash.clone()));
+
+        let codebase = Codebase::open(&dir).unwrap();
+        match run(&codebase, "twice(4) + plus(1, 1)") {
+            Ok(Value::Num(num)) if num == 10 => {}
+            val => panic!("expected 10, got {:?}", val),
+        }
+        match run(&codebase, "add(1, 1)") {
+            Err(RunErr { msg, .. }) => assert_eq!(msg, "Error function add is not defined"),
+            val => panic!("expected an error, got {:?}", val),
+        }
+
+        // definitions of the program shadow those of the codebase
+        match run(&codebase, "fun twice(x) x end twice(4)") {
+            Ok(Value::Num(num)) if num == 4 => {}
+            val => panic!("expected 4, got {:?}", val),
+        }
+
+        // edited or damaged definitions are load errors rather than panics
+        let comment = Node::new(AstNode::Comment("-- x".to_string(), false), Span::default());
+        let stored = Node::new(
+            AstNode::Program(LinkedList::from([comment.clone()])),
+            Span::default(),
+        );
+        let defs_dir = dir.join("defs");
+        std::fs::write(defs_dir.join(hash(&comment)), to_binary(&stored)).unwrap();
+        std::fs::copy(defs_dir.join(&defs[0].hash), defs_dir.join("abc")).unwrap();
+        std::fs::write(
+            dir.join("names"),
+            format!("{} comment\nabc forged\n", hash(&comment)),
+        )
+        .unwrap();
+
+        let codebase = Codebase::open(&dir).unwrap();
+        for (txt, msg, used) in [
+            ("1 + comment", "is not a `let` or a `fun`", "comment"),
+            (
+                "1 + forged(1, 2)",
+                "does not match its hash",
+                "forged(1, 2)",
+            ),
+        ] {
+            match run(&codebase, txt) {
+                Err(RunErr {
+                    kind: ErrKind::LoadError,
+                    msg: err,
+                    span,
+                }) => {
+                    assert!(err.contains(msg), "{}", err);
+                    assert_eq!(&txt[span.start..span.end], used);
+                }
+                val => panic!("expected a load error, got {:?}", val),
+            }
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_syntax_round_trip() {
+        use crate::syntax::syntaxes;
+
+        let txt = "-- every kind of node
+fun f(a, b) -- trailing
+  let m = { k: \"str\", n: true, }
+  -- own line
+  match not a or b and (a != 1)
+    { 1, x, } => 2 ** 3 * 4 / 5 - 6 >= 7 -- case
+    { k: v, l: { _, none, }, } if v > 1 => none
+    _ => { 1, 2, } |> [id |> g(1)] |> { x: h, y: [id], }
+    -- last case
+  end
+  g() -- inline -- -- and more
+end
+
+type Shape = Circle(radius) | Rect(width, height) | Empty -- types
+brand UserId = Num
+
+let l = fun(x, y) -- lambda
+  match x
+    Circle(r) => r
+    Rect(w, _) if w > 1 => w
+    Empty => y
+  end
+end
+(l)(1, fun z: z)(2) + f(1, 2)(3)
+
+f(1, match 2
+  1 => 2
+  _ => 3
+end) + 1
+-- eof";
+
+        let mut srcs = vec![txt.to_string()];
+        for path in ["examples/sample.bang", "examples/loop.bang"] {
+            srcs.push(std::fs::read_to_string(path).unwrap());
+        }
+
+        for src in srcs {
+            let node = BangParser::parse(&src).unwrap();
+
+            for from in syntaxes() {
+                let txt = from.render(&node);
+                let parsed = match from.parse(&txt) {
+                    Ok(parsed) => parsed,
+                    Err(e) => panic!("{} failed on\n{}\n{:?}", from.name(), txt, e),
+                };
+                assert_eq!(parsed, node, "{} round trip of\n{}", from.name(), txt);
+                assert_eq!(from.render(&parsed), txt);
+
+                for to in syntaxes() {
+                    let txt = to.render(&parsed);
+                    assert_eq!(
+                        to.parse(&txt).unwrap(),
+                        node,
+                        "{} to {}",
+                        from.name(),
+                        to.name()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_syntax_render() {
+        use crate::syntax::syntax;
+
+        let node = BangParser::parse(
+            "fun fact(n) -- factorial
+  match n
+    0 => 1
+    _ => n * fact(n - 1)
+  end
+end
+fact(3) |> [put]
+not true and a",
+        )
+        .unwrap();
+
+        let sexpr = "(fun fact (n) ; factorial
+  (match n
+    (0 1)
+    (_ (* n (fact (- n 1))))))
+
+(|> (fact 3) (each put))
+
+(and (not true) a)
+";
+        let brace = "fn fact(n) { // factorial
+  match n {
+    0 => 1,
+    _ => n * fact(n - 1),
+  }
+}
+
+fact(3) |> [put];
+
+!true && a;
+";
+
+        assert_eq!(syntax("sexpr").unwrap().render(&node), sexpr);
+        assert_eq!(syntax("brace").unwrap().render(&node), brace);
+
+        // forms shadow functions of the same name, which go through `call`
+        let node = BangParser::parse("map(1)").unwrap();
+        assert_eq!(syntax("sexpr").unwrap().render(&node), "(call map 1)\n");
+
+        match syntax("brace").unwrap().parse("fn f(a) { a + }") {
+            Err(BangParseError { msg, span, .. }) => {
+                assert_eq!(msg, "Error: expected expression, found `}`");
+                assert_eq!((span.line, span.col), (1, 15));
+            }
+            Ok(node) => panic!("expected an error, got {:?}", node),
+        }
+    }
+
+    #[test]
+    fn test_sexpr_rejects() {
+        use crate::syntax::syntax;
+
+        let err = |txt: &str| match syntax("sexpr").unwrap().parse(txt) {
+            Err(BangParseError { msg, .. }) => msg,
+            Ok(node) => panic!("expected an error, got {:?}", node),
+        };
+
+        // what the keyword syntax cannot express does not parse either
+        assert_eq!(
+            err("((fun f (1) 1) 2)"),
+            "Error: expected identifier, found `1`"
+        );
+        assert_eq!(
+            err("(lambda (a (b)) a)"),
+            "Error: expected identifier, found a list"
+        );
+        assert_eq!(err("(|> 1 2)"), "Error: expected pipe stage, found `2`");
+        assert_eq!(
+            err("(|> (list 1) (each (+ 1 2)))"),
+            "Error: expected pipe stage, found a list"
+        );
+        assert_eq!(
+            err("(|> (list 1) (fork (1 f)))"),
+            "Error: expected identifier, found `1`"
+        );
+        assert_eq!(
+            err("(|> (list 1) (fork (a 3)))"),
+            "Error: expected pipe stage, found `3`"
+        );
+
+        assert!(syntax("sexpr")
+            .unwrap()
+            .parse("(|> (list 1) (fork (a f (lambda (x) x)) (b (g 2))) (each h))")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_diagram_rules() {
+        use crate::diagram::{rules, Diagram, GRAMMAR};
+
+        let rules = rules("a = { \"x\" ~ (b | \"y\")* ~ c? }\nb = { \"b\" }\nc = @{ '0'..'9'{2} }")
+            .unwrap();
+        let names: Vec<_> = rules.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+
+        let term = |txt: &str| Diagram::Terminal(txt.to_string());
+        assert_eq!(
+            rules[0].diagram,
+            Diagram::Sequence(vec![
+                term("\"x\""),
+                Diagram::Optional(Box::new(Diagram::Repeat(
+                    Box::new(Diagram::Choice(vec![
+                        Diagram::NonTerminal("b".to_string()),
+                        term("\"y\""),
+                    ])),
+                    None
+                ))),
+                Diagram::Optional(Box::new(Diagram::NonTerminal("c".to_string()))),
+            ])
+        );
+        assert_eq!(
+            rules[2].diagram,
+            Diagram::Repeat(Box::new(term("'0'..'9'")), Some("2 times".to_string()))
+        );
+
+        assert!(crate::diagram::rules("a = { \"x\" ~ }").is_err());
+
+        // every rule of the grammar the parser is built from gets a diagram
+        let rules = crate::diagram::rules(GRAMMAR).unwrap();
+        for name in ["program", "expr", "fun", "pipe", "binpow", "idt"] {
+            assert!(
+                rules.iter().any(|r| r.name == name),
+                "no diagram for {}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_diagram_write() {
+        use crate::diagram::{write_diagrams, GRAMMAR};
+
+        let dir = std::env::temp_dir().join(format!("bang-diagrams-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let names = write_diagrams(GRAMMAR, &dir).unwrap();
+        for name in &names {
+            let svg = std::fs::read_to_string(dir.join(format!("{}.svg", name))).unwrap();
+            assert!(svg.starts_with("<svg") && svg.ends_with("</svg>\n"));
+        }
+
+        // references link to the diagram of the rule they name
+        let fun = std::fs::read_to_string(dir.join("fun.svg")).unwrap();
+        assert!(fun.contains("<a href=\"idt.svg\">"));
+        assert!(fun.contains("&quot;fun&quot;"));
+
+        let index = std::fs::read_to_string(dir.join("index.html")).unwrap();
+        assert!(index.contains("<h2 id=\"expr\">"));
+        assert!(index.contains("<a href=\"#expr\">"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_lambda() {
+        // functions are values that can be returned, passed and stored
+        let txt = "fun adder(n)
+  fun(x) x + n end
+end
+let twice = fun f, x: f(f(x))
+let inc = adder(1)
+let fs = { inc: inc, dec: fun c: c - 1, }
+let m = { 1, 2, 3, } |> [fun c: c * 10]
+twice(adder(3), 1) + adder(2)(3) + (twice)(fun x: x * 2, 1)";
+        assert_eq!(run(txt), Ok(Value::Num(Num::from(16))));
+
+        match run("fun id(x) x end") {
+            Ok(Value::Fun(args, _, _)) => assert_eq!(args.len(), 1),
+            val => panic!("expected a function, got {:?}", val),
+        }
+
+        match run("{ 1, 2, } |> [fun c: c * 10]") {
+            Ok(Value::Map(map)) => assert_eq!(
+                map.into_values().collect::<Vec<_>>(),
+                vec![Value::Num(Num::from(10)), Value::Num(Num::from(20))]
+            ),
+            val => panic!("expected a list, got {:?}", val),
+        }
+
+        // a lambda closes over the scope it is created in
+        assert_eq!(
+            run("let n = 5\nlet f = fun x: x * n\nf(2)"),
+            Ok(Value::Num(Num::from(10)))
+        );
+
+        let txt = "let x = 1\n(x)(2)";
+        match run(txt) {
+            Err(RunErr { msg, span, .. }) => {
+                assert!(msg.contains("not a function"), "{}", msg);
+                assert_eq!(&txt[span.start..span.end], "(x)(2)");
+            }
+            val => panic!("expected an error, got {:?}", val),
+        }
+    }
+
+    #[test]
+    fn test_closure_env() {
+        // functions see the functions defined after them
+        let txt = "fun even(n)
+  match n
+    0 => true
+    _ => odd(n - 1)
+  end
+end
+fun odd(n)
+  match n
+    0 => false
+    _ => even(n - 1)
+  end
+end
+even(10) and odd(7) and not even(3)";
+        assert_eq!(run(txt), Ok(Value::Bool(true)));
+
+        // a lambda bound with let can call itself through that name
+        let txt = "let sum = fun n: match n
+  0 => 0
+  _ => n + sum(n - 1)
+end
+sum(100)";
+        assert_eq!(run(txt), Ok(Value::Num(Num::from(5050))));
+
+        // every counter keeps its own count, stepping gives a new counter
+        let txt = "fun counter(n)
+  fun(step)
+    match step
+      0 => n
+      _ => counter(n + step)
+    end
+  end
+end
+let a = counter(0)
+let b = a(1)(1)
+let c = b(5)
+a(0) + 10 * b(0) + 100 * c(0)";
+        assert_eq!(run(txt), Ok(Value::Num(Num::from(720))));
+
+        // closures keep the names bound when they were created, binding a
+        // name again shadows it for the code that follows only
+        let txt = "let n = 1
+let get = fun() n end
+let n = 2
+get() + 10 * n";
+        assert_eq!(run(txt), Ok(Value::Num(Num::from(21))));
+
+        // names bound after a closure was created are still seen by it
+        let txt = "let n = 1
+fun get() n + later() end
+let n = 2
+fun later() 10 * n end
+get()";
+        assert_eq!(run(txt), Ok(Value::Num(Num::from(21))));
+    }
+
+    #[test]
+    fn test_escaping_functions() {
+        // functions are bound without the scope they close over, which they
+        // get back when they leave it
+        let txt = "fun outer(n)
+  fun inner(x) x + n end
+  let twice = fun y: inner(inner(y))
+  let thrice = (fun y: inner(twice(y)))
+  thrice
+end
+let f = outer(10)
+f(1)";
+        assert_eq!(run(txt), Ok(Value::Num(Num::from(31))));
+    }
+
+    #[test]
+    fn test_currying() {
+        let txt = "fun add(a, b, c) a * 100 + b * 10 + c end
+let one = add(1)
+let two = one(2)
+two(3) + add(4)(5)(6) + add(7, 8)(9) + add()(1, 1, 1)";
+        assert_eq!(run(txt), Ok(Value::Num(Num::from(123 + 456 + 789 + 111))));
+
+        // a partial application is a function of the remaining arguments
+        match run("fun add(a, b) a + b end\nadd(1)") {
+            Ok(Value::Fun(args, _, _)) => assert_eq!(args.len(), 1),
+            val => panic!("expected a function, got {:?}", val),
+        }
+
+        // pipe stages can be partially applied functions
+        let txt = "fun sub(a, b) a - b end
+let minus = fun b, a: a - b
+10 |> sub(3) |> minus(2) |> minus";
+        match run(txt) {
+            Ok(Value::Fun(args, _, _)) => assert_eq!(args.len(), 1),
+            val => panic!("expected a function, got {:?}", val),
+        }
+        assert_eq!(
+            run("let minus = fun b, a: a - b\n10 |> minus |> fun f: f(4)"),
+            Ok(Value::Num(Num::from(-6)))
+        );
+
+        let over = |txt: &str, exp: &str| match run(txt) {
+            Err(RunErr { msg, span, .. }) => {
+                assert_eq!(msg, exp);
+                assert!(span.start < span.end);
+            }
+            val => panic!("expected an error, got {:?}", val),
+        };
+        over(
+            "fun add(a, b) a + b end\nadd(1, 2, 3)",
+            "Error: function add takes 2 arguments, got 3",
+        );
+        over(
+            "fun add(a, b) a + b end\nadd(1)(2, 3)",
+            "Error: function add(1) takes 1 argument, got 2",
+        );
+        over(
+            "(fun(x) x end)(1, 2)",
+            "Error: function (fun(x) x end) takes 1 argument, got 2",
+        );
+    }
+
+    #[test]
+    fn test_match_patterns() {
+        let str = |s: &str| Ok(Value::String(format!("\"{}\"", s)));
+
+        let txt = "fun describe(p)
+  match p
+    { name: \"root\" } => { kind: \"admin\", who: none, }
+    { name: n, age: a } if a < 18 => n |> fun n: { kind: \"minor\", who: n, }
+    { name: n, age: _ } => { kind: \"adult\", who: n, }
+    { 0, _ } => { kind: \"origin\", who: none, }
+    { x, { y, _ } } if x == y => { kind: \"diagonal\", who: x, }
+    { x, _ } => { kind: \"row\", who: x, }
+    none => { kind: \"nothing\", who: none, }
+    true => { kind: \"yes\", who: none, }
+    _ => { kind: \"other\", who: p, }
+  end
+end
+let diag = { 3, 4, }
+let off = { 2, 4, }
+";
+        let kind = |arg: &str| {
+            run(&format!(
+                "{}let r = describe({})\nmatch r\n  {{ kind: k }} => k\nend",
+                txt, arg
+            ))
+        };
+        assert_eq!(kind("{ name: \"root\", age: 30, }"), str("admin"));
+        assert_eq!(kind("{ name: \"ann\", age: 12, }"), str("minor"));
+        assert_eq!(kind("{ name: \"bob\", age: 40, }"), str("adult"));
+        assert_eq!(kind("{ 0, 1, }"), str("origin"));
+        assert_eq!(kind("{ 3, diag, }"), str("diagonal"));
+        assert_eq!(kind("{ 3, off, }"), str("row"));
+        assert_eq!(kind("none"), str("nothing"));
+        assert_eq!(kind("true"), str("yes"));
+        assert_eq!(kind("{ 1, 2, 3, }"), str("other"));
+        assert_eq!(kind("{ other: 1, }"), str("other"));
+
+        // bindings are visible in the guard and the branch only
+        let txt = "let x = 1
+let r = match { 5, 6, }
+  { x, y } if x < y => x * 10 + y
+end
+r + x";
+        assert_eq!(run(txt), Ok(Value::Num(Num::from(57))));
+
+        // a failing guard falls through to the next branch
+        let txt = "match 4
+  n if n > 5 => 1
+  n if n > 3 => 2
+  _ => 3
+end";
+        assert_eq!(run(txt), Ok(Value::Num(Num::from(2))));
+
+        // anything that is not a pattern is a value to compare with
+        let txt = "let two = 2
+match 4
+  two => 1
+  two * 2 => 2
+end";
+        assert_eq!(run(txt), Ok(Value::Num(Num::from(1))));
+        let txt = "let two = 2
+match 4
+  (two) => 1
+  two * 2 => 2
+end";
+        assert_eq!(run(txt), Ok(Value::Num(Num::from(2))));
+
+        match run("match { a: 1, }\n  { b: _ } => 1\n  { 1 } => 2\nend") {
+            Err(RunErr { msg, span, .. }) => {
+                assert_eq!(msg, "Error: no branch of the match matches { a: 1, }");
+                assert!(span.start < span.end);
+            }
+            val => panic!("expected an error, got {:?}", val),
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        use crate::check::check;
+
+        let warnings = |txt: &str| -> Vec<(String, usize)> {
+            check(&BangParser::parse(txt).unwrap())
+                .into_iter()
+                .map(|warning| (warning.msg, warning.span.line))
+                .collect()
+        };
+        let missing =
+            |pat: &str| format!("Warning: match is not exhaustive, `{}` is not covered", pat);
+        let wild = "Warning: match is not exhaustive, add a `_` branch".to_string();
+        let unreachable =
+            "Warning: unreachable branch, earlier branches cover every value it matches"
+                .to_string();
+
+        for path in ["examples/sample.bang", "examples/loop.bang"] {
+            let txt = std::fs::read_to_string(path).unwrap();
+            assert_eq!(warnings(&txt), vec![]);
+        }
+
+        let txt = "match a
+  true => 1
+  false => 2
+end";
+        assert_eq!(warnings(txt), vec![]);
+
+        let txt = "match a
+  true => 1
+end";
+        assert_eq!(warnings(txt), vec![(missing("false"), 1)]);
+
+        // guarded branches cover nothing
+        let txt = "match a
+  true => 1
+  false if b => 2
+end";
+        assert_eq!(warnings(txt), vec![(missing("false"), 1)]);
+
+        // lists are told apart by length, literals never cover everything
+        let txt = "match a
+  { true, _ } => 1
+  { false, x } => 2
+  { _ } => 3
+  { 1 } => 4
+end";
+        assert_eq!(
+            warnings(txt),
+            vec![(unreachable.clone(), 5), (wild.clone(), 1)]
+        );
+
+        let txt = "match a
+  { x: true } => 1
+  { x: _, y: _ } => 2
+  { y: 1, x: false } => 3
+  _ => 4
+  n => 5
+  n * 2 => 6
+end";
+        assert_eq!(
+            warnings(txt),
+            vec![
+                (unreachable.clone(), 4),
+                (unreachable.clone(), 6),
+                (unreachable.clone(), 7)
+            ]
+        );
+
+        // expressions may equal any value, only a catch all makes them unreachable
+        let txt = "match a
+  b * 2 => 1
+  b + 1 => 2
+end";
+        assert_eq!(warnings(txt), vec![(wild.clone(), 1)]);
+
+        // nested matches are checked too, wherever they are
+        let txt = "fun f(a)
+  g(match a
+    _ => 1
+    0 => 2
+  end)
+end";
+        assert_eq!(warnings(txt), vec![(unreachable, 4)]);
+    }
+
+    #[test]
+    fn test_types() {
+        let data = |tag: &str, fields: Vec<(&str, Value)>| {
+            Value::Data(
+                tag.to_string(),
+                fields
+                    .into_iter()
+                    .map(|(name, val)| (name.to_string(), val))
+                    .collect(),
+            )
+        };
+
+        let shapes = "type Shape =
+  | Circle(radius)
+  | Rect(width, height)
+  | Empty
+
+fun area(s)
+  match s
+    Circle(r) => 3 * r * r
+    Rect(w, h) => w * h
+    Empty => 0
+  end
+end
+";
+
+        assert_eq!(
+            run(&format!("{}Rect(3, 4)", shapes)),
+            Ok(data(
+                "Rect",
+                vec![
+                    ("width", Value::Num(Num::from(3))),
+                    ("height", Value::Num(Num::from(4)))
+                ]
+            ))
+        );
+        assert_eq!(run(&format!("{}Empty", shapes)), Ok(data("Empty", vec![])));
+        assert_eq!(
+            run(&format!(
+                "{}area(Circle(2)) + area(Rect(3, 4)) + area(Empty)",
+                shapes
+            )),
+            Ok(Value::Num(Num::from(24)))
+        );
+
+        // constructors are functions, they can be partially applied
+        assert_eq!(
+            run(&format!("{}let tall = Rect(1)\narea(tall(5))", shapes)),
+            Ok(Value::Num(Num::from(5)))
+        );
+        assert_eq!(
+            run(&format!("{}Rect(1, 2) == Rect(1, 2)", shapes)),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            run(&format!("{}Rect(1, 2) == Rect(2, 1)", shapes)),
+            Ok(Value::Bool(false))
+        );
+
+        // a product type is a type of a single variant
+        let txt = "type Point = Point(x, y)
+fun norm(p)
+  match p
+    Point(x, y) if x < 0 => 0 - x + y
+    Point(x, y) => x + y
+  end
+end
+norm(Point(3, 4)) + norm(Point(0 - 1, 1))";
+        assert_eq!(run(txt), Ok(Value::Num(Num::from(9))));
+
+        // variants nest, and a tag alone matches whatever the fields
+        let txt = "type Opt = Some(value) | Nothing
+let wrapped = Some(Some(3))
+let inner = match wrapped
+  Some(Nothing) => 0
+  Some(Some(n)) => n
+  Nothing => 1
+end
+let outer = match Some(1)
+  Some => 10
+  _ => 20
+end
+inner + outer";
+        assert_eq!(run(txt), Ok(Value::Num(Num::from(13))));
+
+        // values of variants print as their constructor applied to the fields
+        use crate::eval::show;
+        assert_eq!(
+            run("type Opt = Some(value) | Nothing\nSome(Some(Nothing))").map(|val| show(&val)),
+            Ok("Some(Some(Nothing))".to_string())
+        );
+
+        match run(&format!("{}Rect(1, 2, 3)", shapes)) {
+            Err(RunErr { msg, .. }) => {
+                assert_eq!(msg, "Error: function Rect takes 2 arguments, got 3")
+            }
+            val => panic!("expected an error, got {:?}", val),
+        }
+
+        // matches over the variants of a type are checked against all of them
+        use crate::check::check;
+        let warnings = |txt: &str| -> Vec<String> {
+            check(&BangParser::parse(txt).unwrap())
+                .into_iter()
+                .map(|warning| warning.msg)
+                .collect()
+        };
+        assert_eq!(warnings(shapes), Vec::<String>::new());
+        assert_eq!(
+            warnings(&shapes.replace("    Empty => 0\n", "")),
+            ["Warning: match is not exhaustive, `Empty` is not covered"]
+        );
+        let txt = "type Opt = Some(value) | Nothing
+match o
+  Some(true) => 1
+  Some(1, 2) => 2
+  Nothing => 3
+end";
+        assert_eq!(
+            warnings(txt),
+            [
+                "Warning: variant Some has 1 field, the pattern has 2",
+                "Warning: match is not exhaustive, `Some(false)` is not covered"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_brands() {
+        let err = |txt: &str| match run(txt) {
+            Err(RunErr { msg, .. }) => msg,
+            val => panic!("expected an error, got {:?}", val),
+        };
+
+        let ids = "brand UserId = Num
+brand PostId = Num
+let user = wrap(UserId, 3)
+let post = wrap(PostId, 3)
+";
+
+        assert_eq!(
+            run(&format!("{}user + wrap(UserId, 4)", ids)),
+            Ok(Value::Branded(
+                "UserId".to_string(),
+                Box::new(Value::Num(Num::from(7)))
+            ))
+        );
+        assert_eq!(
+            run(&format!("{}unwrap(UserId, user) + 1", ids)),
+            Ok(Value::Num(Num::from(4)))
+        );
+        assert_eq!(
+            run(&format!("{}user < wrap(UserId, 4)", ids)),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            run(&format!("{}user == wrap(UserId, 3)", ids)),
+            Ok(Value::Bool(true))
+        );
+
+        // values of different brands, or of a brand and none, do not mix
+        assert_eq!(
+            err(&format!("{}user + 1", ids)),
+            "Error: cannot add UserId(3) and 1, branded values only mix with values of their brand"
+        );
+        assert_eq!(
+            err(&format!("{}user == post", ids)),
+            "Error: cannot compare UserId(3) and PostId(3), branded values only mix with values of their brand"
+        );
+        assert_eq!(
+            err(&format!("{}unwrap(PostId, user)", ids)),
+            "Error: cannot unwrap UserId(3) as PostId"
+        );
+        assert_eq!(
+            err(&format!("{}wrap(UserId, user)", ids)),
+            "Error: cannot wrap UserId(3) as UserId, it wraps values of type Num"
+        );
+        assert_eq!(
+            err(&format!("{}wrap(3, 4)", ids)),
+            "Error: wrap expects a brand, got 3"
+        );
+        assert_eq!(
+            err(&format!("{}unwrap(user)", ids)),
+            "Error: unwrap takes a brand and a value"
+        );
+        assert_eq!(
+            err("brand Id = Shape"),
+            "Error: brand Id cannot wrap Shape, only Num, Str, Bool, Map, Fun"
+        );
+
+        use crate::eval::show;
+        let tokens = "brand Token = Map
+let secret = wrap(Token, { key: \"abc\", })
+";
+        match run(&format!("{}secret", tokens)) {
+            Ok(val) => assert_eq!(show(&val), "Token({ key: \"abc\", })"),
+            val => panic!("expected a value, got {:?}", val),
+        }
+        assert_eq!(
+            run("brand Score = Num\nScore").map(|val| show(&val)),
+            Ok("brand Score = Num".to_string())
+        );
+    }
+
+    #[test]
+    fn test_infer() {
+        use crate::infer::infer;
+
+        let types = |txt: &str| infer(&BangParser::parse(txt).unwrap()).0;
+        let errs = |txt: &str| -> Vec<(String, usize, usize)> {
+            infer(&BangParser::parse(txt).unwrap())
+                .1
+                .into_iter()
+                .map(|err| (err.msg, err.span.line, err.span.col))
+                .collect()
+        };
+
+        for path in ["examples/sample.bang", "examples/loop.bang"] {
+            let txt = std::fs::read_to_string(path).unwrap();
+            assert_eq!(errs(&txt), vec![]);
+        }
+
+        let txt = "fun add(a, b)
+  a + b
+end
+fun id(x) x end
+fun twice(f, x) f(f(x)) end
+let inc = add(1)
+let n = twice(inc, id(1)) |> inc
+let yes = id(true)
+fun even(n)
+  match n
+    0 => true
+    _ => odd(n - 1)
+  end
+end
+fun odd(n)
+  match n
+    0 => false
+    _ => even(n - 1)
+  end
+end
+let sum = fun k: match k
+  0 => 0
+  _ => k + sum(k - 1)
+end
+type Opt = Some(value) | Nothing
+fun get(o, d)
+  match o
+    Some(v) => v
+    Nothing => d
+  end
+end
+let got = get(Some(1), 0)";
+        assert_eq!(errs(txt), vec![]);
+
+        let types = types(txt);
+        for (name, ty) in [
+            ("add", "fun(Num, Num) -> Num"),
+            ("id", "fun(a) -> a"),
+            ("twice", "fun(fun(a) -> a, a) -> a"),
+            ("inc", "fun(Num) -> Num"),
+            ("n", "Num"),
+            ("yes", "Bool"),
+            ("even", "fun(Num) -> Bool"),
+            ("sum", "fun(Num) -> Num"),
+            ("Some", "fun(a) -> Opt"),
+            ("Nothing", "Opt"),
+            ("get", "fun(Opt, a) -> a"),
+            ("got", "Num"),
+        ] {
+            assert_eq!(types.get(name).map(String::as_str), Some(ty), "{}", name);
+        }
+
+        let txt = "fun add(a, b)
+  a + b
+end
+let m = { a: 1, }
+m + 1
+add(1, true)
+add(1, 2, 3)
+let x = 1
+x(2)
+brand UserId = Num
+let u = wrap(UserId, 1)
+u + 1
+unwrap(UserId, u) + 1
+match x
+  0 => 1
+  \"a\" => 2
+  _ => not true
+end
+not x
+fun(y) y(y) end";
+        assert_eq!(
+            errs(txt),
+            vec![
+                ("Error: expected Num, found Map".to_string(), 5, 1),
+                ("Error: expected Num, found Bool".to_string(), 6, 8),
+                (
+                    "Error: function add takes 2 arguments, got 3".to_string(),
+                    7,
+                    1
+                ),
+                ("Error: Num is not a function".to_string(), 9, 1),
+                ("Error: expected UserId, found Num".to_string(), 12, 5),
+                (
+                    "Error: this pattern matches Str, the value matched is Num".to_string(),
+                    16,
+                    3
+                ),
+                (
+                    "Error: this branch gives Bool, the branches before give Num".to_string(),
+                    17,
+                    8
+                ),
+                ("Error: expected Bool, found Num".to_string(), 19, 5),
+                ("Error: expected fun(a) -> b, found a".to_string(), 20, 8),
+            ]
+        );
+
+        // a function sees the names it uses as bound when it was created,
+        // like when it runs, not as they are bound again later on
+        let txt = "let n = 1
+let get = fun() n end
+let n = true
+get() + 1";
+        assert_eq!(errs(txt), vec![]);
+        assert_eq!(run(txt), Ok(Value::Num(Num::from(2))));
+
+        let txt = "let n = true
+let get = fun() n end
+let n = 1
+get() + n";
+        assert_eq!(
+            errs(txt),
+            vec![("Error: expected Num, found Bool".to_string(), 4, 1)]
+        );
+        assert!(run(txt).is_err());
+    }
+
+    #[test]
+    fn test_narrowing() {
+        use crate::check::check;
+        use crate::infer::infer;
+
+        let infer = |txt: &str| infer(&BangParser::parse(txt).unwrap());
+        let warnings = |txt: &str| -> Vec<(String, usize)> {
+            check(&BangParser::parse(txt).unwrap())
+                .into_iter()
+                .map(|warning| (warning.msg, warning.span.line))
+                .collect()
+        };
+
+        // a value matched with `none` is optional, whichever branch does, and
+        // is only a number once the branch for `none` is past
+        let txt = "fun inc(x)
+  match x
+    n => n + 1
+    none => 0
+  end
+end
+fun or-zero(x)
+  match x
+    none => 0
+    _ => x + 1
+  end
+end
+fun half(n)
+  match n
+    0 => none
+    _ => n / 2
+  end
+end
+let got = half(4)
+let sure = match got
+  none => 0
+  m => m
+end
+inc(none) + inc(2) + or-zero(got) + sure";
+        let (types, errs) = infer(txt);
+        let errs: Vec<_> = errs
+            .into_iter()
+            .map(|err| (err.msg, err.span.line))
+            .collect();
+        assert_eq!(errs, [("Error: expected Num, found a?".to_string(), 3)]);
+        for (name, ty) in [
+            ("or-zero", "fun(Num?) -> Num"),
+            ("half", "fun(Num) -> Num?"),
+            ("got", "Num?"),
+            ("sure", "Num"),
+        ] {
+            assert_eq!(types.get(name).map(String::as_str), Some(ty), "{}", name);
+        }
+
+        let txt = "fun half(n)
+  match n
+    0 => none
+    _ => n / 2
+  end
+end
+half(4) + 1
+let x = half(2)
+match x
+  none => 0
+  _ => match x
+    none => 1
+    _ => x * 2
+  end
+end";
+        let errs: Vec<_> = infer(txt)
+            .1
+            .into_iter()
+            .map(|err| (err.msg, err.span.line))
+            .collect();
+        assert_eq!(
+            errs,
+            [
+                ("Error: expected Num, found Num?".to_string(), 7),
+                (
+                    "Error: this pattern matches None, the value matched is Num".to_string(),
+                    12
+                )
+            ]
+        );
+        assert_eq!(
+            warnings(txt),
+            [(
+                "Warning: unreachable branch, the enclosing branches rule out every value it matches"
+                    .to_string(),
+                12
+            )]
+        );
+
+        // a branch for a variant knows the others are ruled out
+        let txt = "type Shape = Circle(radius) | Rect(width, height) | Empty
+fun area(s)
+  match s
+    Circle(r) => 3 * r * r
+    Empty => 0
+    _ => match s
+      Rect(w, h) => w * h
+    end
+  end
+end
+fun radius(s)
+  match s
+    Circle(r) => match s
+      Circle(q) => q
+      Empty => 0
+    end
+    _ => 0
+  end
+end
+fun shadowed(s)
+  match s
+    Circle(r) => match s
+      Circle(q) => q
+    end
+    s => 0
+  end
+end";
+        assert_eq!(
+            warnings(txt),
+            [(
+                "Warning: unreachable branch, the enclosing branches rule out every value it matches"
+                    .to_string(),
+                15
+            )]
+        );
+        let txt = txt.replace(
+            "    s => 0\n",
+            "    _ => fun(s) match s\n      Empty => 0\n    end end\n",
+        );
+        assert_eq!(
+            warnings(&txt),
+            [
+                (
+                    "Warning: unreachable branch, the enclosing branches rule out every value it matches"
+                        .to_string(),
+                    15
+                ),
+                (
+                    "Warning: match is not exhaustive, `Circle(_)` is not covered".to_string(),
+                    25
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let err = |txt: &str| match run(txt) {
+            Err(RunErr { kind, msg, .. }) => (kind, msg),
+            val => panic!("expected an error, got {:?}", val),
+        };
+
+        assert_eq!(
+            err("let m = { a: 1, }\nm + 1"),
+            (
+                ErrKind::TypeError,
+                "Error: cannot add Map and Num, only numbers can be".to_string()
+            )
+        );
+        assert_eq!(
+            err("let f = fun x: x\n2 ** f"),
+            (
+                ErrKind::TypeError,
+                "Error: cannot raise Num and Fun, only numbers can be".to_string()
+            )
+        );
+        assert_eq!(
+            err("let m = { a: \"b\", }\nm < 2"),
+            (
+                ErrKind::TypeError,
+                "Error: cannot order Map and Num, only numbers and strings can be".to_string()
+            )
+        );
+        assert_eq!(
+            err("let x = 3\nx(1)"),
+            (
+                ErrKind::TypeError,
+                "Error: cannot call 3, it is a Num and not a function".to_string()
+            )
+        );
+        assert_eq!(
+            err("(true)(1)"),
+            (
+                ErrKind::TypeError,
+                "Error: cannot call true, it is a Bool and not a function".to_string()
+            )
+        );
+        assert_eq!(
+            err("not 3"),
+            (
+                ErrKind::TypeError,
+                "Error: expected a Bool, got Num 3".to_string()
+            )
+        );
+        assert_eq!(
+            err("3 |> [ fun x: x ]"),
+            (
+                ErrKind::TypeError,
+                "Error: cannot map over 3, it is a Num and not a list or map".to_string()
+            )
+        );
+        assert_eq!(
+            err("y + 1"),
+            (
+                ErrKind::NameError,
+                "Error: identifier y is not defined".to_string()
+            )
+        );
+        assert_eq!(
+            err("fun f(a) a end\nf(1, 2)"),
+            (
+                ErrKind::ArityError,
+                "Error: function f takes 1 argument, got 2".to_string()
+            )
+        );
+        assert_eq!(
+            err("match 3\n  4 => 1\nend"),
+            (
+                ErrKind::MatchError,
+                "Error: no branch of the match matches 3".to_string()
+            )
+        );
+        assert_eq!(
+            err("type Opt = Some(value) | Nothing\nmatch Some(1)\n  Nothing => 0\nend"),
+            (
+                ErrKind::MatchError,
+                "Error: no branch of the match matches Some(1)".to_string()
+            )
+        );
+
+        // trees built rather than parsed can hold nodes where the parser
+        // never puts them
+        use crate::parse::{AstNode, Node};
+        use crate::span::Span;
+        use std::collections::LinkedList;
+
+        let node = |ast| Node::new(ast, Span::default());
+        let num = || node(AstNode::Num("1".to_string()));
+        for (ast, msg) in [
+            (
+                AstNode::Match(Box::new(num()), LinkedList::new()),
+                "Error: a Num node cannot be the subject of a match",
+            ),
+            (
+                AstNode::Call(
+                    Box::new(node(AstNode::Lambda(
+                        LinkedList::from([num()]),
+                        LinkedList::new(),
+                    ))),
+                    LinkedList::from([num()]),
+                ),
+                "Error: a Num node cannot be an argument of a function",
+            ),
+            (
+                AstNode::Pipe(Box::new(num()), LinkedList::from([num()])),
+                "Error: a Num node cannot be a stage of a pipeline",
+            ),
+        ] {
+            let program = node(AstNode::Program(LinkedList::from([node(ast)])));
+            assert_eq!(
+                eval_prgm(program),
+                Err(RunErr::new(
+                    ErrKind::SyntaxError,
+                    msg.to_string(),
+                    Span::default()
+                ))
+            );
+        }
+    }
+
+    // Random programs mixing every kind of value with every operation, none of
+    // them may make the interpreter panic whatever errors it runs into
+    #[test]
+    fn test_no_panics() {
+        struct Gen(u64);
+
+        impl Gen {
+            fn next(&mut self, n: usize) -> usize {
+                // xorshift, fixed seed so that failures reproduce
+                self.0 ^= self.0 << 13;
+                self.0 ^= self.0 >> 7;
+                self.0 ^= self.0 << 17;
+                (self.0 % n as u64) as usize
+            }
+
+            fn pick<'a>(&mut self, names: &'a [String]) -> &'a str {
+                &names[self.next(names.len())]
+            }
+
+            fn atom(&mut self, names: &[String]) -> String {
+                match self.next(6) {
+                    0 => self.next(20).to_string(),
+                    1 => ["true", "false"][self.next(2)].to_string(),
+                    2 => "none".to_string(),
+                    3 => "nope".to_string(),
+                    4 => format!("{{ {}, 1, \"s\", }}", self.pick(names)),
+                    _ => self.pick(names).to_string(),
+                }
+            }
+
+            fn expr(&mut self, depth: usize, names: &[String]) -> String {
+                if depth == 0 {
+                    return self.atom(names);
+                }
+
+                // only compound operands need parentheses, which the parser
+                // backtracks through
+                let sub = |gen: &mut Gen| match gen.expr(depth - 1, names) {
+                    expr if expr.contains(' ') => format!("({})", expr),
+                    expr => expr,
+                };
+                match self.next(14) {
+                    0 => {
+                        let ops = ["+", "-", "*", "/", "**", "==", "<", ">=", "and", "or"];
+                        let op = ops[self.next(ops.len())];
+                        format!("{} {} {}", sub(self), op, sub(self))
+                    }
+                    1 => format!("not {}", sub(self)),
+                    2 => format!("{}({})", self.pick(names), sub(self)),
+                    3 => format!("{}({}, {})", self.pick(names), sub(self), sub(self)),
+                    4 => format!("{}({})", sub(self), sub(self)),
+                    5 => {
+                        let mut inner = names.to_vec();
+                        inner.push("x".to_string());
+                        format!("fun(x) {} end", self.expr(depth - 1, &inner))
+                    }
+                    6 => format!(
+                        "match {}\n  1 => {}\n  true => {}\n  none => {}\n  {{ x, }} => x\n  x if {} => x\n{}end",
+                        sub(self),
+                        sub(self),
+                        sub(self),
+                        sub(self),
+                        sub(self),
+                        ["", "  _ => 0\n"][self.next(2)]
+                    ),
+                    7 => format!("{} |> {}", sub(self), self.pick(names)),
+                    8 => format!(
+                        "{{ {}, {}, }} |> [ {} ]",
+                        self.pick(names),
+                        self.next(5),
+                        self.pick(names)
+                    ),
+                    9 => format!(
+                        "{} |> {{ p: {}, q: fun x: x, }}",
+                        sub(self),
+                        self.pick(names)
+                    ),
+                    10 => format!("{{ k: {}, j: \"s\", }}", self.pick(names)),
+                    11 => format!("wrap(Id, {})", sub(self)),
+                    12 => format!("unwrap(Id, {})", sub(self)),
+                    _ => self.atom(names),
+                }
+            }
+        }
+
+        let mut gen = Gen(0x2545_f491_4f6c_dd1d);
+        let mut parsed = 0;
+
+        for _ in 0..250 {
+            let mut names = vec!["Id".to_string(), "Some".to_string()];
+            let mut txt = "brand Id = Num\ntype Opt = Some(value) | Nothing\n".to_string();
+
+            // definitions only refer to the ones before them, so that no
+            // program recurses forever
+            for i in 0..3 {
+                let name = format!("d{}", ["a", "b", "c"][i]);
+                if gen.next(2) == 0 {
+                    txt += &format!("let {} = {}\n", name, gen.expr(2, &names));
+                } else {
+                    let mut inner = names.clone();
+                    inner.push("x".to_string());
+                    txt += &format!("fun {}(x) {} end\n", name, gen.expr(2, &inner));
+                }
+                names.push(name);
+            }
+            txt += &gen.expr(2, &names);
+
+            let node = match BangParser::parse(&txt) {
+                Ok(node) => node,
+                Err(_) => continue,
+            };
+            parsed += 1;
+
+            let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| eval_prgm(node)));
+            assert!(res.is_ok(), "evaluating the program panicked:\n{}", txt);
+        }
+
+        assert!(parsed > 200, "only {} programs parsed", parsed);
+    }
+
+    #[test]
+    fn test_numbers() {
+        let num = |txt: &str| match run(txt) {
+            Ok(Value::Num(num)) => num.to_string(),
+            val => panic!("expected a number, got {:?}", val),
+        };
+        let err = |txt: &str| match run(txt) {
+            Err(RunErr { kind, msg, .. }) => (kind, msg),
+            val => panic!("expected an error, got {:?}", val),
+        };
+
+        // integers grow past any machine word
+        assert_eq!(num("2147483647 + 1"), "2147483648");
+        assert_eq!(num("2 ** 100"), "1267650600228229401496703205376");
+        assert_eq!(num("0 - 2 ** 64 * 3"), "-55340232221128654848");
+        assert_eq!(
+            num("123456789012345678901234567890 - 123456789012345678901234567889"),
+            "1"
+        );
+
+        // a division that does not come out even gives an exact fraction, in
+        // lowest terms and back to an integer when it comes out even again
+        assert_eq!(num("1 / 3"), "1/3");
+        assert_eq!(num("6 / 4"), "3/2");
+        assert_eq!(num("7 / (0 - 2)"), "-7/2");
+        assert_eq!(num("1 / 3 + 1 / 6"), "1/2");
+        assert_eq!(num("1 / 3 * 3"), "1");
+        assert_eq!(num("(2 / 3) ** 2"), "4/9");
+        assert_eq!(num("10 / 5"), "2");
+        assert_eq!(run("1 / 3 < 1 / 2 and 3 / 2 > 1"), Ok(Value::Bool(true)));
+        assert_eq!(run("2 / 4 == 1 / 2"), Ok(Value::Bool(true)));
+
+        // a negative power is the inverse of the positive one
+        assert_eq!(num("2 ** (0 - 1)"), "1/2");
+        assert_eq!(num("(2 / 3) ** (0 - 3)"), "27/8");
+        assert_eq!(num("(0 - 1) ** (0 - 3)"), "-1");
+        assert_eq!(num("1 ** (0 - 99999999999999999999)"), "1");
+
+        assert_eq!(
+            err("let n = 0\n1 / n"),
+            (
+                ErrKind::ArithError,
+                "Error: cannot divide 1 by zero".to_string()
+            )
+        );
+        assert_eq!(
+            err("0 ** (0 - 1)"),
+            (
+                ErrKind::ArithError,
+                "Error: cannot raise 0 to -1, it divides by zero".to_string()
+            )
+        );
+        assert_eq!(
+            err("4 ** (1 / 2)"),
+            (
+                ErrKind::ArithError,
+                "Error: cannot raise 4 to 1/2, powers have to be integers".to_string()
+            )
+        );
+        assert_eq!(
+            err("2 ** 99999999"),
+            (
+                ErrKind::ArithError,
+                "Error: 2 ** 99999999 is too large, powers go up to 1048576 bits".to_string()
+            )
+        );
+
+        // brands keep their values exact too
+        assert_eq!(
+            eval_prgm(
+                BangParser::parse(
+                    "brand Usd = Num\nlet a = wrap(Usd, 10)\nlet b = wrap(Usd, 4)\na / b"
+                )
+                .unwrap()
+            ),
+            Ok(Value::Branded(
+                "Usd".to_string(),
+                Box::new(Value::Num(Num::from(5).div(&Num::from(2)).unwrap()))
+            ))
+        );
+    }
 }